@@ -1,18 +1,23 @@
 #![allow(clippy::too_many_arguments)]
 mod util;
 
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use arboard::Clipboard;
 use async_std::sync::Arc;
 use clap::{Args, CommandFactory, Parser, Subcommand};
 use color_eyre::{eyre, eyre::Context};
 use console::{style, Term};
-use futures::{future::Either, Future, FutureExt};
+use futures::{future::Either, FutureExt};
 use indicatif::{MultiProgress, ProgressBar};
 use std::{io::Write, path::PathBuf};
 
-use magic_wormhole::{forwarding, transfer, transit, MailboxConnection, Wormhole};
+use magic_wormhole::{
+    forwarding, transfer, transit,
+    uri::{qr::QrCode, WormholeTransferUri},
+    wordlist::{self, Wordlist},
+    CodeCompleter, MailboxConnection, Wormhole,
+};
 
 fn install_ctrlc_handler(
 ) -> eyre::Result<impl Fn() -> futures::future::BoxFuture<'static, ()> + Clone> {
@@ -67,12 +72,19 @@ struct CommonSenderArgs {
         value_hint = clap::ValueHint::AnyPath,
     )]
     files: Vec<PathBuf>,
+    /// Also print the code as QR code, for receiving on a phone
+    #[clap(long)]
+    qr: bool,
+    /// Also print the code as `wormhole-transfer:` link
+    #[clap(long = "uri")]
+    print_uri: bool,
 }
 
 // send, send-many, serve
 #[derive(Debug, Args)]
 struct CommonLeaderArgs {
-    /// Enter a code instead of generating one automatically
+    /// Enter a code instead of generating one automatically. This may also be a
+    /// `wormhole-transfer:` link with `role=leader`, created by the receiving side.
     #[clap(long, value_name = "CODE")]
     code: Option<String>,
     /// Length of code (in bytes/words)
@@ -91,7 +103,8 @@ struct CommonReceiverArgs {
 // receive, connect
 #[derive(Debug, Args)]
 struct CommonFollowerArgs {
-    /// Provide the code now rather than typing it interactively
+    /// Provide the code now rather than typing it interactively. This may also be a
+    /// `wormhole-transfer:` link.
     #[clap(value_name = "CODE")]
     code: Option<String>,
 }
@@ -111,12 +124,42 @@ struct CommonArgs {
     /// Use a custom rendezvous server. Both sides need to use the same value in order to find each other.
     #[clap(long, value_name = "ws://example.org", value_hint = clap::ValueHint::Url)]
     rendezvous_server: Option<url::Url>,
+    /// Trust the CA certificates in this PEM file for wss:// rendezvous servers (specify multiple times for multiple files)
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath
+    )]
+    rendezvous_ca_cert: Vec<PathBuf>,
+    /// Only accept a wss:// rendezvous server whose certificate chain contains this public key (specify multiple times for multiple keys)
+    #[clap(long, multiple_occurrences = true, value_name = "sha256/BASE64")]
+    rendezvous_pin: Vec<magic_wormhole::rendezvous::tls::SpkiPin>,
+    /// Authenticate to the wss:// rendezvous server with the certificate (chain) in this PEM file
+    #[clap(
+        long,
+        requires = "rendezvous-client-key",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath
+    )]
+    rendezvous_client_cert: Option<PathBuf>,
+    /// The private key for --rendezvous-client-cert, as PEM file
+    #[clap(
+        long,
+        requires = "rendezvous-client-cert",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath
+    )]
+    rendezvous_client_key: Option<PathBuf>,
     /// Disable the relay server support and force a direct connection.
     #[clap(long)]
     force_direct: bool,
     /// Always route traffic over a relay server. This hides your IP address from the peer (but not from the server operators. Use Tor for that).
     #[clap(long, conflicts_with = "force-direct")]
     force_relay: bool,
+    /// Generate and complete codes with this wordlist: pgp (the default), german, pinyin, or a file with one word per line. Codes made of the bundled wordlists are always accepted.
+    #[clap(long, value_name = "NAME|FILE", value_hint = clap::ValueHint::FilePath)]
+    wordlist: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -165,12 +208,23 @@ enum ForwardCommand {
 
 #[derive(Debug, Subcommand)]
 enum WormholeCommand {
-    /// Send a file or a folder
+    /// Send a file, a folder or a text message
     #[clap(
         visible_alias = "tx",
         mut_arg("help", |a| a.help("Print this help message")),
+        mut_arg("files", |a| a.required_unless_present("text")),
     )]
     Send {
+        /// Let the receiver continue an interrupted transfer. This only works if both sides use this flag.
+        #[clap(long)]
+        resume: bool,
+        /// Send a text message instead of files. Use "-" to read it from stdin.
+        #[clap(
+            long,
+            value_name = "MESSAGE",
+            conflicts_with_all = &["files", "file-name", "resume"]
+        )]
+        text: Option<String>,
         #[clap(flatten)]
         common: CommonArgs,
         #[clap(flatten)]
@@ -213,6 +267,15 @@ enum WormholeCommand {
         /// Accept file transfer without asking for confirmation
         #[clap(long, visible_alias = "yes")]
         noconfirm: bool,
+        /// Continue an interrupted transfer, keeping the partially received files if interrupted again. This only works if the sender uses this flag as well.
+        #[clap(long)]
+        resume: bool,
+        /// Also create symlinks that have an absolute target or point outside of the received folder
+        #[clap(long)]
+        allow_unsafe_symlinks: bool,
+        /// Keep the modification times and permissions of the received files, if the sender provides them
+        #[clap(long)]
+        preserve_metadata: bool,
         #[clap(flatten)]
         common: CommonArgs,
         #[clap(flatten)]
@@ -223,6 +286,18 @@ enum WormholeCommand {
     /// Forward ports from one machine to another
     #[clap(subcommand)]
     Forward(ForwardCommand),
+    /// Run a transit relay server for others to use
+    #[clap(
+        mut_arg("help", |a| a.help("Print this help message")),
+    )]
+    RelayServer {
+        /// Accept TCP relay connections on this address
+        #[clap(long, value_name = "ADDRESS", default_value = "[::]:4001", value_hint = clap::ValueHint::Other)]
+        listen: std::net::SocketAddr,
+        /// Additionally accept WebSocket relay connections on this address
+        #[clap(long, value_name = "ADDRESS", value_hint = clap::ValueHint::Other)]
+        websocket: Option<std::net::SocketAddr>,
+    },
     /// Generate shell completions for the wormhole CLI
     #[clap(hide = true)]
     Completion {
@@ -288,14 +363,30 @@ async fn main() -> eyre::Result<()> {
 
     match app.command {
         WormholeCommand::Send {
+            resume,
+            text,
             common,
             common_leader: CommonLeaderArgs { code, code_length },
-            common_send: CommonSenderArgs { file_name, files },
+            common_send:
+                CommonSenderArgs {
+                    file_name,
+                    files,
+                    qr,
+                    print_uri,
+                },
             ..
         } => {
-            let offer = make_send_offer(files, file_name).await?;
+            let offer = match text {
+                Some(_) => None,
+                None => Some(make_send_offer(files, file_name).await?),
+            };
+            let text = match text.as_deref() {
+                Some("-") => Some(read_text_from_stdin().await?),
+                _ => text,
+            };
 
             let transit_abilities = parse_transit_args(&common);
+            let print_code = sender_print_code_fn(qr, print_uri);
             let (wormhole, _code, relay_hints) = match util::cancellable(
                 Box::pin(parse_and_connect(
                     &mut term,
@@ -303,8 +394,8 @@ async fn main() -> eyre::Result<()> {
                     code,
                     Some(code_length),
                     true,
-                    transfer::APP_CONFIG,
-                    Some(&sender_print_code),
+                    transfer_config(resume),
+                    Some(&print_code),
                     clipboard.as_mut(),
                 )),
                 ctrl_c(),
@@ -315,14 +406,18 @@ async fn main() -> eyre::Result<()> {
                 Err(_) => return Ok(()),
             };
 
-            Box::pin(send(
-                wormhole,
-                relay_hints,
-                offer,
-                transit_abilities,
-                ctrl_c.clone(),
-            ))
-            .await?;
+            if let Some(offer) = offer {
+                Box::pin(send(
+                    wormhole,
+                    relay_hints,
+                    offer,
+                    transit_abilities,
+                    ctrl_c.clone(),
+                ))
+                .await?;
+            } else if let Some(text) = text {
+                Box::pin(send_text(wormhole, text, ctrl_c.clone())).await?;
+            }
         },
         #[allow(unused_variables)]
         WormholeCommand::SendMany {
@@ -330,10 +425,27 @@ async fn main() -> eyre::Result<()> {
             timeout,
             common,
             common_leader: CommonLeaderArgs { code, code_length },
-            common_send: CommonSenderArgs { file_name, files },
+            common_send:
+                CommonSenderArgs {
+                    file_name,
+                    files,
+                    qr,
+                    print_uri,
+                },
             ..
         } => {
+            /* A link is made for a single transfer */
+            eyre::ensure!(
+                !code.as_deref().is_some_and(|code| code.contains(':')),
+                "Sending to many recipients does not work with links, use a code instead"
+            );
             let transit_abilities = parse_transit_args(&common);
+            /* Every recipient connects anew, with the same server settings */
+            let mut app_config = transfer::APP_CONFIG.tls(rendezvous_tls(&common)?);
+            if let Some(rendezvous_server) = &common.rendezvous_server {
+                app_config = app_config.rendezvous_url(rendezvous_server.to_string().into());
+            }
+            let print_code = sender_print_code_fn(qr, print_uri);
             let (wormhole, code, relay_hints) = {
                 let connect_fut = Box::pin(parse_and_connect(
                     &mut term,
@@ -341,8 +453,8 @@ async fn main() -> eyre::Result<()> {
                     code,
                     Some(code_length),
                     true,
-                    transfer::APP_CONFIG,
-                    Some(&sender_print_code),
+                    app_config.clone(),
+                    Some(&print_code),
                     clipboard.as_mut(),
                 ));
                 match futures::future::select(connect_fut, ctrl_c()).await {
@@ -353,8 +465,9 @@ async fn main() -> eyre::Result<()> {
             let timeout = Duration::from_secs(timeout * 60);
 
             Box::pin(send_many(
+                app_config,
                 relay_hints,
-                &code,
+                code,
                 files,
                 file_name,
                 tries,
@@ -368,6 +481,9 @@ async fn main() -> eyre::Result<()> {
         },
         WormholeCommand::Receive {
             noconfirm,
+            resume,
+            allow_unsafe_symlinks,
+            preserve_metadata,
             common,
             common_follower: CommonFollowerArgs { code },
             common_receiver: CommonReceiverArgs { file_path },
@@ -381,7 +497,7 @@ async fn main() -> eyre::Result<()> {
                     code,
                     None,
                     false,
-                    transfer::APP_CONFIG,
+                    transfer_config(resume),
                     None,
                     clipboard.as_mut(),
                 ));
@@ -396,6 +512,13 @@ async fn main() -> eyre::Result<()> {
                 relay_hints,
                 &file_path,
                 noconfirm,
+                resume,
+                if allow_unsafe_symlinks {
+                    transfer::SymlinkPolicy::Any
+                } else {
+                    transfer::SymlinkPolicy::Contained
+                },
+                preserve_metadata,
                 transit_abilities,
                 ctrl_c,
             ))
@@ -516,6 +639,25 @@ async fn main() -> eyre::Result<()> {
                 offer.reject().await?;
             }
         },
+        WormholeCommand::RelayServer { listen, websocket } => {
+            let mut relay = transit::server::RelayServer::new()
+                .listen_tcp(listen)
+                .await
+                .context(format!("Failed to listen on {}", listen))?;
+            log::info!("Relaying TCP connections on {}", listen);
+            if let Some(websocket) = websocket {
+                relay = relay
+                    .listen_websocket(websocket)
+                    .await
+                    .context(format!("Failed to listen on {}", websocket))?;
+                log::info!("Relaying WebSocket connections on {}", websocket);
+            }
+            match futures::future::select(Box::pin(relay.run()), ctrl_c()).await {
+                Either::Left((result, _)) => result?,
+                Either::Right(((), _)) => (),
+            }
+            return Ok(());
+        },
         WormholeCommand::Completion { shell } => {
             let mut cmd = WormholeCli::command();
             let binary_name = cmd.get_name().to_string();
@@ -578,10 +720,13 @@ async fn parse_and_connect(
     code: Option<String>,
     code_length: Option<usize>,
     is_send: bool,
-    mut app_config: magic_wormhole::AppConfig<impl serde::Serialize + Send + Sync + 'static>,
+    mut app_config: magic_wormhole::AppConfig<
+        impl serde::Serialize + Clone + Send + Sync + 'static,
+    >,
     print_code: Option<&PrintCodeFn>,
     clipboard: Option<&mut Clipboard>,
 ) -> eyre::Result<(Wormhole, magic_wormhole::Code, Vec<transit::RelayHint>)> {
+    let tls = rendezvous_tls(&common_args)?;
     // TODO handle relay servers with multiple endpoints better
     let mut relay_hints: Vec<transit::RelayHint> = common_args
         .relay_server
@@ -596,16 +741,66 @@ async fn parse_and_connect(
                 .unwrap()],
         )?)
     }
-    let code = code
-        .map(Result::Ok)
-        .or_else(|| (!is_send).then(enter_code))
-        .transpose()?
-        .map(magic_wormhole::Code);
+    /* Apply the server settings already, the code completion needs them */
+    app_config = app_config.tls(tls);
+    if let Some(rendezvous_server) = &common_args.rendezvous_server {
+        app_config = app_config.rendezvous_url(rendezvous_server.to_string().into());
+    }
+    if let Some(wordlist) = &common_args.wordlist {
+        let wordlist = load_wordlist(wordlist)?;
+        log::info!(
+            "Using a wordlist with {:.1} bits of entropy per word",
+            wordlist.entropy_per_word()
+        );
+        if let (Some(code_length), None) = (code_length, &code) {
+            let entropy = wordlist.entropy_per_word() * code_length as f64;
+            if entropy < 16.0 {
+                log::warn!(
+                    "A code with {} words has only {:.1} bits of entropy, consider a longer --code-length",
+                    code_length,
+                    entropy
+                );
+            }
+        }
+        app_config = app_config.wordlist(wordlist);
+    }
+    let code = match code {
+        None if !is_send => Some(enter_code(&app_config).await?),
+        code => code,
+    };
 
     /* We need to track that information for when we generate a QR code */
-    let mut uri_rendezvous = None;
-    if let Some(rendezvous_server) = common_args.rendezvous_server {
-        uri_rendezvous = Some(rendezvous_server.clone());
+    let mut uri_rendezvous = common_args.rendezvous_server;
+    /* Codes never contain a colon, so this must be a link */
+    let code = match code {
+        Some(code) if code.contains(':') => {
+            let uri: WormholeTransferUri = code.parse().context("Invalid wormhole-transfer link")?;
+            match (uri.is_leader, is_send) {
+                (true, false) => eyre::bail!(
+                    "This link is for sending files to whoever created it, use `wormhole-rs send --code <LINK>` instead"
+                ),
+                (false, true) => eyre::bail!(
+                    "This link is for receiving, use `wormhole-rs receive <LINK>` instead"
+                ),
+                _ => {},
+            }
+            if let Some(rendezvous_server) = uri.rendezvous_server {
+                if let Some(configured) = &uri_rendezvous {
+                    eyre::ensure!(
+                        *configured == rendezvous_server,
+                        "The link uses the rendezvous server {}, but {} was given",
+                        rendezvous_server,
+                        configured
+                    );
+                }
+                uri_rendezvous = Some(rendezvous_server);
+            }
+            Some(uri.code)
+        },
+        Some(code) => Some(parse_code(&code, &app_config).context("Invalid code")?),
+        None => None,
+    };
+    if let Some(rendezvous_server) = &uri_rendezvous {
         app_config = app_config.rendezvous_url(rendezvous_server.to_string().into());
     }
     let mailbox_connection = match code {
@@ -647,6 +842,57 @@ async fn parse_and_connect(
     eyre::Result::<_>::Ok((wormhole, code, relay_hints))
 }
 
+/** A bundled wordlist by name, or else the one in the file at that path */
+fn load_wordlist(name: &str) -> eyre::Result<Wordlist> {
+    match Wordlist::bundled(name) {
+        Some(wordlist) => Ok(wordlist),
+        None => Wordlist::load(name).with_context(|| {
+            format!(
+                "'{}' is neither a file nor one of the bundled wordlists ({})",
+                name,
+                wordlist::BUNDLED_WORDLISTS.join(", ")
+            )
+        }),
+    }
+}
+
+/** Parse a code, also accepting the words from the configured wordlist */
+fn parse_code<V>(
+    code: &str,
+    app_config: &magic_wormhole::AppConfig<V>,
+) -> Result<magic_wormhole::Code, magic_wormhole::CodeParseError> {
+    magic_wormhole::Code::parse_with_config(code, app_config)
+}
+
+/** Load the TLS settings for the rendezvous server from the files given on the command line */
+fn rendezvous_tls(
+    common_args: &CommonArgs,
+) -> eyre::Result<magic_wormhole::rendezvous::tls::TlsConfig> {
+    use magic_wormhole::rendezvous::tls::{ClientIdentity, TlsConfig};
+
+    let read = |path: &PathBuf| {
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+    };
+
+    let mut tls = TlsConfig::new();
+    for path in &common_args.rendezvous_ca_cert {
+        tls = tls
+            .add_root_certificates_pem(&read(path)?)
+            .with_context(|| format!("Failed to load CA certificates from {}", path.display()))?;
+    }
+    tls.spki_pins.extend(&common_args.rendezvous_pin);
+    if let (Some(cert), Some(key)) = (
+        &common_args.rendezvous_client_cert,
+        &common_args.rendezvous_client_key,
+    ) {
+        tls = tls.client_identity(
+            ClientIdentity::from_pem(&read(cert)?, &read(key)?)
+                .context("Failed to load the client certificate")?,
+        );
+    }
+    Ok(tls)
+}
+
 async fn make_send_offer(
     mut files: Vec<PathBuf>,
     file_name: Option<String>,
@@ -711,23 +957,73 @@ fn create_progress_bar(file_size: u64) -> ProgressBar {
 }
 
 fn create_progress_handler(pb: ProgressBar) -> impl FnMut(u64, u64) {
-    move |sent, total| {
-        if sent == 0 {
-            pb.reset_elapsed();
-            pb.set_length(total);
-            pb.enable_steady_tick(std::time::Duration::from_millis(250));
-        }
-        pb.set_position(sent);
+    move |sent, total| update_progress_bar(&pb, sent, total)
+}
+
+fn update_progress_bar(pb: &ProgressBar, sent: u64, total: u64) {
+    if sent == 0 {
+        pb.reset_elapsed();
+        pb.set_length(total);
+        pb.enable_steady_tick(std::time::Duration::from_millis(250));
     }
+    pb.set_position(sent);
 }
 
-fn enter_code() -> eyre::Result<String> {
+async fn enter_code(
+    app_config: &magic_wormhole::AppConfig<impl serde::Serialize + Clone + Send + Sync + 'static>,
+) -> eyre::Result<String> {
     use dialoguer::Input;
 
-    Input::new()
+    let new_completer = || CodeCompleter::from_config(app_config, 2);
+
+    /* Don't wait for the server before prompting. Until the nameplates are listed, only the words get completed */
+    let completer = Arc::new(std::sync::Mutex::new(new_completer()));
+    let fetch = {
+        let completer = completer.clone();
+        let mut fetching = new_completer();
+        let app_config = app_config.clone();
+        async_std::task::spawn(async move {
+            match fetching.fetch_nameplates(&app_config).await {
+                Ok(()) => *completer.lock().unwrap() = fetching,
+                Err(err) => log::debug!("Failed to list the nameplates for completion: {}", err),
+            }
+        })
+    };
+
+    let code = Input::new()
         .with_prompt("Enter code")
-        .interact_text()
-        .map_err(From::from)
+        .completion_with(&CodeCompletion(completer))
+        .validate_with(|code: &String| -> Result<(), String> {
+            /* Links get checked later on, with a more helpful error message */
+            if code.contains(':') {
+                return Ok(());
+            }
+            parse_code(code, app_config)
+                .map(drop)
+                .map_err(|err| err.to_string())
+        })
+        .interact_text();
+    fetch.cancel().await;
+    code.map_err(From::from)
+}
+
+/** Tab completes codes as far as all possible completions agree */
+struct CodeCompletion(Arc<std::sync::Mutex<CodeCompleter>>);
+
+impl dialoguer::Completion for CodeCompletion {
+    fn get(&self, input: &str) -> Option<String> {
+        let completions = self.0.lock().unwrap().complete(input);
+        let first = completions.first()?.as_str();
+        let common = completions.iter().fold(first, |common, completion| {
+            let length = common
+                .char_indices()
+                .zip(completion.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(completion.len()), |((index, _), _)| index);
+            &common[..length]
+        });
+        (common.len() > input.len()).then(|| common.to_owned())
+    }
 }
 
 fn print_welcome(term: &mut Term, welcome: &Option<String>) -> eyre::Result<()> {
@@ -738,26 +1034,40 @@ fn print_welcome(term: &mut Term, welcome: &Option<String>) -> eyre::Result<()>
 }
 
 // For file transfer
+fn sender_print_code_fn(
+    qr: bool,
+    print_uri: bool,
+) -> impl Fn(&mut Term, &magic_wormhole::Code, &Option<url::Url>) -> eyre::Result<()> {
+    move |term, code, rendezvous_server| {
+        sender_print_code(term, code, rendezvous_server, qr, print_uri)
+    }
+}
+
 fn sender_print_code(
     term: &mut Term,
     code: &magic_wormhole::Code,
     rendezvous_server: &Option<url::Url>,
+    qr: bool,
+    print_uri: bool,
 ) -> eyre::Result<()> {
-    let uri = magic_wormhole::uri::WormholeTransferUri {
+    let uri = WormholeTransferUri {
         code: code.clone(),
         rendezvous_server: rendezvous_server.clone(),
         is_leader: false,
-    }
-    .to_string();
+    };
     writeln!(
         term,
         "{}",
         style(&code).bold()
     )?;
-    // writeln!(term, "This is equivalent to the following link: \u{001B}]8;;{}\u{001B}\\{}\u{001B}]8;;\u{001B}\\", &uri, &uri)?;
-    // let qr =
-    //     qr2term::generate_qr_string(&uri).context("Failed to generate QR code for send link")?;
-    // writeln!(term, "{}", qr)?;
+    if print_uri {
+        writeln!(term, "This is equivalent to the following link: {}", uri)?;
+    }
+    if qr {
+        let qr = QrCode::new(&uri).context("Failed to generate QR code for send link")?;
+        /* Most terminals have a dark background */
+        write!(term, "{}", qr.to_terminal_string(true))?;
+    }
 
     // writeln!(
     //     term,
@@ -816,9 +1126,34 @@ async fn send(
     Ok(())
 }
 
+async fn read_text_from_stdin() -> eyre::Result<String> {
+    use async_std::io::ReadExt;
+
+    log::info!("Reading text message from stdin…");
+    let mut text = String::new();
+    async_std::io::stdin()
+        .read_to_string(&mut text)
+        .await
+        .context("Failed to read the text message from stdin")?;
+    Ok(text)
+}
+
+async fn send_text(
+    wormhole: Wormhole,
+    text: String,
+    ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    transfer::send_text(wormhole, text, ctrl_c())
+        .await
+        .context("Send process failed")?;
+    log::info!("Text message sent");
+    Ok(())
+}
+
 async fn send_many(
+    app_config: magic_wormhole::AppConfig<transfer::AppVersion>,
     relay_hints: Vec<transit::RelayHint>,
-    code: &magic_wormhole::Code,
+    code: magic_wormhole::Code,
     files: Vec<PathBuf>,
     file_name: Option<String>,
     max_tries: u64,
@@ -834,100 +1169,93 @@ async fn send_many(
      * the Indicatif repository for more information. Multiple progress bars are not usable
      * for us at the moment, so we'll have to do without for now.
      */
-    let mp = MultiProgress::new();
-    let time = Instant::now();
-
-    /* Special-case the first send with reusing the existing connection */
-    send_in_background(
-        relay_hints.clone(),
-        make_send_offer(files.clone(), file_name.clone()).await?,
-        wormhole,
-        term.clone(),
-        &mp,
-        transit_abilities,
-        ctrl_c(),
-    )
-    .await?;
+    struct Handler {
+        term: Term,
+        mp: MultiProgress,
+        progress_bars: std::sync::Mutex<std::collections::HashMap<u64, ProgressBar>>,
+    }
 
-    for tries in 0.. {
-        if time.elapsed() >= timeout {
-            log::info!(
-                "{:?} have elapsed, we won't accept any new connections now.",
-                timeout
-            );
-            break;
-        }
-        if tries > max_tries {
-            log::info!("Max number of tries reached, we won't accept any new connections now.");
-            break;
+    impl transfer::SendManyHandler for Handler {
+        fn connected(&self, recipient: u64) {
+            writeln!(&self.term, "Sending file to peer").unwrap();
+            let pb = self.mp.add(create_progress_bar(0));
+            self.progress_bars.lock().unwrap().insert(recipient, pb);
         }
 
-        let wormhole = Wormhole::connect(
-            MailboxConnection::connect(transfer::APP_CONFIG, code.clone(), false).await?,
-        )
-        .await?;
+        fn transit(&self, _recipient: u64, info: transit::TransitInfo) {
+            transit::log_transit_connection(info);
+        }
 
-        send_in_background(
-            relay_hints.clone(),
-            make_send_offer(files.clone(), file_name.clone()).await?,
-            wormhole,
-            term.clone(),
-            &mp,
-            transit_abilities,
-            ctrl_c(),
-        )
-        .await?;
-    }
+        fn progress(&self, recipient: u64, sent: u64, total: u64) {
+            if let Some(pb) = self.progress_bars.lock().unwrap().get(&recipient) {
+                update_progress_bar(pb, sent, total);
+            }
+        }
 
-    async fn send_in_background(
-        relay_hints: Vec<transit::RelayHint>,
-        offer: transfer::OfferSend,
-        wormhole: Wormhole,
-        mut term: Term,
-        mp: &MultiProgress,
-        transit_abilities: transit::Abilities,
-        cancel: impl Future<Output = ()> + Send + 'static,
-    ) -> eyre::Result<()> {
-        writeln!(&mut term, "Sending file to peer").unwrap();
-        let pb = create_progress_bar(0);
-        let pb = mp.add(pb);
-        async_std::task::spawn(async move {
-            let pb2 = pb.clone();
-            let result = async move {
-                transfer::send(
-                    wormhole,
-                    relay_hints,
-                    transit_abilities,
-                    offer,
-                    &transit::log_transit_connection,
-                    create_progress_handler(pb2),
-                    cancel,
-                )
-                .await?;
-                eyre::Result::<_>::Ok(())
-            };
-            match result.await {
-                Ok(_) => {
-                    pb.finish();
+        fn finished(&self, recipient: u64, result: Result<(), transfer::TransferError>) {
+            let pb = self.progress_bars.lock().unwrap().remove(&recipient);
+            match result {
+                Ok(()) => {
+                    if let Some(pb) = pb {
+                        pb.finish();
+                    }
                     log::info!("Successfully sent file to someone");
                 },
                 Err(e) => {
-                    pb.abandon();
+                    if let Some(pb) = pb {
+                        pb.abandon();
+                    }
                     log::error!("Send failed, {}", e);
                 },
-            };
-        });
-        Ok(())
+            }
+        }
     }
 
+    let handler = Handler {
+        term: term.clone(),
+        mp: MultiProgress::new(),
+        progress_bars: Default::default(),
+    };
+    transfer::send_many(
+        wormhole,
+        app_config,
+        code,
+        relay_hints,
+        transit_abilities,
+        || {
+            make_send_offer(files.clone(), file_name.clone())
+                .map(|offer| offer.map_err(std::io::Error::other))
+        },
+        transfer::SendManyLimits {
+            max_recipients: max_tries,
+            timeout,
+        },
+        handler,
+        ctrl_c(),
+    )
+    .await
+    .context("Send process failed")?;
+
     Ok(())
 }
 
+/** Resuming transfers requires transfer-v2, which we don't advertize by default yet */
+fn transfer_config(resume: bool) -> magic_wormhole::AppConfig<transfer::AppVersion> {
+    if resume {
+        transfer::APP_CONFIG.app_version(transfer::AppVersion::default().enable_v2())
+    } else {
+        transfer::APP_CONFIG
+    }
+}
+
 async fn receive(
     wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    resume: bool,
+    symlink_policy: transfer::SymlinkPolicy,
+    preserve_metadata: bool,
     transit_abilities: transit::Abilities,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
@@ -937,10 +1265,32 @@ async fn receive(
     /* If None, the task got cancelled */
     match req {
         Some(transfer::ReceiveRequest::V1(req)) => {
+            if resume {
+                log::warn!("The sender does not support resuming transfers, starting from scratch");
+            }
             receive_inner_v1(req, target_dir, noconfirm, ctrl_c).await
         },
+        Some(transfer::ReceiveRequest::Text(req)) => {
+            println!("{}", req.text);
+            req.accept()
+                .await
+                .context("Could not acknowledge the message")
+        },
         Some(transfer::ReceiveRequest::V2(req)) => {
-            receive_inner_v2(req, target_dir, noconfirm, ctrl_c).await
+            receive_inner_v2(
+                req,
+                target_dir,
+                noconfirm,
+                resume,
+                symlink_policy,
+                preserve_metadata,
+                ctrl_c,
+            )
+            .await
+        },
+        Some(req) => {
+            req.reject().await?;
+            eyre::bail!("The sender sent something this version does not know how to receive");
         },
         None => Ok(()),
     }
@@ -1035,12 +1385,20 @@ async fn receive_inner_v2(
     req: transfer::ReceiveRequestV2,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    resume: bool,
+    symlink_policy: transfer::SymlinkPolicy,
+    preserve_metadata: bool,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
     let offer = req.offer();
     let file_size = offer.total_size();
     let offer_name = offer.offer_name();
 
+    if let Err(err) = offer.check_symlinks(symlink_policy) {
+        req.reject().await.context("Could not reject offer")?;
+        return Err(err).context("Refusing the offer, use --allow-unsafe-symlinks to receive it anyway");
+    }
+
     use number_prefix::NumberPrefix;
     if !(noconfirm
         || util::ask_user(
@@ -1066,12 +1424,24 @@ async fn receive_inner_v2(
         pb.set_position(received);
     };
 
-    /* Create a temporary directory for receiving */
-    use rand::Rng;
-    let tmp_dir = target_dir.join(format!(
-        "wormhole-tmp-{:06}",
-        rand::thread_rng().gen_range(0..1_000_000)
-    ));
+    async_std::fs::create_dir_all(target_dir)
+        .await
+        .context("Failed to create the target directory")?;
+
+    /* Create a temporary directory for receiving. When resuming, it is named after the offer so that we find it again */
+    let tmp_dir = if resume {
+        use sha2::Digest;
+
+        let offer_hash = sha2::Sha256::digest(serde_json::to_vec(&transfer::Offer::from(&*offer))?);
+        target_dir.join(format!("wormhole-partial-{:x}", offer_hash))
+    } else {
+        /* Keep it around on failure, so that the received files can still be recovered */
+        tempfile::Builder::new()
+            .prefix("wormhole-tmp-")
+            .tempdir_in(target_dir)
+            .context("Failed to create temporary directory for receiving")?
+            .into_path()
+    };
     async_std::fs::create_dir_all(&tmp_dir)
         .await
         .context("Failed to create temporary directory for receiving")?;
@@ -1080,18 +1450,52 @@ async fn receive_inner_v2(
     offer.create_directories(&tmp_dir).await?;
 
     /* Accept the offer and receive it */
-    let answer = offer.accept_all(&tmp_dir);
-    req.accept(
-        &transit::log_transit_connection,
-        answer,
-        on_progress,
-        ctrl_c(),
-    )
-    .await
-    .context("Receive process failed")?;
+    let answer = if resume {
+        offer
+            .accept_all_resumable(&tmp_dir)
+            .await
+            .context("Failed to check for partially received files")?
+    } else {
+        offer.accept_all(&tmp_dir)
+    };
+    let cancelled = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let cancel = {
+        let cancelled = cancelled.clone();
+        ctrl_c().map(move |()| cancelled.store(true, std::sync::atomic::Ordering::SeqCst))
+    };
+    let result = req
+        .accept(
+            &transit::log_transit_connection,
+            answer,
+            on_progress,
+            cancel,
+        )
+        .await;
+    if result.is_err() || cancelled.load(std::sync::atomic::Ordering::SeqCst) {
+        if resume {
+            log::info!(
+                "Keeping the partially received files in {}. Receive again with --resume to continue.",
+                tmp_dir.display()
+            );
+        } else if result.is_ok() {
+            /* Cancelled, don't leave incomplete files around */
+            let _ = async_std::fs::remove_dir_all(&tmp_dir).await;
+        }
+        return result.context("Receive process failed");
+    }
+
+    /* Put in all the symlinks last, this greatly reduces the attack surface */
+    offer
+        .create_symlinks(&tmp_dir, symlink_policy)
+        .await
+        .context("Failed to create symlinks")?;
 
-    // /* Put in all the symlinks last, this greatly reduces the attack surface */
-    // offer.create_symlinks(&tmp_dir).await?;
+    if preserve_metadata {
+        offer
+            .apply_metadata(&tmp_dir)
+            .await
+            .context("Failed to apply the file metadata")?;
+    }
 
     /* TODO walk the output directory and delete things we did not accept; this will be important for resumption */
 
@@ -1150,4 +1554,40 @@ mod test {
             String::from_utf8(out).unwrap();
         }
     }
+
+    #[test]
+    fn test_code_completion() {
+        use dialoguer::Completion;
+
+        let completion = CodeCompletion(Arc::new(std::sync::Mutex::new(
+            CodeCompleter::new(2).with_nameplates(vec![
+                magic_wormhole::Nameplate::new("12"),
+                magic_wormhole::Nameplate::new("13"),
+            ]),
+        )));
+        assert_eq!(completion.get(""), Some("1".into()));
+        assert_eq!(completion.get("1"), None);
+        assert_eq!(completion.get("12"), Some("12-".into()));
+        assert_eq!(completion.get("12-armis"), Some("12-armistice-".into()));
+        /* "backfield" and "backward" */
+        assert_eq!(completion.get("12-armistice-bac"), Some("12-armistice-back".into()));
+        assert_eq!(completion.get("12-armistice-bab"), Some("12-armistice-baboon".into()));
+        assert_eq!(completion.get("12-armistice-baboon"), None);
+    }
+
+    #[test]
+    fn test_wordlist() {
+        let app_config = transfer::APP_CONFIG.wordlist(load_wordlist("german").unwrap());
+        assert!(parse_code("7-apfel-adler", &app_config).is_ok());
+        assert!(parse_code("7-apfel-adlr", &app_config).is_err());
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "11111\tabacus\n11112\tabdomen\n").unwrap();
+        let app_config =
+            transfer::APP_CONFIG.wordlist(load_wordlist(file.path().to_str().unwrap()).unwrap());
+        assert!(parse_code("7-abdomen-abacus", &app_config).is_ok());
+        assert!(parse_code("7-abdomen-abacsu", &app_config).is_err());
+
+        assert!(load_wordlist("klingon").is_err());
+    }
 }
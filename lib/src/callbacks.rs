@@ -0,0 +1,296 @@
+//! Function pointers through which C callers get notified about the progress of a session
+
+use std::{
+    ffi::{c_void, CString},
+    os::raw::c_char,
+};
+
+use color_eyre::eyre;
+use magic_wormhole::{transfer, transit};
+
+use crate::error::{self, WormholeErrorCode};
+
+/// How we are connected to the peer
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WormholeConnectionType {
+    /// Directly connected to the peer
+    Direct = 0,
+    /// Connected through a relay server
+    Relay = 1,
+}
+
+/// A file in an offer, only valid during the `on_offer` callback
+#[repr(C)]
+pub struct WormholeOfferFile {
+    /// The path of the file within the offer, with `/` as separator
+    pub path: *const c_char,
+    pub size: u64,
+}
+
+/// Callbacks for the events of a session
+///
+/// All of them are optional and may be null. They get called from a background thread,
+/// together with `user_data`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WormholeCallbacks {
+    pub user_data: *mut c_void,
+    /// The code to tell the peer has been allocated
+    pub on_code: Option<extern "C" fn(user_data: *mut c_void, code: *const c_char)>,
+    /// The transit connection to the peer has been established. `peer_address` is the address
+    /// of the peer or relay server we connected to, as `ip:port`.
+    pub on_connected: Option<
+        extern "C" fn(
+            user_data: *mut c_void,
+            connection_type: WormholeConnectionType,
+            peer_address: *const c_char,
+        ),
+    >,
+    /// The peer offered us these files
+    pub on_offer: Option<
+        extern "C" fn(user_data: *mut c_void, files: *const WormholeOfferFile, length: usize),
+    >,
+    /// `transferred` out of `total` bytes are done
+    pub on_progress: Option<extern "C" fn(user_data: *mut c_void, transferred: u64, total: u64)>,
+}
+
+/* The caller is responsible for `user_data` being usable from other threads */
+unsafe impl Send for WormholeCallbacks {}
+unsafe impl Sync for WormholeCallbacks {}
+
+impl Default for WormholeCallbacks {
+    fn default() -> Self {
+        Self {
+            user_data: std::ptr::null_mut(),
+            on_code: None,
+            on_connected: None,
+            on_offer: None,
+            on_progress: None,
+        }
+    }
+}
+
+impl WormholeCallbacks {
+    /// Copy the callbacks from a nullable pointer
+    pub fn from_ptr(callbacks: *const WormholeCallbacks) -> Self {
+        unsafe { callbacks.as_ref() }.copied().unwrap_or_default()
+    }
+
+    pub fn code(&self, code: &str) {
+        if let Some(on_code) = self.on_code {
+            let code = CString::new(code).expect("Wormhole codes don't contain null bytes");
+            on_code(self.user_data, code.as_ptr());
+        }
+    }
+
+    pub fn connected(&self, info: &transit::TransitInfo) {
+        if let Some(on_connected) = self.on_connected {
+            let (connection_type, peer_address) = connection_info(info);
+            on_connected(self.user_data, connection_type, peer_address.as_ptr());
+        }
+    }
+
+    pub fn offer(&self, files: impl IntoIterator<Item = (String, u64)>) {
+        if let Some(on_offer) = self.on_offer {
+            let paths = files
+                .into_iter()
+                .map(|(path, size)| (crate::to_c_string_lossy(&path), size))
+                .collect::<Vec<_>>();
+            let files = paths
+                .iter()
+                .map(|(path, size)| WormholeOfferFile {
+                    path: path.as_ptr(),
+                    size: *size,
+                })
+                .collect::<Vec<_>>();
+            on_offer(self.user_data, files.as_ptr(), files.len());
+        }
+    }
+
+    pub fn progress(&self, transferred: u64, total: u64) {
+        if let Some(on_progress) = self.on_progress {
+            on_progress(self.user_data, transferred, total);
+        }
+    }
+}
+
+/// The connection type and the peer address of a transit connection
+fn connection_info(info: &transit::TransitInfo) -> (WormholeConnectionType, CString) {
+    let connection_type = match info.conn_type {
+        transit::ConnectionType::Direct => WormholeConnectionType::Direct,
+        _ => WormholeConnectionType::Relay,
+    };
+    (connection_type, CString::new(info.peer_addr.to_string()).unwrap())
+}
+
+/// Callbacks for sending to many recipients
+///
+/// All of them are optional and may be null. They get called from a background thread,
+/// together with `user_data`. Recipients are numbered from zero in the order in which they
+/// connect, the transfers to them may run at the same time.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WormholeSendManyCallbacks {
+    pub user_data: *mut c_void,
+    /// The code to tell the recipients has been allocated
+    pub on_code: Option<extern "C" fn(user_data: *mut c_void, code: *const c_char)>,
+    /// A recipient connected, and the transfer to it starts
+    pub on_recipient_connected: Option<extern "C" fn(user_data: *mut c_void, recipient: u64)>,
+    /// The transit connection to a recipient has been established, like `on_connected`
+    pub on_recipient_transit: Option<
+        extern "C" fn(
+            user_data: *mut c_void,
+            recipient: u64,
+            connection_type: WormholeConnectionType,
+            peer_address: *const c_char,
+        ),
+    >,
+    /// `transferred` out of `total` bytes are done for a recipient
+    pub on_recipient_progress: Option<
+        extern "C" fn(user_data: *mut c_void, recipient: u64, transferred: u64, total: u64),
+    >,
+    /// The transfer to a recipient is over. `error` is `Ok` and `message` is null on success.
+    /// This also gets called for recipients that entered a wrong code.
+    pub on_recipient_finished: Option<
+        extern "C" fn(
+            user_data: *mut c_void,
+            recipient: u64,
+            error: WormholeErrorCode,
+            message: *const c_char,
+        ),
+    >,
+}
+
+/* The caller is responsible for `user_data` being usable from other threads */
+unsafe impl Send for WormholeSendManyCallbacks {}
+unsafe impl Sync for WormholeSendManyCallbacks {}
+
+impl Default for WormholeSendManyCallbacks {
+    fn default() -> Self {
+        Self {
+            user_data: std::ptr::null_mut(),
+            on_code: None,
+            on_recipient_connected: None,
+            on_recipient_transit: None,
+            on_recipient_progress: None,
+            on_recipient_finished: None,
+        }
+    }
+}
+
+impl WormholeSendManyCallbacks {
+    /// Copy the callbacks from a nullable pointer
+    pub fn from_ptr(callbacks: *const WormholeSendManyCallbacks) -> Self {
+        unsafe { callbacks.as_ref() }.copied().unwrap_or_default()
+    }
+
+    pub fn code(&self, code: &str) {
+        if let Some(on_code) = self.on_code {
+            let code = CString::new(code).expect("Wormhole codes don't contain null bytes");
+            on_code(self.user_data, code.as_ptr());
+        }
+    }
+}
+
+impl transfer::SendManyHandler for WormholeSendManyCallbacks {
+    fn connected(&self, recipient: u64) {
+        if let Some(on_recipient_connected) = self.on_recipient_connected {
+            on_recipient_connected(self.user_data, recipient);
+        }
+    }
+
+    fn transit(&self, recipient: u64, info: transit::TransitInfo) {
+        if let Some(on_recipient_transit) = self.on_recipient_transit {
+            let (connection_type, peer_address) = connection_info(&info);
+            on_recipient_transit(self.user_data, recipient, connection_type, peer_address.as_ptr());
+        }
+        transit::log_transit_connection(info);
+    }
+
+    fn progress(&self, recipient: u64, sent: u64, total: u64) {
+        if let Some(on_recipient_progress) = self.on_recipient_progress {
+            on_recipient_progress(self.user_data, recipient, sent, total);
+        }
+    }
+
+    fn finished(&self, recipient: u64, result: Result<(), transfer::TransferError>) {
+        let Some(on_recipient_finished) = self.on_recipient_finished else {
+            return;
+        };
+        match result {
+            Ok(()) => {
+                on_recipient_finished(self.user_data, recipient, WormholeErrorCode::Ok, std::ptr::null())
+            },
+            Err(error) => {
+                let report = eyre::Report::new(error);
+                let message = crate::to_c_string_lossy(&error::report_message(&report));
+                on_recipient_finished(
+                    self.user_data,
+                    recipient,
+                    WormholeErrorCode::from_report(&report),
+                    message.as_ptr(),
+                );
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{ffi::CStr, sync::Mutex};
+
+    extern "C" fn on_code(user_data: *mut c_void, code: *const c_char) {
+        let events = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+        let code = unsafe { CStr::from_ptr(code) }.to_str().unwrap();
+        events.lock().unwrap().push(format!("code {code}"));
+    }
+
+    extern "C" fn on_offer(user_data: *mut c_void, files: *const WormholeOfferFile, length: usize) {
+        let events = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+        for file in unsafe { std::slice::from_raw_parts(files, length) } {
+            let path = unsafe { CStr::from_ptr(file.path) }.to_str().unwrap();
+            events.lock().unwrap().push(format!("file {path} {}", file.size));
+        }
+    }
+
+    extern "C" fn on_progress(user_data: *mut c_void, transferred: u64, total: u64) {
+        let events = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+        events
+            .lock()
+            .unwrap()
+            .push(format!("progress {transferred}/{total}"));
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let events = Mutex::new(Vec::<String>::new());
+        let callbacks = WormholeCallbacks {
+            user_data: &events as *const _ as *mut c_void,
+            on_code: Some(on_code),
+            on_connected: None,
+            on_offer: Some(on_offer),
+            on_progress: Some(on_progress),
+        };
+        let callbacks = WormholeCallbacks::from_ptr(&callbacks);
+
+        callbacks.code("1-foo-bar");
+        callbacks.offer([("folder/file".into(), 42), ("other".into(), 0)]);
+        callbacks.progress(21, 42);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "code 1-foo-bar",
+                "file folder/file 42",
+                "file other 0",
+                "progress 21/42"
+            ]
+        );
+
+        /* Missing callbacks are fine */
+        let callbacks = WormholeCallbacks::from_ptr(std::ptr::null());
+        callbacks.code("1-foo-bar");
+        callbacks.progress(21, 42);
+    }
+}
@@ -0,0 +1,72 @@
+//! Code completion for the code entry fields of GUIs
+
+use std::ffi::CString;
+
+use color_eyre::eyre;
+use magic_wormhole::{transfer, AppConfig, CodeCompleter};
+
+/// Completes codes while the user types them
+pub struct Completer {
+    completer: CodeCompleter,
+    app_config: AppConfig<transfer::AppVersion>,
+    completions: Vec<CString>,
+}
+
+impl Completer {
+    pub fn new(app_config: AppConfig<transfer::AppVersion>, code_length: usize) -> Self {
+        let completer = CodeCompleter::from_config(&app_config, code_length);
+        Self {
+            completer,
+            app_config,
+            completions: Vec::new(),
+        }
+    }
+
+    /// Fetch the nameplates that are in use right now from the rendezvous server
+    pub async fn refresh(&mut self) -> eyre::Result<()> {
+        self.completer.fetch_nameplates(&self.app_config).await?;
+        Ok(())
+    }
+
+    /// Replace the completions with the ones for `prefix`, and return them
+    pub fn complete(&mut self, prefix: &str) -> &[CString] {
+        self.completions = self
+            .completer
+            .complete(prefix)
+            .into_iter()
+            .map(|completion| CString::new(completion).unwrap())
+            .collect();
+        &self.completions
+    }
+
+    /// The completions from the last call to `complete`
+    pub fn completions(&self) -> &[CString] {
+        &self.completions
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::options::Options;
+    use magic_wormhole::wordlist::Wordlist;
+
+    #[test]
+    fn test_completer() {
+        let mut completer = Completer::new(Options::default().app_config(), 2);
+        /* No nameplates without asking the server */
+        assert!(completer.complete("4").is_empty());
+        assert_eq!(
+            completer.complete("4-armistice-bab"),
+            &[CString::new("4-armistice-baboon").unwrap()]
+        );
+        assert_eq!(completer.completions().len(), 1);
+
+        let app_config = Options::default()
+            .app_config()
+            .wordlist(Wordlist::bundled("pinyin").unwrap());
+        let mut completer = Completer::new(app_config, 2);
+        assert!(completer.complete("4-armis").is_empty());
+        assert!(!completer.complete("4-a").is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! Error categories and messages for C callers
+
+use std::{cell::RefCell, error::Error, ffi::CString, os::raw::c_char};
+
+use color_eyre::eyre;
+use magic_wormhole::{
+    rendezvous::RendezvousError,
+    transfer::TransferError,
+    transit::{TransitConnectError, TransitError},
+    WormholeError,
+};
+
+/// What kind of error happened
+///
+/// The values are stable, new categories only get appended.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WormholeErrorCode {
+    /// No error happened
+    Ok = 0,
+    /// The code is wrong, or somebody tried to guess it
+    WrongCode = 1,
+    /// The peer rejected the transfer
+    PeerRejected = 2,
+    /// Something went wrong on the peer's side
+    PeerError = 3,
+    /// Could not talk to the rendezvous server, the relay server or the peer
+    Network = 4,
+    /// The transfer got cancelled by the peer, or by us
+    Cancelled = 5,
+    /// Reading or writing the local files failed
+    IO = 6,
+    /// The peer or a server sent something we did not understand
+    Protocol = 7,
+    /// An argument of the called function is invalid
+    InvalidArgument = 8,
+    /// Anything else
+    Other = 9,
+}
+
+/* The message the peer gets when a transfer is cancelled, see `transfer::cancel::Cancelled` */
+const PEER_CANCELLED: &str = "Task has been cancelled";
+const PEER_REJECTED: &str = "transfer rejected";
+
+impl WormholeErrorCode {
+    /// Find the category of the outermost known error in the chain, which is the most specific one
+    pub fn from_report(report: &eyre::Report) -> Self {
+        report
+            .chain()
+            .find_map(Self::from_error)
+            .unwrap_or(Self::Other)
+    }
+
+    fn from_error(error: &(dyn Error + 'static)) -> Option<Self> {
+        if let Some(error) = error.downcast_ref::<TransferError>() {
+            Some(Self::from_transfer_error(error))
+        } else if let Some(error) = error.downcast_ref::<WormholeError>() {
+            Some(Self::from_wormhole_error(error))
+        } else if let Some(error) = error.downcast_ref::<RendezvousError>() {
+            Some(Self::from_rendezvous_error(error))
+        } else if error.is::<TransitConnectError>() || error.is::<TransitError>() {
+            Some(Self::Network)
+        } else if error.is::<std::io::Error>() {
+            Some(Self::IO)
+        } else {
+            None
+        }
+    }
+
+    fn from_transfer_error(error: &TransferError) -> Self {
+        match error {
+            TransferError::PeerError(message) if message == PEER_REJECTED => Self::PeerRejected,
+            TransferError::PeerError(message) if message == PEER_CANCELLED => Self::Cancelled,
+            TransferError::PeerError(_) => Self::PeerError,
+            TransferError::Wormhole(error) => Self::from_wormhole_error(error),
+            TransferError::TransitConnect(_) | TransferError::Transit(_) => Self::Network,
+            TransferError::IO(_) | TransferError::FilesystemSkew => Self::IO,
+            _ => Self::Protocol,
+        }
+    }
+
+    fn from_wormhole_error(error: &WormholeError) -> Self {
+        match error {
+            error if error.is_scared() => Self::WrongCode,
+            WormholeError::UnclaimedNameplate(_) => Self::WrongCode,
+            WormholeError::InvalidCode(_) | WormholeError::UnknownWordlist(_) => {
+                Self::InvalidArgument
+            },
+            WormholeError::ServerError(error) => Self::from_rendezvous_error(error),
+            WormholeError::ProtocolJson(_) | WormholeError::Protocol(_) | WormholeError::Crypto => {
+                Self::Protocol
+            },
+            _ => Self::Other,
+        }
+    }
+
+    fn from_rendezvous_error(error: &RendezvousError) -> Self {
+        match error {
+            RendezvousError::ProtocolJson(_) | RendezvousError::Protocol(_) => Self::Protocol,
+            _ => Self::Network,
+        }
+    }
+}
+
+struct LastError {
+    code: WormholeErrorCode,
+    message: CString,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+/// Remember an error for `wormhole_last_error` on the current thread
+pub fn set_last_error(code: WormholeErrorCode, message: &str) {
+    let message = crate::to_c_string_lossy(message);
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(LastError { code, message }));
+}
+
+/// Remember an error report, and return its category
+pub fn set_last_report(report: &eyre::Report) -> WormholeErrorCode {
+    let code = WormholeErrorCode::from_report(report);
+    set_last_error(code, &report_message(report));
+    code
+}
+
+/// The report together with its causes, on a single line
+pub fn report_message(report: &eyre::Report) -> String {
+    report
+        .chain()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+pub fn last_error_code() -> WormholeErrorCode {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(WormholeErrorCode::Ok, |last_error| last_error.code)
+    })
+}
+
+/// The pointer stays valid until the next error on the current thread
+pub fn last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |last_error| last_error.message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_error_codes() {
+        let report = |error: TransferError| eyre::Report::new(error).wrap_err("Transfer failed");
+
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::PeerError(
+                "transfer rejected".into()
+            ))),
+            WormholeErrorCode::PeerRejected
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::PeerError(
+                "Task has been cancelled".into()
+            ))),
+            WormholeErrorCode::Cancelled
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::PeerError("Disk full".into()))),
+            WormholeErrorCode::PeerError
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::Wormhole(
+                WormholeError::PakeFailed
+            ))),
+            WormholeErrorCode::WrongCode
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::Wormhole(
+                WormholeError::InvalidCode(magic_wormhole::CodeParseError::TooShort)
+            ))),
+            WormholeErrorCode::InvalidArgument
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&report(TransferError::Checksum)),
+            WormholeErrorCode::Protocol
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&eyre::Report::new(TransitConnectError::Handshake)),
+            WormholeErrorCode::Network
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&eyre::Report::new(std::io::Error::from(
+                std::io::ErrorKind::NotFound
+            ))),
+            WormholeErrorCode::IO
+        );
+        assert_eq!(
+            WormholeErrorCode::from_report(&eyre::eyre!("Something went wrong")),
+            WormholeErrorCode::Other
+        );
+    }
+
+    #[test]
+    fn test_last_error() {
+        std::thread::spawn(|| {
+            assert_eq!(last_error_code(), WormholeErrorCode::Ok);
+            assert!(last_error_message().is_null());
+
+            let report =
+                eyre::Report::new(TransferError::PeerError("transfer rejected".into()))
+                    .wrap_err("Could not send");
+            assert_eq!(set_last_report(&report), WormholeErrorCode::PeerRejected);
+            assert_eq!(last_error_code(), WormholeErrorCode::PeerRejected);
+            assert_eq!(
+                unsafe { CStr::from_ptr(last_error_message()) }.to_str().unwrap(),
+                "Could not send: Something went wrong on the other side: transfer rejected"
+            );
+        })
+        .join()
+        .unwrap();
+    }
+}
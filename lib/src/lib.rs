@@ -1,4 +1,15 @@
+// The exported functions take raw pointers from C, marking them `unsafe` would not change anything for the callers
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+mod callbacks;
+mod completion;
+mod error;
 mod mediator;
+mod memory;
+mod options;
+mod request;
+mod session;
+mod version;
 
 use std::ptr;
 use std::ffi::CStr;
@@ -8,6 +19,17 @@ use std::os::raw::c_char;
 
 use async_std::task;
 use encoding_rs::UTF_8;
+use futures::FutureExt;
+use magic_wormhole::uri::{qr::QrCode, WormholeTransferUri};
+
+use callbacks::{WormholeCallbacks, WormholeOfferFile, WormholeSendManyCallbacks};
+use completion::Completer;
+use error::WormholeErrorCode;
+use memory::{ReceivedFiles, Sink, WormholeBuffer, WormholeDataCallback};
+use options::{Options, WormholeOptions, WormholeSendManyLimits};
+use request::{Request, WormholeAcceptFile};
+use session::{Session, WormholeSessionStatus};
+use version::WormholeAbilities;
 
 // ***********************************************
 // lib:
@@ -15,13 +37,16 @@ use encoding_rs::UTF_8;
 // ***********************************************
 
 /// # 发送文件
+///
+/// Returns the code once the transfer is done, or null on failure. Free it with
+/// `wormhole_string_free`.
 #[no_mangle]
 pub extern "C" fn send_files(
     file_paths: *const *const c_char,
     length: usize,
     new_name: *const c_char,
     code_length: usize,
-) -> *const c_char {
+) -> *mut c_char {
     // # 发送文件
     let paths_slice = unsafe { std::slice::from_raw_parts(file_paths, length) };
     let mut paths_vec = Vec::new();
@@ -51,13 +76,15 @@ pub extern "C" fn send_files(
             return c_code.into_raw();
         },
         Err(error_report) => {
-            println!("{:?}", error_report);
-            return ptr::null();
+            error::set_last_report(&error_report);
+            return ptr::null_mut();
         },
     }
 }
 
 /// # 接受文件
+///
+/// The code may also be a `wormhole-transfer:` link, which can bring its own rendezvous server.
 #[no_mangle]
 pub extern "C" fn receive_files(wormhole_code:*const c_char,save_path: *const c_char)->bool{
     let c_wormhole_code = unsafe { CStr::from_ptr(wormhole_code) };
@@ -74,51 +101,945 @@ pub extern "C" fn receive_files(wormhole_code:*const c_char,save_path: *const c_
 
     let res =  task::block_on(mediator::try_recieve(s_wormhole_code,buf_save_path));
     match res{
-        Ok(res) => {
-            return res;
+        Ok(true) => true,
+        Ok(false) => {
+            error::set_last_error(WormholeErrorCode::Cancelled, "The transfer got cancelled");
+            false
         },
         Err(error_report) => {
-            println!("{:?}", error_report);
-            return false;
+            error::set_last_report(&error_report);
+            false
         },
     }
 
 }
 
+/// # 设置 wss:// 会合服务器的 TLS 参数
+///
+/// All arguments may be null. `ca_cert_pem` contains extra trusted CA certificates,
+/// `pins` are public key pins in the form `sha256/<base64>`, and `client_cert_pem`
+/// together with `client_key_pem` enable mutual TLS. All of them are PEM contents,
+/// not file names. The settings apply to all following `send_files`/`receive_files` calls.
+#[no_mangle]
+pub extern "C" fn set_rendezvous_tls(
+    ca_cert_pem: *const c_char,
+    pins: *const *const c_char,
+    pins_length: usize,
+    client_cert_pem: *const c_char,
+    client_key_pem: *const c_char,
+) -> bool {
+    let pins_vec = if pins.is_null() {
+        Vec::new()
+    } else {
+        let pins_slice = unsafe { std::slice::from_raw_parts(pins, pins_length) };
+        pins_slice.iter().filter_map(|&pin| optional_string(pin)).collect()
+    };
 
-// ***********************************************
-// lib:
-// C/C++ Can Call Them
-// ***********************************************
+    let res = mediator::set_rendezvous_tls(
+        optional_string(ca_cert_pem),
+        pins_vec,
+        optional_string(client_cert_pem),
+        optional_string(client_key_pem),
+    );
+    match res {
+        Ok(()) => true,
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            false
+        },
+    }
+}
 
+/// # 在后台发送文件
+///
+/// Like `send_files`, but returns as soon as the code has been allocated, while the transfer
+/// continues in the background. Returns null on failure. Otherwise, get the code with
+/// `wormhole_session_code` and free the session with `wormhole_session_free` once you are done.
+/// `options` and `callbacks` may be null, the structs get copied.
+#[no_mangle]
+pub extern "C" fn wormhole_send_files(
+    file_paths: *const *const c_char,
+    length: usize,
+    new_name: *const c_char,
+    code_length: usize,
+    options: *const WormholeOptions,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(options) = parse_options(options) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let paths_vec: Vec<PathBuf> = if file_paths.is_null() {
+        Vec::new()
+    } else {
+        let paths_slice = unsafe { std::slice::from_raw_parts(file_paths, length) };
+        paths_slice
+            .iter()
+            .filter_map(|&path| optional_string(path))
+            .map(PathBuf::from)
+            .collect()
+    };
+    if paths_vec.is_empty() {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "No files to send");
+        return ptr::null_mut();
+    }
+    let file_name_str = optional_string(new_name);
 
+    start_send(
+        mediator::make_send_offer(paths_vec, file_name_str),
+        code_length,
+        options,
+        callbacks,
+    )
+}
 
+/// # 在后台发送内存中的数据
+///
+/// Like `wormhole_send_files`, but sends `length` bytes from `data` as a file called `name`.
+/// The data gets copied, so the buffer may be freed right after the call.
+#[no_mangle]
+pub extern "C" fn wormhole_send_bytes(
+    name: *const c_char,
+    data: *const u8,
+    length: usize,
+    code_length: usize,
+    options: *const WormholeOptions,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(options) = parse_options(options) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let Some(name) = optional_string(name) else {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The name must not be null");
+        return ptr::null_mut();
+    };
+    let data = if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, length) }
+    };
 
-// ***********************************************
-// Cpp Call Rust Test
-// Begin
-// ***********************************************
+    let offer = magic_wormhole::transfer::OfferSend::new_file_bytes(name, data);
+    start_send(async { Ok(offer) }, code_length, options, callbacks)
+}
+
+/// Allocate a code and send `offer` in the background
+fn start_send(
+    offer: impl std::future::Future<
+        Output = color_eyre::eyre::Result<magic_wormhole::transfer::OfferSend>,
+    >,
+    code_length: usize,
+    options: Options,
+    callbacks: WormholeCallbacks,
+) -> *mut Session {
+    let res = task::block_on(async {
+        let offer = offer.await?;
+        let mailbox_connection = mediator::create_send_mailbox(&options, code_length).await?;
+        color_eyre::eyre::Result::<_>::Ok((offer, mailbox_connection))
+    });
+    match res {
+        Ok((offer, mailbox_connection)) => {
+            let code = mailbox_connection.code.0.clone();
+            callbacks.code(&code);
+            let session = Session::spawn(code, move |cancel| async move {
+                mediator::send(mailbox_connection, offer, &options, callbacks, move || {
+                    cancel.clone().boxed()
+                })
+                .await
+            });
+            Box::into_raw(Box::new(session))
+        },
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            ptr::null_mut()
+        },
+    }
+}
+
+/// # 在后台向多人发送文件
+///
+/// Like `wormhole_send_files`, but keeps sending the files to everyone who enters the code, until
+/// one of the `limits` is reached or the session gets cancelled. Every recipient gives an
+/// attacker another try at guessing the code, so consider a longer `code_length`. The session
+/// finishes once the last transfer is over, the outcome of each transfer goes to the callbacks.
+/// `limits`, `options` and `callbacks` may be null, the structs get copied.
+#[no_mangle]
+pub extern "C" fn wormhole_send_many_files(
+    file_paths: *const *const c_char,
+    length: usize,
+    new_name: *const c_char,
+    code_length: usize,
+    limits: *const WormholeSendManyLimits,
+    options: *const WormholeOptions,
+    callbacks: *const WormholeSendManyCallbacks,
+) -> *mut Session {
+    let Some(options) = parse_options(options) else {
+        return ptr::null_mut();
+    };
+    let limits = WormholeSendManyLimits::from_ptr(limits);
+    let callbacks = WormholeSendManyCallbacks::from_ptr(callbacks);
+    let paths_vec: Vec<PathBuf> = if file_paths.is_null() {
+        Vec::new()
+    } else {
+        let paths_slice = unsafe { std::slice::from_raw_parts(file_paths, length) };
+        paths_slice
+            .iter()
+            .filter_map(|&path| optional_string(path))
+            .map(PathBuf::from)
+            .collect()
+    };
+    if paths_vec.is_empty() {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "No files to send");
+        return ptr::null_mut();
+    }
+    let file_name_str = optional_string(new_name);
+
+    let res = task::block_on(async {
+        /* Check the files before allocating a code, the first recipient gets this offer */
+        let offer = mediator::make_send_offer(paths_vec.clone(), file_name_str.clone()).await?;
+        let mailbox_connection = mediator::create_send_mailbox(&options, code_length).await?;
+        color_eyre::eyre::Result::<_>::Ok((offer, mailbox_connection))
+    });
+    match res {
+        Ok((offer, mailbox_connection)) => {
+            let code = mailbox_connection.code.0.clone();
+            callbacks.code(&code);
+            let mut first_offer = Some(offer);
+            let make_offer = move || {
+                let first_offer = first_offer.take();
+                let (paths_vec, file_name_str) = (paths_vec.clone(), file_name_str.clone());
+                async move {
+                    match first_offer {
+                        Some(offer) => Ok(offer),
+                        None => mediator::make_send_offer(paths_vec, file_name_str)
+                            .await
+                            .map_err(std::io::Error::other),
+                    }
+                }
+            };
+            let session = Session::spawn(code, move |cancel| async move {
+                mediator::send_many(mailbox_connection, make_offer, &options, limits, callbacks, move || {
+                    cancel.clone().boxed()
+                })
+                .await
+            });
+            Box::into_raw(Box::new(session))
+        },
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            ptr::null_mut()
+        },
+    }
+}
+
+/// # 在后台接收文件
+///
+/// Like `receive_files`, but returns right away while the transfer continues in the background.
+/// Free the session with `wormhole_session_free` once you are done. `options` and `callbacks`
+/// may be null, the structs get copied.
+#[no_mangle]
+pub extern "C" fn wormhole_receive_files(
+    wormhole_code: *const c_char,
+    save_path: *const c_char,
+    options: *const WormholeOptions,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(mut options) = parse_options(options) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let (Some(code), Some(save_path)) = (optional_string(wormhole_code), optional_string(save_path))
+    else {
+        error::set_last_error(
+            WormholeErrorCode::InvalidArgument,
+            "The code and the save path must not be null",
+        );
+        return ptr::null_mut();
+    };
+    let Some(code) = parse_code(&mut options, &code) else {
+        return ptr::null_mut();
+    };
+
+    let session = Session::spawn(code.to_string(), move |cancel| async move {
+        mediator::receive(
+            code,
+            PathBuf::from(save_path),
+            &options,
+            callbacks,
+            move || cancel.clone().boxed(),
+        )
+        .await
+        .map(|_| ())
+    });
+    Box::into_raw(Box::new(session))
+}
+
+/// # 获取对方的报价
+///
+/// Connects to the sender and blocks until it offered its files, without receiving anything yet.
+/// Gives up after `timeout_seconds`, or waits as long as it takes if that is 0. Returns null on
+/// failure, with `Cancelled` as error after the timeout. Otherwise, look at the offer with `wormhole_request_file_count` and
+/// `wormhole_request_file`, answer it with `wormhole_request_accept_all`, `wormhole_request_accept`
+/// or `wormhole_request_reject`, and free it with `wormhole_request_free` in any case.
+/// `options` may be null. The code may also be a `wormhole-transfer:` link, like for
+/// `receive_files`.
+#[no_mangle]
+pub extern "C" fn wormhole_receive_request(
+    wormhole_code: *const c_char,
+    options: *const WormholeOptions,
+    timeout_seconds: u64,
+) -> *mut Request {
+    let Some(mut options) = parse_options(options) else {
+        return ptr::null_mut();
+    };
+    let Some(code) = optional_string(wormhole_code) else {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The code must not be null");
+        return ptr::null_mut();
+    };
+    let Some(code) = parse_code(&mut options, &code) else {
+        return ptr::null_mut();
+    };
+
+    /* Connecting and waiting for the offer share the same deadline */
+    let deadline = (timeout_seconds != 0)
+        .then(|| std::time::Instant::now() + std::time::Duration::from_secs(timeout_seconds));
+    let timeout = move || match deadline {
+        Some(deadline) => {
+            task::sleep(deadline.saturating_duration_since(std::time::Instant::now())).boxed()
+        },
+        None => futures::future::pending().boxed(),
+    };
+    let res = task::block_on(async {
+        match mediator::request(code.clone(), &options, timeout).await? {
+            Some(req @ magic_wormhole::transfer::ReceiveRequest::Text(_)) => {
+                mediator::reject_not_files(req).await
+            },
+            req => Ok(req),
+        }
+    });
+    match res {
+        Ok(Some(req)) => Box::into_raw(Box::new(Request::new(code.to_string(), req))),
+        Ok(None) => {
+            error::set_last_error(WormholeErrorCode::Cancelled, "Timed out waiting for the offer");
+            ptr::null_mut()
+        },
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            ptr::null_mut()
+        },
+    }
+}
+
+/// # 报价中的文件数量
+///
+/// Returns 0 if `request` is null.
+#[no_mangle]
+pub extern "C" fn wormhole_request_file_count(request: *const Request) -> usize {
+    request_ref(request).map_or(0, |request| request.files().len())
+}
+
+/// # 获取报价中的文件
+///
+/// The path belongs to the request and is valid until `wormhole_request_free`. Out of range
+/// indices and a null `request` return a null path.
+#[no_mangle]
+pub extern "C" fn wormhole_request_file(request: *const Request, index: usize) -> WormholeOfferFile {
+    match request_ref(request).and_then(|request| request.files().get(index)) {
+        Some((_, path, size)) => WormholeOfferFile {
+            path: path.as_ptr(),
+            size: *size,
+        },
+        None => WormholeOfferFile {
+            path: ptr::null(),
+            size: 0,
+        },
+    }
+}
+
+/// # 接受全部文件
+///
+/// Receives everything into `save_path` in the background, like `wormhole_receive_files`.
+/// Returns null on failure, including when the request has already been answered.
+#[no_mangle]
+pub extern "C" fn wormhole_request_accept_all(
+    request: *const Request,
+    save_path: *const c_char,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(request) = request_ref(request) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let Some(save_path) = optional_string(save_path) else {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The save path must not be null");
+        return ptr::null_mut();
+    };
+    let Some(req) = take_request(request) else {
+        return ptr::null_mut();
+    };
+
+    let session = Session::spawn(request.code().to_owned(), move |cancel| async move {
+        mediator::accept_all(req, &PathBuf::from(save_path), callbacks, move || {
+            cancel.clone().boxed()
+        })
+        .await
+    });
+    Box::into_raw(Box::new(session))
+}
+
+/// # 接受部分文件
+///
+/// Receives only the given files in the background, each one into its own destination.
+/// Missing directories get created, existing files get overwritten. Accepting no files at
+/// all rejects the offer. Returns null on failure, including when the request has already
+/// been answered or a path is not part of the offer.
+#[no_mangle]
+pub extern "C" fn wormhole_request_accept(
+    request: *const Request,
+    files: *const WormholeAcceptFile,
+    length: usize,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(request) = request_ref(request) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let files_slice = if files.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(files, length) }
+    };
+
+    let mut destinations = std::collections::BTreeMap::new();
+    for file in files_slice {
+        let (Some(path), Some(destination)) =
+            (optional_string(file.path), optional_string(file.destination))
+        else {
+            error::set_last_error(
+                WormholeErrorCode::InvalidArgument,
+                "The paths of the accepted files must not be null",
+            );
+            return ptr::null_mut();
+        };
+        if !request.contains(&path) {
+            error::set_last_error(
+                WormholeErrorCode::InvalidArgument,
+                &format!("{path} is not part of the offer"),
+            );
+            return ptr::null_mut();
+        }
+        destinations.insert(path, PathBuf::from(destination));
+    }
+    let Some(req) = take_request(request) else {
+        return ptr::null_mut();
+    };
+
+    let session = Session::spawn(request.code().to_owned(), move |cancel| {
+        mediator::accept_files(req, destinations, callbacks, move || cancel.clone().boxed())
+    });
+    Box::into_raw(Box::new(session))
+}
+
+/// # 接收到内存
+///
+/// Receives everything in the background without touching the disk. If `on_data` is given,
+/// every received chunk gets passed to it together with the `user_data` of `callbacks`, and
+/// nothing is kept. Otherwise, take the files out with `wormhole_session_take_file` once the
+/// session finished. Returns null on failure, including when the request has already been
+/// answered.
+#[no_mangle]
+pub extern "C" fn wormhole_request_accept_memory(
+    request: *const Request,
+    on_data: WormholeDataCallback,
+    callbacks: *const WormholeCallbacks,
+) -> *mut Session {
+    let Some(request) = request_ref(request) else {
+        return ptr::null_mut();
+    };
+    let callbacks = WormholeCallbacks::from_ptr(callbacks);
+    let Some(req) = take_request(request) else {
+        return ptr::null_mut();
+    };
+
+    let received = ReceivedFiles::default();
+    let sink = match on_data {
+        Some(on_data) => Sink::Callback(on_data, callbacks),
+        None => Sink::Memory(received.clone()),
+    };
+    let session = Session::spawn(request.code().to_owned(), move |cancel| {
+        mediator::accept_memory(req, sink, callbacks, move || cancel.clone().boxed())
+    });
+    Box::into_raw(Box::new(session.with_received(received)))
+}
+
+/// # 拒绝报价
+///
+/// Tells the sender that we don't want its files. Returns `false` on failure.
+#[no_mangle]
+pub extern "C" fn wormhole_request_reject(request: *const Request) -> bool {
+    let Some(request) = request_ref(request) else {
+        return false;
+    };
+    let Some(req) = take_request(request) else {
+        return false;
+    };
+    match task::block_on(mediator::reject(req)) {
+        Ok(()) => true,
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            false
+        },
+    }
+}
+
+/// # 释放报价
+///
+/// Frees the request. An offer that has not been answered gets dropped without telling the
+/// sender, use `wormhole_request_reject` for that. The request must not be used afterwards,
+/// but sessions started from it keep running.
+#[no_mangle]
+pub extern "C" fn wormhole_request_free(request: *mut Request) {
+    if !request.is_null() {
+        drop(unsafe { Box::from_raw(request) });
+    }
+}
+
+fn take_request(request: &Request) -> Option<magic_wormhole::transfer::ReceiveRequest> {
+    let req = request.take();
+    if req.is_none() {
+        error::set_last_error(
+            WormholeErrorCode::InvalidArgument,
+            "The request has already been answered",
+        );
+    }
+    req
+}
+
+fn request_ref<'a>(request: *const Request) -> Option<&'a Request> {
+    let request = unsafe { request.as_ref() };
+    if request.is_none() {
+        error::set_last_error(
+            WormholeErrorCode::InvalidArgument,
+            "The request must not be null",
+        );
+    }
+    request
+}
+
+fn session_ref<'a>(session: *const Session) -> Option<&'a Session> {
+    let session = unsafe { session.as_ref() };
+    if session.is_none() {
+        error::set_last_error(
+            WormholeErrorCode::InvalidArgument,
+            "The session must not be null",
+        );
+    }
+    session
+}
+
+/// # 获取会话的代码
+///
+/// The returned string belongs to the session and is valid until `wormhole_session_free`.
+/// Returns null if `session` is null.
+#[no_mangle]
+pub extern "C" fn wormhole_session_code(session: *const Session) -> *const c_char {
+    session_ref(session).map_or(ptr::null(), |session| session.code().as_ptr())
+}
+
+/// # 查询会话状态
+///
+/// If the session failed, its error becomes the last error of the calling thread. A null
+/// `session` counts as failed.
+#[no_mangle]
+pub extern "C" fn wormhole_session_status(session: *const Session) -> WormholeSessionStatus {
+    session_ref(session).map_or(WormholeSessionStatus::Failed, |session| {
+        session_result(session, session.status())
+    })
+}
+
+/// # 等待会话结束
+///
+/// Blocks until the transfer is over, and returns how it ended. If the session failed,
+/// its error becomes the last error of the calling thread. A null `session` counts as failed.
+#[no_mangle]
+pub extern "C" fn wormhole_session_wait(session: *const Session) -> WormholeSessionStatus {
+    session_ref(session).map_or(WormholeSessionStatus::Failed, |session| {
+        session_result(session, session.wait())
+    })
+}
+
+/// # 取消会话
+///
+/// Tells the peer that we are stopping and returns immediately. Use `wormhole_session_wait`
+/// to wait until that has happened. Does nothing if the transfer is already over.
 #[no_mangle]
-pub extern "C" fn add(a: i32, b: i32) -> i32 {
-    a + b
+pub extern "C" fn wormhole_session_cancel(session: *const Session) {
+    if let Some(session) = session_ref(session) {
+        session.cancel();
+    }
+}
+
+/// # 释放会话
+///
+/// Cancels the transfer if it is still running. The session must not be used afterwards.
+#[no_mangle]
+pub extern "C" fn wormhole_session_free(session: *mut Session) {
+    if !session.is_null() {
+        drop(unsafe { Box::from_raw(session) });
+    }
+}
+
+/// # 取出接收到内存的文件
+///
+/// Takes out the file at `path`, as given by `wormhole_request_file`, after receiving with
+/// `wormhole_request_accept_memory`. The buffer belongs to the caller and must be freed with
+/// `wormhole_buffer_free`. Returns a null buffer if there is no such file, or it has already
+/// been taken out.
+#[no_mangle]
+pub extern "C" fn wormhole_session_take_file(
+    session: *const Session,
+    path: *const c_char,
+) -> WormholeBuffer {
+    let Some(session) = session_ref(session) else {
+        return WormholeBuffer::null();
+    };
+    optional_string(path)
+        .and_then(|path| session.take_file(&path))
+        .map_or_else(WormholeBuffer::null, WormholeBuffer::from_vec)
+}
+
+/// # 释放缓冲区
+///
+/// Frees buffers returned by the library. A null buffer is fine.
+#[no_mangle]
+pub extern "C" fn wormhole_buffer_free(buffer: WormholeBuffer) {
+    drop(unsafe { buffer.into_vec() });
 }
 
+/// # 生成 SVG 二维码
+///
+/// Renders the `wormhole-transfer:` link for `code` as SVG image, with `module_size` pixels per
+/// QR module. A custom rendezvous server from `options` becomes part of the link, `options` may
+/// be null. Returns null on failure, free the string with `wormhole_string_free`.
 #[no_mangle]
-pub extern "C" fn hello() {
-    println!("Hello from Rust!");
+pub extern "C" fn wormhole_qr_svg(
+    code: *const c_char,
+    options: *const WormholeOptions,
+    module_size: u32,
+) -> *mut c_char {
+    match qr_code(code, options) {
+        Some(qr) => CString::new(qr.to_svg(module_size)).unwrap().into_raw(),
+        None => ptr::null_mut(),
+    }
+}
+
+/// # 生成 PNG 二维码
+///
+/// Like `wormhole_qr_svg`, but as grayscale PNG image. Returns a null buffer on failure, free the
+/// buffer with `wormhole_buffer_free`.
+#[no_mangle]
+pub extern "C" fn wormhole_qr_png(
+    code: *const c_char,
+    options: *const WormholeOptions,
+    module_size: u32,
+) -> WormholeBuffer {
+    let Some(qr) = qr_code(code, options) else {
+        return WormholeBuffer::null();
+    };
+    match qr.to_png(module_size) {
+        Ok(png) => WormholeBuffer::from_vec(png),
+        Err(err) => {
+            error::set_last_report(&color_eyre::eyre::Report::new(err));
+            WormholeBuffer::null()
+        },
+    }
+}
+
+/// # 生成终端二维码
+///
+/// Like `wormhole_qr_svg`, but drawn with Unicode half blocks, one line per two rows of modules.
+/// The blocks are the dark modules, set `invert` for light text on a dark background.
+#[no_mangle]
+pub extern "C" fn wormhole_qr_text(
+    code: *const c_char,
+    options: *const WormholeOptions,
+    invert: bool,
+) -> *mut c_char {
+    match qr_code(code, options) {
+        Some(qr) => CString::new(qr.to_terminal_string(invert)).unwrap().into_raw(),
+        None => ptr::null_mut(),
+    }
+}
+
+/// # 创建代码补全器
+///
+/// Completes codes while the user types them, for example in a code entry field. The password
+/// gets completed from the wordlist right away, `code_length` is the number of words to expect.
+/// The nameplate only gets completed after `wormhole_completer_refresh`. `options` may be null.
+/// Returns null on failure, free the completer with `wormhole_completer_free`. A completer must
+/// not be used from several threads at the same time.
+#[no_mangle]
+pub extern "C" fn wormhole_completer_new(
+    options: *const WormholeOptions,
+    code_length: usize,
+) -> *mut Completer {
+    match parse_options(options) {
+        Some(options) => Box::into_raw(Box::new(Completer::new(options.app_config(), code_length))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// # 刷新名牌列表
+///
+/// Blocks while fetching the nameplates that are currently in use from the rendezvous server.
+/// Call it again whenever the list might be outdated, for example when the entry field gets
+/// focused. Returns false on failure, the completer keeps working with the old list then.
+#[no_mangle]
+pub extern "C" fn wormhole_completer_refresh(completer: *mut Completer) -> bool {
+    let Some(completer) = completer_mut(completer) else {
+        return false;
+    };
+    match task::block_on(completer.refresh()) {
+        Ok(()) => true,
+        Err(error_report) => {
+            error::set_last_report(&error_report);
+            false
+        },
+    }
+}
+
+/// # 补全代码
+///
+/// Completes the partially entered `prefix` and returns the number of completions, which are
+/// full codes up to the completed part. Get them with `wormhole_completer_get`. Returns 0 if
+/// `completer` or `prefix` is null.
+#[no_mangle]
+pub extern "C" fn wormhole_completer_complete(
+    completer: *mut Completer,
+    prefix: *const c_char,
+) -> usize {
+    let Some(completer) = completer_mut(completer) else {
+        return 0;
+    };
+    let Some(prefix) = optional_string(prefix) else {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The prefix must not be null");
+        return 0;
+    };
+    completer.complete(&prefix).len()
+}
+
+/// # 获取补全结果
+///
+/// The string belongs to the completer and is valid until the next `wormhole_completer_complete`
+/// or `wormhole_completer_free`. Out of range indices and a null `completer` return null.
+#[no_mangle]
+pub extern "C" fn wormhole_completer_get(completer: *const Completer, index: usize) -> *const c_char {
+    let completer = unsafe { completer.as_ref() };
+    if completer.is_none() {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The completer must not be null");
+    }
+    completer
+        .and_then(|completer| completer.completions().get(index))
+        .map_or(ptr::null(), |completion| completion.as_ptr())
+}
+
+/// # 释放代码补全器
+///
+/// A null completer is fine.
+#[no_mangle]
+pub extern "C" fn wormhole_completer_free(completer: *mut Completer) {
+    if !completer.is_null() {
+        drop(unsafe { Box::from_raw(completer) });
+    }
+}
+
+fn completer_mut<'a>(completer: *mut Completer) -> Option<&'a mut Completer> {
+    let completer = unsafe { completer.as_mut() };
+    if completer.is_none() {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The completer must not be null");
+    }
+    completer
+}
+
+/// # 计算词表的熵
+///
+/// Returns how many bits of entropy each word of a code adds with the wordlist of `options`,
+/// for telling users how long their codes should be. `options` may be null for the PGP word
+/// list. Returns 0 on failure.
+#[no_mangle]
+pub extern "C" fn wormhole_wordlist_entropy(options: *const WormholeOptions) -> f64 {
+    match parse_options(options) {
+        Some(options) => options.wordlist().entropy_per_word(),
+        None => 0.0,
+    }
+}
+
+/// # 获取最近的错误类型
+///
+/// Returns the category of the last error on the calling thread, or `Ok` if there was none.
+/// Successful calls don't reset it, so only check it after a call failed.
+#[no_mangle]
+pub extern "C" fn wormhole_last_error() -> WormholeErrorCode {
+    error::last_error_code()
+}
+
+/// # 获取最近的错误信息
+///
+/// Returns a human readable description of the last error on the calling thread, or null if
+/// there was none. The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn wormhole_last_error_message() -> *const c_char {
+    error::last_error_message()
+}
+
+/// # 释放字符串
+///
+/// Frees strings that the library returned as `char *`. Null is fine.
+#[no_mangle]
+pub extern "C" fn wormhole_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+/// # 获取库的版本
+///
+/// The returned string is static and must not be freed.
+#[no_mangle]
+pub extern "C" fn wormhole_version() -> *const c_char {
+    version::version()
+}
+
+/// # 查询支持的协议功能
+#[no_mangle]
+pub extern "C" fn wormhole_abilities() -> WormholeAbilities {
+    version::abilities()
+}
+
+fn parse_options(options: *const WormholeOptions) -> Option<Options> {
+    match Options::from_ptr(options) {
+        Ok(options) => Some(options),
+        Err(error_report) => {
+            error::set_last_error(
+                WormholeErrorCode::InvalidArgument,
+                &error::report_message(&error_report),
+            );
+            None
+        },
+    }
+}
+
+fn qr_code(code: *const c_char, options: *const WormholeOptions) -> Option<QrCode> {
+    let options = parse_options(options)?;
+    let Some(code) = optional_string(code) else {
+        error::set_last_error(WormholeErrorCode::InvalidArgument, "The code must not be null");
+        return None;
+    };
+    let code = match code.parse::<magic_wormhole::Code>() {
+        Ok(code) => code,
+        Err(err) => {
+            error::set_last_error(WormholeErrorCode::InvalidArgument, &err.to_string());
+            return None;
+        },
+    };
+    let uri = WormholeTransferUri {
+        code,
+        rendezvous_server: options.rendezvous_url().cloned(),
+        is_leader: false,
+    };
+    match QrCode::new(&uri) {
+        Ok(qr) => Some(qr),
+        Err(err) => {
+            error::set_last_error(WormholeErrorCode::InvalidArgument, &err.to_string());
+            None
+        },
+    }
+}
+
+fn parse_code(options: &mut Options, code: &str) -> Option<magic_wormhole::Code> {
+    match options.parse_code(code) {
+        Ok(code) => Some(code),
+        Err(error_report) => {
+            error::set_last_error(
+                WormholeErrorCode::InvalidArgument,
+                &error::report_message(&error_report),
+            );
+            None
+        },
+    }
+}
+
+fn session_result(session: &Session, status: WormholeSessionStatus) -> WormholeSessionStatus {
+    if let Some((code, message)) = session.error() {
+        error::set_last_error(code, &message);
+    }
+    status
+}
+
+/// Received data like file names and error messages might contain anything, so drop null bytes
+/// instead of panicking on them
+fn to_c_string_lossy(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).unwrap()
+}
+
+fn optional_string(c_string: *const c_char) -> Option<String> {
+    if c_string.is_null() {
+        return None;
+    }
+    let c_str = unsafe { CStr::from_ptr(c_string) };
+    let (decoded, _, _) = UTF_8.decode(c_str.to_bytes());
+    Some(decoded.to_string())
 }
 
 // ***********************************************
-// Cpp Call Rust Test
-// End
+// lib:
+// C/C++ Can Call Them
 // ***********************************************
 
 
 
+
 // ***********************************************
 // Test Case
 // ***********************************************
 
+/// The default options, with a custom rendezvous server unless `rendezvous_url` is null
+#[cfg(test)]
+fn test_options(rendezvous_url: *const c_char) -> WormholeOptions
+{
+    WormholeOptions {
+        size: std::mem::size_of::<WormholeOptions>(),
+        rendezvous_url,
+        relay_urls: ptr::null(),
+        relay_urls_length: 0,
+        app_id: ptr::null(),
+        force_direct: false,
+        force_relay: false,
+        wordlist: ptr::null(),
+    }
+}
+
+/// Run `test` against a fresh in-process rendezvous server, passing it the server's URL
+#[cfg(test)]
+fn with_local_server(test: fn(&CStr))
+{
+    use magic_wormhole::rendezvous::server::MailboxServer;
+
+    /* Blocking on a whole transfer takes more stack than test threads have in debug builds */
+    std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(move || {
+            let server = task::block_on(MailboxServer::bind("127.0.0.1:0")).unwrap();
+            let rendezvous_url = CString::new(server.url()).unwrap();
+            task::spawn(server.run());
+            test(&rendezvous_url)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
 #[test]
 fn test_send()
 {
@@ -146,4 +1067,387 @@ fn test_send()
     let c_wormhole_code: *const c_char = send_files(c_ptrs_ptr,1, new_name,2);
 
     println!("Pointer to pointers: {:?}", c_wormhole_code);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_send_session_missing_file()
+{
+    let file_path = CString::new("./does-not-exist").unwrap();
+    let file_paths = [file_path.as_ptr()];
+
+    let session = wormhole_send_files(file_paths.as_ptr(), 1, ptr::null(), 2, ptr::null(), ptr::null());
+    assert!(session.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::IO);
+    let message = unsafe { CStr::from_ptr(wormhole_last_error_message()) };
+    assert_eq!(message.to_str().unwrap(), "./does-not-exist does not exist");
+}
+
+#[test]
+fn test_null_session()
+{
+    assert!(wormhole_session_code(ptr::null()).is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    assert_eq!(wormhole_session_status(ptr::null()), WormholeSessionStatus::Failed);
+    assert_eq!(wormhole_session_wait(ptr::null()), WormholeSessionStatus::Failed);
+    wormhole_session_cancel(ptr::null());
+    let path = CString::new("file").unwrap();
+    assert!(wormhole_session_take_file(ptr::null(), path.as_ptr()).data.is_null());
+    wormhole_session_free(ptr::null_mut());
+}
+
+#[test]
+fn test_null_request()
+{
+    assert_eq!(wormhole_request_file_count(ptr::null()), 0);
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    assert!(wormhole_request_file(ptr::null(), 0).path.is_null());
+    let save_path = CString::new(".").unwrap();
+    assert!(wormhole_request_accept_all(ptr::null(), save_path.as_ptr(), ptr::null()).is_null());
+    assert!(wormhole_request_accept(ptr::null(), ptr::null(), 0, ptr::null()).is_null());
+    assert!(wormhole_request_accept_memory(ptr::null(), None, ptr::null()).is_null());
+    assert!(!wormhole_request_reject(ptr::null()));
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    wormhole_request_free(ptr::null_mut());
+}
+
+#[test]
+fn test_receive_request_without_code()
+{
+    let request = wormhole_receive_request(ptr::null(), ptr::null(), 0);
+    assert!(request.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    wormhole_request_free(request);
+}
+
+#[test]
+fn test_receive_request_leader_link()
+{
+    let code = CString::new("wormhole-transfer:1-foo-bar?role=leader").unwrap();
+    let request = wormhole_receive_request(code.as_ptr(), ptr::null(), 0);
+    assert!(request.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_receive_request_invalid_options()
+{
+    let code = CString::new("1-foo-bar").unwrap();
+    let options = WormholeOptions {
+        force_direct: true,
+        force_relay: true,
+        ..test_options(ptr::null())
+    };
+    let request = wormhole_receive_request(code.as_ptr(), &options, 0);
+    assert!(request.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_receive_request_timeout()
+{
+    with_local_server(receive_request_timeout);
+}
+
+#[cfg(test)]
+fn receive_request_timeout(rendezvous_url: &CStr)
+{
+    let options = test_options(rendezvous_url.as_ptr());
+
+    /* Nobody is sending anything with this code */
+    let code = CString::new("5-foo-bar").unwrap();
+    let request = wormhole_receive_request(code.as_ptr(), &options, 1);
+    assert!(request.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::Cancelled);
+}
+
+#[test]
+fn test_send_bytes_without_name()
+{
+    let data = b"key = 1";
+    let session =
+        wormhole_send_bytes(ptr::null(), data.as_ptr(), data.len(), 2, ptr::null(), ptr::null());
+    assert!(session.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_transfer_in_memory()
+{
+    with_local_server(transfer_in_memory);
+}
+
+#[cfg(test)]
+fn transfer_in_memory(rendezvous_url: &CStr)
+{
+    let options = WormholeOptions {
+        force_direct: true,
+        ..test_options(rendezvous_url.as_ptr())
+    };
+
+    let name = CString::new("config.toml").unwrap();
+    let data = b"key = 1";
+    let send_session =
+        wormhole_send_bytes(name.as_ptr(), data.as_ptr(), data.len(), 2, &options, ptr::null());
+    assert!(!send_session.is_null());
+
+    let request = wormhole_receive_request(wormhole_session_code(send_session), &options, 0);
+    assert!(!request.is_null());
+    assert_eq!(wormhole_request_file_count(request), 1);
+    let file = wormhole_request_file(request, 0);
+    assert_eq!(unsafe { CStr::from_ptr(file.path) }, name.as_c_str());
+    assert_eq!(file.size, data.len() as u64);
+
+    let receive_session = wormhole_request_accept_memory(request, None, ptr::null());
+    assert!(!receive_session.is_null());
+    assert_eq!(wormhole_session_wait(receive_session), WormholeSessionStatus::Finished);
+    assert_eq!(wormhole_session_wait(send_session), WormholeSessionStatus::Finished);
+
+    let buffer = wormhole_session_take_file(receive_session, name.as_ptr());
+    assert_eq!(unsafe { std::slice::from_raw_parts(buffer.data, buffer.length) }, data);
+    wormhole_buffer_free(buffer);
+    assert!(wormhole_session_take_file(receive_session, name.as_ptr()).data.is_null());
+
+    wormhole_request_free(request);
+    wormhole_session_free(receive_session);
+    wormhole_session_free(send_session);
+}
+
+#[test]
+fn test_send_many_files()
+{
+    with_local_server(send_many_files);
+}
+
+#[cfg(test)]
+extern "C" fn on_recipient_finished(
+    user_data: *mut std::ffi::c_void,
+    recipient: u64,
+    error: WormholeErrorCode,
+    message: *const c_char,
+) {
+    let events = unsafe { &*(user_data as *const std::sync::Mutex<Vec<String>>) };
+    assert_eq!(error == WormholeErrorCode::Ok, message.is_null());
+    events.lock().unwrap().push(format!("finished {recipient} {error:?}"));
+}
+
+#[cfg(test)]
+fn send_many_files(rendezvous_url: &CStr)
+{
+    let options = WormholeOptions {
+        force_direct: true,
+        ..test_options(rendezvous_url.as_ptr())
+    };
+
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), b"key = 1").unwrap();
+    let file_path = CString::new(file.path().to_str().unwrap()).unwrap();
+    let file_paths = [file_path.as_ptr()];
+    let name = CString::new("config.toml").unwrap();
+
+    let events = std::sync::Mutex::new(Vec::<String>::new());
+    let callbacks = WormholeSendManyCallbacks {
+        user_data: &events as *const _ as *mut std::ffi::c_void,
+        on_recipient_finished: Some(on_recipient_finished),
+        ..WormholeSendManyCallbacks::default()
+    };
+    let limits = WormholeSendManyLimits {
+        max_recipients: 2,
+        timeout_seconds: 0,
+    };
+    let send_session = wormhole_send_many_files(
+        file_paths.as_ptr(), 1, name.as_ptr(), 2, &limits, &options, &callbacks,
+    );
+    assert!(!send_session.is_null());
+
+    for _ in 0..2 {
+        let request = wormhole_receive_request(wormhole_session_code(send_session), &options, 0);
+        assert!(!request.is_null());
+        let receive_session = wormhole_request_accept_memory(request, None, ptr::null());
+        assert_eq!(wormhole_session_wait(receive_session), WormholeSessionStatus::Finished);
+        let buffer = wormhole_session_take_file(receive_session, name.as_ptr());
+        assert_eq!(unsafe { std::slice::from_raw_parts(buffer.data, buffer.length) }, b"key = 1");
+        wormhole_buffer_free(buffer);
+        wormhole_request_free(request);
+        wormhole_session_free(receive_session);
+    }
+
+    /* The limit has been reached, so the session ends without waiting for the timeout */
+    assert_eq!(wormhole_session_wait(send_session), WormholeSessionStatus::Finished);
+    let mut events = events.lock().unwrap().clone();
+    events.sort();
+    assert_eq!(events, ["finished 0 Ok", "finished 1 Ok"]);
+    wormhole_session_free(send_session);
+}
+
+#[test]
+fn test_send_files_without_files()
+{
+    let session = wormhole_send_files(ptr::null(), 0, ptr::null(), 2, ptr::null(), ptr::null());
+    assert!(session.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_send_many_files_without_files()
+{
+    let session = wormhole_send_many_files(
+        ptr::null(), 0, ptr::null(), 2, ptr::null(), ptr::null(), ptr::null(),
+    );
+    assert!(session.is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_version()
+{
+    let version = unsafe { CStr::from_ptr(wormhole_version()) };
+    assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+
+    let abilities = wormhole_abilities();
+    assert!(abilities.direct_tcp_v1 && abilities.relay_v1);
+
+    wormhole_string_free(CString::new("1-foo-bar").unwrap().into_raw());
+    wormhole_string_free(ptr::null_mut());
+}
+
+#[test]
+fn test_header_declares_all_exports()
+{
+    let header = include_str!("../wormhole.h");
+    let exports = include_str!("lib.rs")
+        .lines()
+        .filter_map(|line| line.strip_prefix("pub extern \"C\" fn "))
+        .map(|line| line.split('(').next().unwrap());
+    for export in exports {
+        assert!(
+            header.contains(&format!(" {export}(")) || header.contains(&format!("*{export}(")),
+            "{export} is missing in wormhole.h"
+        );
+    }
+}
+
+#[test]
+fn test_set_rendezvous_tls()
+{
+    let ca_cert = CString::new(include_str!("../../tests/tls/ca.pem")).unwrap();
+    let client_cert = CString::new(include_str!("../../tests/tls/client.pem")).unwrap();
+    let client_key = CString::new(include_str!("../../tests/tls/client-key.pem")).unwrap();
+    let good_pin = CString::new("sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").unwrap();
+    let bad_pin = CString::new("sha256/nope").unwrap();
+
+    let pins = [good_pin.as_ptr()];
+    assert!(set_rendezvous_tls(ca_cert.as_ptr(), pins.as_ptr(), 1, client_cert.as_ptr(), client_key.as_ptr()));
+    assert!(set_rendezvous_tls(ptr::null(), ptr::null(), 0, ptr::null(), ptr::null()));
+
+    let pins = [good_pin.as_ptr(), bad_pin.as_ptr()];
+    assert!(!set_rendezvous_tls(ptr::null(), pins.as_ptr(), 2, ptr::null(), ptr::null()));
+    assert!(!set_rendezvous_tls(client_key.as_ptr(), ptr::null(), 0, ptr::null(), ptr::null()));
+    assert!(!set_rendezvous_tls(ptr::null(), ptr::null(), 0, client_cert.as_ptr(), ptr::null()));
+}
+
+#[test]
+fn test_qr()
+{
+    let code = CString::new("4-hurricane-equipment").unwrap();
+    let svg = wormhole_qr_svg(code.as_ptr(), ptr::null(), 4);
+    assert!(unsafe { CStr::from_ptr(svg) }.to_str().unwrap().ends_with("</svg>"));
+    wormhole_string_free(svg);
+
+    let text = wormhole_qr_text(code.as_ptr(), ptr::null(), true);
+    assert!(unsafe { CStr::from_ptr(text) }.to_str().unwrap().starts_with('█'));
+    wormhole_string_free(text);
+
+    /* A custom rendezvous server makes the link longer, and thus the QR code larger */
+    let rendezvous_url = CString::new("ws://example.org:4000/v1").unwrap();
+    let options = test_options(rendezvous_url.as_ptr());
+    let png = unsafe { wormhole_qr_png(code.as_ptr(), ptr::null(), 1).into_vec() };
+    let custom_png = wormhole_qr_png(code.as_ptr(), &options, 1);
+    assert!(custom_png.length > png.len());
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    wormhole_buffer_free(custom_png);
+
+    assert!(wormhole_qr_svg(ptr::null(), ptr::null(), 4).is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    let typo = CString::new("4-hurricane-equipmnet").unwrap();
+    assert!(wormhole_qr_text(typo.as_ptr(), ptr::null(), true).is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+}
+
+#[test]
+fn test_null_completer()
+{
+    assert!(!wormhole_completer_refresh(ptr::null_mut()));
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    let prefix = CString::new("4-a").unwrap();
+    assert_eq!(wormhole_completer_complete(ptr::null_mut(), prefix.as_ptr()), 0);
+    assert!(wormhole_completer_get(ptr::null(), 0).is_null());
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+    wormhole_completer_free(ptr::null_mut());
+}
+
+#[test]
+fn test_completer()
+{
+    with_local_server(code_completion);
+}
+
+#[cfg(test)]
+fn code_completion(rendezvous_url: &CStr)
+{
+    let options = WormholeOptions {
+        force_direct: true,
+        ..test_options(rendezvous_url.as_ptr())
+    };
+
+    let completer = wormhole_completer_new(&options, 2);
+    assert!(!completer.is_null());
+    let prefix = CString::new("").unwrap();
+    assert!(wormhole_completer_refresh(completer));
+    assert_eq!(wormhole_completer_complete(completer, prefix.as_ptr()), 0);
+
+    let name = CString::new("config.toml").unwrap();
+    let send_session =
+        wormhole_send_bytes(name.as_ptr(), b"key = 1".as_ptr(), 7, 2, &options, ptr::null());
+    assert!(!send_session.is_null());
+    let code = unsafe { CStr::from_ptr(wormhole_session_code(send_session)) }.to_str().unwrap();
+    let nameplate = code.split('-').next().unwrap();
+
+    assert!(wormhole_completer_refresh(completer));
+    assert_eq!(wormhole_completer_complete(completer, prefix.as_ptr()), 1);
+    let completion = unsafe { CStr::from_ptr(wormhole_completer_get(completer, 0)) };
+    assert_eq!(completion.to_str().unwrap(), format!("{nameplate}-"));
+    assert!(wormhole_completer_get(completer, 1).is_null());
+
+    let prefix = CString::new(format!("{nameplate}-armis")).unwrap();
+    assert_eq!(wormhole_completer_complete(completer, prefix.as_ptr()), 1);
+    assert_eq!(wormhole_completer_complete(completer, ptr::null()), 0);
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+
+    wormhole_completer_free(completer);
+    wormhole_session_cancel(send_session);
+    wormhole_session_free(send_session);
+}
+
+#[test]
+fn test_wordlist_entropy()
+{
+    assert_eq!(wormhole_wordlist_entropy(ptr::null()), 8.0);
+
+    let wordlist = CString::new("pinyin").unwrap();
+    let mut options = WormholeOptions {
+        wordlist: wordlist.as_ptr(),
+        ..test_options(ptr::null())
+    };
+    assert_eq!(wormhole_wordlist_entropy(&options), 8.0);
+
+    let wordlist = CString::new("klingon").unwrap();
+    options.wordlist = wordlist.as_ptr();
+    assert_eq!(wormhole_wordlist_entropy(&options), 0.0);
+    assert_eq!(wormhole_last_error(), WormholeErrorCode::InvalidArgument);
+
+    /* Callers that don't know about the wordlist yet get the default one */
+    options.size = std::mem::size_of::<WormholeOptions>() - std::mem::size_of::<*const c_char>();
+    assert_eq!(wormhole_wordlist_entropy(&options), 8.0);
+    options.size = 0;
+    assert_eq!(wormhole_wordlist_entropy(&options), 0.0);
+}
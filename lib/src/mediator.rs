@@ -1,109 +1,335 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use futures::FutureExt;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use indicatif::ProgressBar;
 use color_eyre::eyre::{self, Ok, Context,ErrReport};
-use magic_wormhole::{ transfer::{self}, transit::Abilities,transit, MailboxConnection, Wormhole};
+use magic_wormhole::{ transfer::{self}, transit::Abilities,transit, MailboxConnection, Wormhole, AppConfig};
+use magic_wormhole::rendezvous::tls::{ClientIdentity, TlsConfig};
+
+use crate::callbacks::{WormholeCallbacks, WormholeSendManyCallbacks};
+use crate::memory::Sink;
+use crate::options::Options;
+
+/// TLS settings for wss:// rendezvous servers, used by all following transfers
+static RENDEZVOUS_TLS: Mutex<TlsConfig> = Mutex::new(TlsConfig::new());
+
+pub fn set_rendezvous_tls(
+    ca_cert_pem: Option<String>,
+    pins: Vec<String>,
+    client_cert_pem: Option<String>,
+    client_key_pem: Option<String>,
+) -> eyre::Result<()> {
+    let mut tls = TlsConfig::new();
+    if let Some(ca_cert_pem) = ca_cert_pem {
+        tls = tls.add_root_certificates_pem(ca_cert_pem.as_bytes())?;
+    }
+    for pin in pins {
+        tls = tls.spki_pin(pin.parse()?);
+    }
+    match (client_cert_pem, client_key_pem) {
+        (Some(cert), Some(key)) => {
+            tls = tls.client_identity(ClientIdentity::from_pem(cert.as_bytes(), key.as_bytes())?);
+        },
+        (None, None) => {},
+        _ => eyre::bail!("The client certificate and its key must be given together"),
+    }
+    *RENDEZVOUS_TLS.lock().unwrap() = tls;
+    Ok(())
+}
+
+/// The default configuration, see [`Options::app_config`] for the one to use
+pub fn app_config() -> AppConfig<transfer::AppVersion> {
+    transfer::APP_CONFIG.tls(RENDEZVOUS_TLS.lock().unwrap().clone())
+}
 
 pub async fn try_send(paths_vec: Vec<PathBuf>, new_name_str: Option<String>, code_length: usize) 
 -> eyre::Result<String, ErrReport> 
 {
     let offer = make_send_offer(paths_vec, new_name_str).await?;
-    //rintln!("{:?}",  offer);
-    let transit_abilities: Abilities = transit::Abilities::ALL_ABILITIES;
+    let options = Options::default();
+    let mailbox_connection = create_send_mailbox(&options, code_length).await?;
+    let wormhole_code: magic_wormhole::Code = mailbox_connection.code.clone();
+    let pb = create_progress_bar();
+    send(mailbox_connection, offer, &options, progress_bar_callbacks(&pb), || do_nothing().boxed()).await?;
+    pb.finish();
+   
+    Ok(wormhole_code.0)
+}
 
-    let mailbox_connection: MailboxConnection<transfer::AppVersion> 
-    = MailboxConnection::create(transfer::APP_CONFIG, code_length).await?;
+/// Allocate a code to send with, the transfer happens in [`send`]
+pub async fn create_send_mailbox(options: &Options, code_length: usize)
+-> eyre::Result<MailboxConnection<transfer::AppVersion>>
+{
+    Ok(MailboxConnection::create(options.app_config(), code_length).await?)
+}
+
+pub async fn send(
+    mailbox_connection: MailboxConnection<transfer::AppVersion>,
+    offer: transfer::OfferSend,
+    options: &Options,
+    callbacks: WormholeCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    let transit_abilities: Abilities = options.abilities();
+
+    let Some(wormhole) = cancellable(Box::pin(Wormhole::connect(mailbox_connection)), cancel()).await else {
+        return Ok(());
+    };
+    let wormhole: Wormhole = wormhole?;
+    let relay_hints = options.relay_hints();
 
-    let wormhole_code: magic_wormhole::Code = mailbox_connection.code.clone();
-    let wormhole: Wormhole = Wormhole::connect(mailbox_connection).await?;
-    let mut relay_hints: Vec<transit::RelayHint> = Vec::new();
-    relay_hints.push(transit::RelayHint::from_urls(
-        None,
-        [magic_wormhole::transit::DEFAULT_RELAY_SERVER
-            .parse()
-            .unwrap()],
-    )?);
-    
-
-    let pb = create_progress_bar(0);
-    let pb2 = pb.clone();
     transfer::send(
         wormhole,
         relay_hints,
         transit_abilities,
         offer,
-        &transit::log_transit_connection,
-        create_progress_handler(pb),
-        do_nothing(),
+        transit_handler(callbacks),
+        move |sent, total| callbacks.progress(sent, total),
+        cancel(),
     )
     .await
     .context("Send process failed")?;
-    pb2.finish();
-   
-    Ok(wormhole_code.0)
+
+    Ok(())
 }
 
+/// Send to everyone who connects with the code of `mailbox_connection`, until one of the `limits` is reached
+pub async fn send_many<F, G>(
+    mailbox_connection: MailboxConnection<transfer::AppVersion>,
+    make_offer: F,
+    options: &Options,
+    limits: transfer::SendManyLimits,
+    callbacks: WormholeSendManyCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()>
+where
+    F: FnMut() -> G,
+    G: std::future::Future<Output = std::io::Result<transfer::OfferSend>>,
+{
+    let code = mailbox_connection.code.clone();
+    let Some(wormhole) = cancellable(Box::pin(Wormhole::connect(mailbox_connection)), cancel()).await else {
+        return Ok(());
+    };
+
+    transfer::send_many(
+        wormhole?,
+        options.app_config(),
+        code,
+        options.relay_hints(),
+        options.abilities(),
+        make_offer,
+        limits,
+        callbacks,
+        cancel(),
+    )
+    .await
+    .context("Send process failed")?;
+
+    Ok(())
+}
 
 pub async fn  try_recieve(wormhole_code:String, save_path: PathBuf)-> eyre::Result<bool,ErrReport> 
 {
-    let transit_abilities: Abilities = transit::Abilities::ALL_ABILITIES;
-    
+    let mut options = Options::default();
     let code = Some(wormhole_code)
         .map(Result::Ok)
         .or_else(|| (true).then(enter_code))
         .transpose()?
-        .map(magic_wormhole::Code);
+        .map(|code| options.parse_code(&code))
+        .transpose()?;
 
     match code {
         Some(code)=>{
-            let mailbox_connection: MailboxConnection<transfer::AppVersion> 
-            = MailboxConnection::connect(transfer::APP_CONFIG, code, true).await?;
-
-
-            
-            let wormhole: Wormhole = Wormhole::connect(mailbox_connection).await?;
-            let mut relay_hints: Vec<transit::RelayHint> = Vec::new();
-            relay_hints.push(transit::RelayHint::from_urls(
-                None,
-                [magic_wormhole::transit::DEFAULT_RELAY_SERVER
-                    .parse()
-                    .unwrap()],
-            )?);
-          
-            let req = transfer::request(wormhole, relay_hints, transit_abilities, do_nothing()) .await
-            .context("Could not get an offer")?;
             let ctrl_c = install_ctrlc_handler()?;
-            match req {
-                Some(transfer::ReceiveRequest::V1(req)) => {
-                    receive_inner_v1(req, &save_path, true, ctrl_c).await?
-                },
-                Some(transfer::ReceiveRequest::V2(req)) => {
-                    receive_inner_v2(req,&save_path,  ctrl_c).await?
-                },
-                None => {
-                    return Ok(false);
-                }
-            }
+            let pb = create_progress_bar();
+            let received = receive(code, save_path, &options, progress_bar_callbacks(&pb), ctrl_c).await;
+            pb.finish();
+            received
         }
         None =>{
-            return Ok(false);
+            Ok(false)
         }
     }
-    
+}
+
+/// Receive into `save_path`, returns `false` if cancelled
+pub async fn receive(
+    code: magic_wormhole::Code,
+    save_path: PathBuf,
+    options: &Options,
+    callbacks: WormholeCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<bool> {
+    let Some(req) = request(code, options, &cancel).await? else {
+        return Ok(false);
+    };
+    callbacks.offer(offer_files(&req));
+    accept_all(req, &save_path, callbacks, cancel).await?;
+
     Ok(true)
 }
 
-async fn make_send_offer(
+/// Connect to the sender and wait for its offer, returns `None` if cancelled
+pub async fn request(
+    code: magic_wormhole::Code,
+    options: &Options,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<Option<transfer::ReceiveRequest>> {
+    let transit_abilities: Abilities = options.abilities();
+
+    let connect = async {
+        let mailbox_connection: MailboxConnection<transfer::AppVersion>
+        = MailboxConnection::connect(options.app_config(), code, true).await?;
+        eyre::Result::<_>::Ok(Wormhole::connect(mailbox_connection).await?)
+    };
+    let Some(wormhole) = cancellable(Box::pin(connect), cancel()).await else {
+        return Ok(None);
+    };
+    let wormhole: Wormhole = wormhole?;
+    let relay_hints = options.relay_hints();
+
+    transfer::request(wormhole, relay_hints, transit_abilities, cancel()).await
+    .context("Could not get an offer")
+}
+
+/// The offered files with their size, with `/` as path separator
+pub fn offer_files(req: &transfer::ReceiveRequest) -> Vec<(String, u64)> {
+    match req {
+        transfer::ReceiveRequest::V1(req) => vec![(req.filename.clone(), req.filesize)],
+        transfer::ReceiveRequest::V2(req) => req
+            .offer()
+            .iter_files()
+            .map(|(path, _, size)| (path.join("/"), size))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Accept everything into `save_path`
+pub async fn accept_all(
+    req: transfer::ReceiveRequest,
+    save_path: &std::path::Path,
+    callbacks: WormholeCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    match req {
+        transfer::ReceiveRequest::V1(req) => {
+            receive_inner_v1(req, save_path, true, callbacks, cancel).await
+        },
+        transfer::ReceiveRequest::V2(req) => {
+            receive_inner_v2(req, save_path, callbacks, cancel).await
+        },
+        req => reject_not_files(req).await,
+    }
+}
+
+/// Accept only the files in `destinations`, each one written to the path it maps to
+///
+/// The keys are paths within the offer, as returned by [`offer_files`]. Missing parent
+/// directories of the destinations get created. An empty map rejects the offer.
+pub async fn accept_files(
+    req: transfer::ReceiveRequest,
+    destinations: BTreeMap<String, PathBuf>,
+    callbacks: WormholeCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    if destinations.is_empty() {
+        return reject(req).await;
+    }
+    for destination in destinations.values() {
+        if let Some(parent) = destination.parent() {
+            async_std::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create destination directory")?;
+        }
+    }
+
+    let on_progress = move |received, total| callbacks.progress(received, total);
+    match req {
+        transfer::ReceiveRequest::V1(req) => {
+            let Some(destination) = destinations.get(&req.filename) else {
+                return reject(transfer::ReceiveRequest::V1(req)).await;
+            };
+            let mut file = async_std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(destination)
+                .await
+                .context("Failed to create destination file")?;
+            req.accept(transit_handler(callbacks), &mut file, on_progress, cancel())
+                .await
+                .context("Receive process failed")
+        },
+        transfer::ReceiveRequest::V2(req) => {
+            let answer = req
+                .offer()
+                .accept_files(|path| destinations.get(&path.join("/")).cloned());
+            req.accept(transit_handler(callbacks), answer, on_progress, cancel())
+                .await
+                .context("Receive process failed")
+        },
+        req => reject_not_files(req).await,
+    }
+}
+
+/// Accept everything, but hand the received bytes to `sink` instead of writing them to disk
+pub async fn accept_memory(
+    req: transfer::ReceiveRequest,
+    sink: Sink,
+    callbacks: WormholeCallbacks,
+    cancel: impl Fn() -> futures::future::BoxFuture<'static, ()>,
+) -> eyre::Result<()> {
+    let on_progress = move |received, total| callbacks.progress(received, total);
+    match req {
+        transfer::ReceiveRequest::V1(req) => {
+            let mut writer = sink.writer(req.filename.clone());
+            req.accept(transit_handler(callbacks), &mut writer, on_progress, cancel())
+                .await
+                .context("Receive process failed")
+        },
+        transfer::ReceiveRequest::V2(req) => {
+            let answer = req.offer().set_content(|path| {
+                let sink = sink.clone();
+                let path = path.join("/");
+                transfer::AcceptInner {
+                    offset: 0,
+                    sha256: None,
+                    content: transfer::new_accept_content(move |_append| {
+                        let writer = sink.writer(path.clone());
+                        async move { std::io::Result::Ok(writer) }
+                    }),
+                }
+            });
+            req.accept(transit_handler(callbacks), answer, on_progress, cancel())
+                .await
+                .context("Receive process failed")
+        },
+        req => reject_not_files(req).await,
+    }
+}
+
+pub async fn reject(req: transfer::ReceiveRequest) -> eyre::Result<()> {
+    req.reject().await?;
+    Ok(())
+}
+
+/// Reject a request that is no file offer, like a text message, which is always an error
+pub async fn reject_not_files<T>(req: transfer::ReceiveRequest) -> eyre::Result<T> {
+    reject(req).await?;
+    eyre::bail!("Expected a file, but the sender sent a text message");
+}
+
+pub async fn make_send_offer(
     mut files: Vec<PathBuf>,
     file_name: Option<String>,
 ) -> eyre::Result<transfer::OfferSend> {
     for file in &files {
-        eyre::ensure!(
-            async_std::path::Path::new(&file).exists().await,
-            "{} does not exist",
-            file.display()
-        );
+        if !async_std::path::Path::new(&file).exists().await {
+            let message = format!("{} does not exist", file.display());
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, message).into());
+        }
     }
     log::trace!("Making send offer in {files:?}, with name {file_name:?}");
 
@@ -145,13 +371,32 @@ async fn make_send_offer(
 
 async fn do_nothing() {}
 
-fn create_progress_bar(file_size: u64) -> ProgressBar {
+/// Returns `None` if `cancel` resolves first
+async fn cancellable<T>(
+    future: impl std::future::Future<Output = T> + Unpin,
+    cancel: impl std::future::Future<Output = ()>,
+) -> Option<T> {
+    futures::pin_mut!(cancel);
+    match futures::future::select(future, cancel).await {
+        futures::future::Either::Left((value, _)) => Some(value),
+        futures::future::Either::Right(((), _)) => None,
+    }
+}
+
+/// Log the transit connection and tell the caller about it
+fn transit_handler(callbacks: WormholeCallbacks) -> impl FnOnce(transit::TransitInfo) {
+    move |info| {
+        callbacks.connected(&info);
+        transit::log_transit_connection(info);
+    }
+}
+
+fn create_progress_bar() -> ProgressBar {
     use indicatif::ProgressStyle;
 
-    let pb = ProgressBar::new(file_size);
+    let pb = ProgressBar::new(0);
     pb.set_style(
         ProgressStyle::default_bar()
-            // .template("[{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
             .template("[{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta})")
             .unwrap()
             .progress_chars("#>-"),
@@ -159,14 +404,23 @@ fn create_progress_bar(file_size: u64) -> ProgressBar {
     pb
 }
 
-fn create_progress_handler(pb: ProgressBar) -> impl FnMut(u64, u64) {
-    move |sent, total| {
-        if sent == 0 {
+/// Callbacks that drive `pb`, for the blocking API which shows progress on the terminal.
+/// `pb` must outlive the transfer.
+fn progress_bar_callbacks(pb: &ProgressBar) -> WormholeCallbacks {
+    extern "C" fn on_progress(user_data: *mut std::ffi::c_void, transferred: u64, total: u64) {
+        let pb = unsafe { &*(user_data as *const ProgressBar) };
+        if transferred == 0 {
             pb.reset_elapsed();
             pb.set_length(total);
             pb.enable_steady_tick(std::time::Duration::from_millis(250));
         }
-        pb.set_position(sent);
+        pb.set_position(transferred);
+    }
+
+    WormholeCallbacks {
+        user_data: pb as *const ProgressBar as *mut std::ffi::c_void,
+        on_progress: Some(on_progress),
+        ..WormholeCallbacks::default()
     }
 }
 
@@ -184,6 +438,7 @@ async fn receive_inner_v1(
     req: transfer::ReceiveRequestV1,
     target_dir: &std::path::Path,
     noconfirm: bool,
+    callbacks: WormholeCallbacks,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
     use async_std::fs::OpenOptions;
@@ -199,8 +454,6 @@ async fn receive_inner_v1(
     // TODO validate untrusted input here
     let file_path = std::path::Path::new(target_dir).join(&req.filename);
 
-    let pb = create_progress_bar(req.filesize);
-
     /* Then, accept if the file exists */
     if !file_path.exists() || noconfirm {
         let mut file = OpenOptions::new()
@@ -211,9 +464,9 @@ async fn receive_inner_v1(
             .context("Failed to create destination file")?;
         return req
             .accept(
-                &transit::log_transit_connection,
+                transit_handler(callbacks),
                 &mut file,
-                create_progress_handler(pb),
+                move |received, total| callbacks.progress(received, total),
                 ctrl_c(),
             )
             .await
@@ -237,9 +490,9 @@ async fn receive_inner_v1(
         .open(&file_path)
         .await?;
     req.accept(
-        &transit::log_transit_connection,
+        transit_handler(callbacks),
         &mut file,
-        create_progress_handler(pb),
+        move |received, total| callbacks.progress(received, total),
         ctrl_c(),
     )
     .await
@@ -249,18 +502,17 @@ async fn receive_inner_v1(
 async fn receive_inner_v2(
     req: transfer::ReceiveRequestV2,
     target_dir: &std::path::Path,
+    callbacks: WormholeCallbacks,
     ctrl_c: impl Fn() -> futures::future::BoxFuture<'static, ()>,
 ) -> eyre::Result<()> {
     let offer = req.offer();
-    let file_size = offer.total_size();
 
-    
+    if let Err(err) = offer.check_symlinks(transfer::SymlinkPolicy::default()) {
+        req.reject().await.context("Could not reject offer")?;
+        return Err(err).context("Refusing the offer");
+    }
 
-    let pb = create_progress_bar(file_size);
-
-    let on_progress = move |received, _total| {
-        pb.set_position(received);
-    };
+    let on_progress = move |received, total| callbacks.progress(received, total);
 
     /* Create a temporary directory for receiving */
     use rand::Rng;
@@ -278,7 +530,7 @@ async fn receive_inner_v2(
     /* Accept the offer and receive it */
     let answer = offer.accept_all(&tmp_dir);
     req.accept(
-        &transit::log_transit_connection,
+        transit_handler(callbacks),
         answer,
         on_progress,
         ctrl_c(),
@@ -286,8 +538,10 @@ async fn receive_inner_v2(
     .await
     .context("Receive process failed")?;
 
-    // /* Put in all the symlinks last, this greatly reduces the attack surface */
-    // offer.create_symlinks(&tmp_dir).await?;
+    /* Put in all the symlinks last, this greatly reduces the attack surface */
+    offer
+        .create_symlinks(&tmp_dir, transfer::SymlinkPolicy::default())
+        .await?;
 
     /* TODO walk the output directory and delete things we did not accept; this will be important for resumption */
 
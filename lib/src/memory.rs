@@ -0,0 +1,173 @@
+//! Receiving files into memory instead of onto the disk
+
+use std::{
+    collections::BTreeMap,
+    ffi::{c_void, CString},
+    os::raw::c_char,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::AsyncWrite;
+
+use crate::callbacks::WormholeCallbacks;
+
+/// Gets called with every chunk of received data, together with the path of its file in the offer
+pub type WormholeDataCallback = Option<
+    extern "C" fn(user_data: *mut c_void, path: *const c_char, data: *const u8, length: usize),
+>;
+
+/// A byte buffer owned by the caller, free it with `wormhole_buffer_free`
+#[repr(C)]
+pub struct WormholeBuffer {
+    pub data: *mut u8,
+    pub length: usize,
+}
+
+impl WormholeBuffer {
+    pub fn null() -> Self {
+        Self {
+            data: std::ptr::null_mut(),
+            length: 0,
+        }
+    }
+
+    /// Hand the bytes over to the caller
+    pub fn from_vec(bytes: Vec<u8>) -> Self {
+        let length = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, length }
+    }
+
+    /// Take back a buffer created with [`WormholeBuffer::from_vec`]
+    pub unsafe fn into_vec(self) -> Vec<u8> {
+        if self.data.is_null() {
+            return Vec::new();
+        }
+        let slice = std::ptr::slice_from_raw_parts_mut(self.data, self.length);
+        unsafe { Box::from_raw(slice) }.into_vec()
+    }
+}
+
+/// The received files by their path in the offer, with `/` as separator
+pub type ReceivedFiles = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+/// Where the received bytes go
+#[derive(Clone)]
+pub enum Sink {
+    /// Keep the files until the caller takes them
+    Memory(ReceivedFiles),
+    /// Pass every chunk on to the caller right away, using the `user_data` of the callbacks
+    Callback(
+        extern "C" fn(*mut c_void, *const c_char, *const u8, usize),
+        WormholeCallbacks,
+    ),
+}
+
+impl Sink {
+    /// A writer for the file at `path`
+    pub fn writer(&self, path: String) -> SinkWriter {
+        if let Sink::Memory(files) = self {
+            files.lock().unwrap().insert(path.clone(), Vec::new());
+        }
+        SinkWriter {
+            sink: self.clone(),
+            c_path: crate::to_c_string_lossy(&path),
+            path,
+        }
+    }
+}
+
+pub struct SinkWriter {
+    sink: Sink,
+    path: String,
+    c_path: CString,
+}
+
+impl AsyncWrite for SinkWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match &self.sink {
+            Sink::Memory(files) => {
+                let mut files = files.lock().unwrap();
+                files
+                    .entry(self.path.clone())
+                    .or_default()
+                    .extend_from_slice(buf);
+            },
+            Sink::Callback(on_data, callbacks) => {
+                on_data(
+                    callbacks.user_data,
+                    self.c_path.as_ptr(),
+                    buf.as_ptr(),
+                    buf.len(),
+                );
+            },
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::AsyncWriteExt;
+    use std::ffi::CStr;
+
+    extern "C" fn on_data(user_data: *mut c_void, path: *const c_char, data: *const u8, length: usize) {
+        let chunks = unsafe { &*(user_data as *const Mutex<Vec<String>>) };
+        let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap();
+        let data = unsafe { std::slice::from_raw_parts(data, length) };
+        chunks
+            .lock()
+            .unwrap()
+            .push(format!("{path}: {}", String::from_utf8_lossy(data)));
+    }
+
+    #[async_std::test]
+    async fn test_sink() {
+        let files = ReceivedFiles::default();
+        let sink = Sink::Memory(files.clone());
+        let mut writer = sink.writer("folder/file".into());
+        writer.write_all(b"Hello, ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        sink.writer("empty".into());
+        assert_eq!(
+            *files.lock().unwrap(),
+            BTreeMap::from([
+                ("empty".to_string(), Vec::new()),
+                ("folder/file".to_string(), b"Hello, world".to_vec()),
+            ])
+        );
+
+        let chunks = Mutex::new(Vec::<String>::new());
+        let callbacks = WormholeCallbacks {
+            user_data: &chunks as *const _ as *mut c_void,
+            ..WormholeCallbacks::default()
+        };
+        let sink = Sink::Callback(on_data, callbacks);
+        let mut writer = sink.writer("file".into());
+        writer.write_all(b"Hello").await.unwrap();
+        assert_eq!(*chunks.lock().unwrap(), ["file: Hello"]);
+    }
+
+    #[test]
+    fn test_buffer() {
+        let buffer = WormholeBuffer::from_vec(b"data".to_vec());
+        assert_eq!(buffer.length, 4);
+        assert_eq!(unsafe { buffer.into_vec() }, b"data");
+        assert!(unsafe { WormholeBuffer::null().into_vec() }.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+//! Server and connection settings of a transfer, like the CLI's common arguments
+
+use std::{os::raw::c_char, time::Duration};
+
+use color_eyre::eyre::{self, Context};
+use magic_wormhole::{
+    transfer, transit, uri::WormholeTransferUri, wordlist::Wordlist, AppConfig, AppID,
+};
+
+use crate::{mediator, optional_string};
+
+/// Settings for a transfer, all fields but `size` may be null or zero for the defaults
+///
+/// New fields only ever get added at the end. Set `size` to `sizeof(WormholeOptions)`, so that
+/// programs built against an older header keep working: fields past `size` get their defaults.
+#[repr(C)]
+pub struct WormholeOptions {
+    /// The size of this struct as the caller knows it, `sizeof(WormholeOptions)`
+    pub size: usize,
+    /// Use a custom rendezvous server, like `ws://example.org:4000/v1`. Both sides need to use
+    /// the same one in order to find each other.
+    pub rendezvous_url: *const c_char,
+    /// Use custom relay servers, as `tcp://HOSTNAME:PORT` or `ws://` and `wss://` URLs
+    pub relay_urls: *const *const c_char,
+    pub relay_urls_length: usize,
+    /// Use a custom application ID. Both sides need to use the same one.
+    pub app_id: *const c_char,
+    /// Disable the relay server support and force a direct connection
+    pub force_direct: bool,
+    /// Always route traffic over a relay server, this hides your IP address from the peer
+    pub force_relay: bool,
+    /// Generate and complete codes with this wordlist, either `pgp` (the default), `german`,
+    /// `pinyin` or the path of a file with one word per line. Codes made of the words of the
+    /// bundled wordlists are accepted either way.
+    pub wordlist: *const c_char,
+}
+
+impl WormholeOptions {
+    /// Copy the fields that the caller's version of the struct has, the others stay zero
+    fn upgrade(options: *const WormholeOptions) -> eyre::Result<WormholeOptions> {
+        let size = unsafe { (*options).size };
+        eyre::ensure!(
+            size != 0,
+            "The size of the WormholeOptions must be set to sizeof(WormholeOptions)"
+        );
+        unsafe {
+            let mut upgraded: WormholeOptions = std::mem::zeroed();
+            std::ptr::copy_nonoverlapping(
+                options as *const u8,
+                &mut upgraded as *mut WormholeOptions as *mut u8,
+                size.min(std::mem::size_of::<WormholeOptions>()),
+            );
+            Ok(upgraded)
+        }
+    }
+}
+
+/// The validated [`WormholeOptions`]
+#[derive(Clone, Debug)]
+pub struct Options {
+    rendezvous_url: Option<url::Url>,
+    app_id: Option<String>,
+    relay_hints: Vec<transit::RelayHint>,
+    abilities: transit::Abilities,
+    wordlist: Option<Wordlist>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rendezvous_url: None,
+            app_id: None,
+            relay_hints: vec![default_relay_hint()],
+            abilities: transit::Abilities::ALL_ABILITIES,
+            wordlist: None,
+        }
+    }
+}
+
+fn default_relay_hint() -> transit::RelayHint {
+    let url: url::Url = transit::DEFAULT_RELAY_SERVER.parse().unwrap();
+    transit::RelayHint::from_urls(None, [url]).unwrap()
+}
+
+impl Options {
+    /// Parse the options from a nullable pointer, null gives the defaults
+    pub fn from_ptr(options: *const WormholeOptions) -> eyre::Result<Self> {
+        if options.is_null() {
+            return Ok(Self::default());
+        }
+        let options = &WormholeOptions::upgrade(options)?;
+
+        let abilities = match (options.force_direct, options.force_relay) {
+            (false, false) => transit::Abilities::ALL_ABILITIES,
+            (true, false) => transit::Abilities::FORCE_DIRECT,
+            (false, true) => transit::Abilities::FORCE_RELAY,
+            (true, true) => eyre::bail!("force_direct and force_relay are mutually exclusive"),
+        };
+
+        let rendezvous_url = optional_string(options.rendezvous_url)
+            .map(|url| url.parse::<url::Url>())
+            .transpose()
+            .context("Invalid rendezvous server URL")?;
+
+        let relay_urls = if options.relay_urls.is_null() {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(options.relay_urls, options.relay_urls_length) }
+        };
+        // TODO handle relay servers with multiple endpoints better
+        let mut relay_hints = relay_urls
+            .iter()
+            .filter_map(|&url| optional_string(url))
+            .map(|url| {
+                let url: url::Url = url
+                    .parse()
+                    .with_context(|| format!("Invalid relay server URL {url}"))?;
+                Ok(transit::RelayHint::from_urls(
+                    url.host_str().map(str::to_owned),
+                    [url],
+                )?)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        if relay_hints.is_empty() {
+            relay_hints.push(default_relay_hint());
+        }
+
+        let wordlist = optional_string(options.wordlist)
+            .map(|name| match Wordlist::bundled(&name) {
+                Some(wordlist) => Ok(wordlist),
+                None => Wordlist::load(&name)
+                    .with_context(|| format!("Failed to load the wordlist {name}")),
+            })
+            .transpose()?;
+
+        Ok(Self {
+            rendezvous_url,
+            app_id: optional_string(options.app_id),
+            relay_hints,
+            abilities,
+            wordlist,
+        })
+    }
+
+    /// Read a code for receiving, which may also be a `wormhole-transfer:` link. The rendezvous
+    /// server of a link replaces the default one.
+    pub fn parse_code(&mut self, code: &str) -> eyre::Result<magic_wormhole::Code> {
+        /* Codes never contain a colon, so this must be a link */
+        if !code.contains(':') {
+            return Ok(match &self.wordlist {
+                Some(wordlist) => magic_wormhole::Code::parse_with_wordlist(code, wordlist)?,
+                None => code.parse()?,
+            });
+        }
+        let uri: WormholeTransferUri = code.parse().context("Invalid wormhole-transfer link")?;
+        eyre::ensure!(
+            !uri.is_leader,
+            "This link is for sending files to whoever created it, not for receiving"
+        );
+        if let Some(rendezvous_url) = uri.rendezvous_server {
+            if let Some(configured) = &self.rendezvous_url {
+                eyre::ensure!(
+                    *configured == rendezvous_url,
+                    "The link uses the rendezvous server {rendezvous_url}, but {configured} is configured"
+                );
+            }
+            self.rendezvous_url = Some(rendezvous_url);
+        }
+        Ok(uri.code)
+    }
+
+    pub fn app_config(&self) -> AppConfig<transfer::AppVersion> {
+        let mut app_config = mediator::app_config();
+        if let Some(rendezvous_url) = &self.rendezvous_url {
+            app_config = app_config.rendezvous_url(rendezvous_url.to_string().into());
+        }
+        if let Some(app_id) = &self.app_id {
+            app_config = app_config.id(AppID::new(app_id.clone()));
+        }
+        if let Some(wordlist) = &self.wordlist {
+            app_config = app_config.wordlist(wordlist.clone());
+        }
+        app_config
+    }
+
+    /// The rendezvous server, if it is not the default one
+    pub fn rendezvous_url(&self) -> Option<&url::Url> {
+        self.rendezvous_url.as_ref()
+    }
+
+    pub fn relay_hints(&self) -> Vec<transit::RelayHint> {
+        self.relay_hints.clone()
+    }
+
+    pub fn abilities(&self) -> transit::Abilities {
+        self.abilities
+    }
+
+    /// The configured wordlist, or the PGP word list
+    pub fn wordlist(&self) -> Wordlist {
+        self.wordlist
+            .clone()
+            .unwrap_or_else(|| Wordlist::bundled("pgp").unwrap())
+    }
+}
+
+/// When to stop sending to more recipients, zero fields mean the defaults
+#[repr(C)]
+pub struct WormholeSendManyLimits {
+    /// Send to at most this many recipients, 30 by default. This is also the number of tries
+    /// an attacker gets at guessing the code.
+    pub max_recipients: u64,
+    /// Stop waiting for new recipients after this many seconds, one hour by default
+    pub timeout_seconds: u64,
+}
+
+impl WormholeSendManyLimits {
+    /// Read the limits from a nullable pointer, null gives the defaults
+    pub fn from_ptr(limits: *const WormholeSendManyLimits) -> transfer::SendManyLimits {
+        let mut result = transfer::SendManyLimits::default();
+        if let Some(limits) = unsafe { limits.as_ref() } {
+            if limits.max_recipients != 0 {
+                result.max_recipients = limits.max_recipients;
+            }
+            if limits.timeout_seconds != 0 {
+                result.timeout = Duration::from_secs(limits.timeout_seconds);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_options() {
+        let options = Options::from_ptr(std::ptr::null()).unwrap();
+        assert_eq!(options.app_config().id, transfer::APPID);
+        assert!(options.rendezvous_url().is_none());
+        assert_eq!(options.relay_hints(), [default_relay_hint()]);
+        assert!(options.abilities().can_direct() && options.abilities().can_relay());
+
+        let rendezvous_url = CString::new("ws://example.org:4000/v1").unwrap();
+        let app_id = CString::new("example.org/wormhole").unwrap();
+        let relay_urls = [
+            CString::new("tcp://relay.example.org:4001").unwrap(),
+            CString::new("wss://relay.example.org/").unwrap(),
+        ];
+        let relay_url_ptrs = relay_urls.iter().map(|url| url.as_ptr()).collect::<Vec<_>>();
+        let mut c_options = WormholeOptions {
+            size: std::mem::size_of::<WormholeOptions>(),
+            rendezvous_url: rendezvous_url.as_ptr(),
+            relay_urls: relay_url_ptrs.as_ptr(),
+            relay_urls_length: relay_url_ptrs.len(),
+            app_id: app_id.as_ptr(),
+            force_direct: false,
+            force_relay: true,
+            wordlist: std::ptr::null(),
+        };
+        let options = Options::from_ptr(&c_options).unwrap();
+        let app_config = options.app_config();
+        assert_eq!(app_config.rendezvous_url, "ws://example.org:4000/v1");
+        assert_eq!(options.rendezvous_url().unwrap().as_str(), "ws://example.org:4000/v1");
+        assert_eq!(app_config.id, AppID::new("example.org/wormhole"));
+        assert_eq!(options.relay_hints().len(), 2);
+        assert!(!options.abilities().can_direct() && options.abilities().can_relay());
+
+        c_options.force_direct = true;
+        assert!(Options::from_ptr(&c_options).is_err());
+
+        let bad_url = CString::new("relay.example.org").unwrap();
+        let bad_url_ptrs = [bad_url.as_ptr()];
+        c_options.force_direct = false;
+        c_options.relay_urls = bad_url_ptrs.as_ptr();
+        c_options.relay_urls_length = 1;
+        assert!(Options::from_ptr(&c_options).is_err());
+
+        let wordlist = CString::new("german").unwrap();
+        c_options.relay_urls = std::ptr::null();
+        c_options.wordlist = wordlist.as_ptr();
+        let mut options = Options::from_ptr(&c_options).unwrap();
+        assert_eq!(options.wordlist(), Wordlist::bundled("german").unwrap());
+        assert_eq!(
+            magic_wormhole::CodeCompleter::from_config(&options.app_config(), 2).complete("4-apf"),
+            ["4-apfel-"]
+        );
+        assert!(options.parse_code("4-apfel-adlr").is_err());
+        assert!(options.parse_code("4-apfel-adler").is_ok());
+
+        let bad_wordlist = CString::new("/nonexistent/wordlist.txt").unwrap();
+        c_options.wordlist = bad_wordlist.as_ptr();
+        assert!(Options::from_ptr(&c_options).is_err());
+    }
+
+    #[test]
+    fn test_parse_code() {
+        let mut options = Options::default();
+        assert_eq!(options.parse_code("4-purple-sausages").unwrap().0, "4-purple-sausages");
+        assert!(options.rendezvous_url().is_none());
+
+        let link = "wormhole-transfer:4-purple-sausages?rendezvous=ws%3A%2F%2Fexample.org%3A4000%2Fv1";
+        assert_eq!(options.parse_code(link).unwrap().0, "4-purple-sausages");
+        assert_eq!(options.app_config().rendezvous_url, "ws://example.org:4000/v1");
+        /* The same server again is fine, a different one is not */
+        assert!(options.parse_code(link).is_ok());
+        let mut other = Options {
+            rendezvous_url: Some("ws://example.com/v1".parse().unwrap()),
+            ..Options::default()
+        };
+        assert!(other.parse_code(link).is_err());
+
+        assert!(options.parse_code("wormhole-transfer:4-purple-sausages?role=leader").is_err());
+        assert!(options.parse_code("https://example.org/4-purple-sausages").is_err());
+        assert!(options.parse_code("4-armistice-baboom").is_err());
+        assert!(options.parse_code("purple-sausages").is_err());
+    }
+
+    #[test]
+    fn test_send_many_limits() {
+        let defaults = transfer::SendManyLimits::default();
+        assert_eq!(WormholeSendManyLimits::from_ptr(std::ptr::null()), defaults);
+
+        let limits = WormholeSendManyLimits {
+            max_recipients: 12,
+            timeout_seconds: 0,
+        };
+        let limits = WormholeSendManyLimits::from_ptr(&limits);
+        assert_eq!(limits.max_recipients, 12);
+        assert_eq!(limits.timeout, defaults.timeout);
+    }
+}
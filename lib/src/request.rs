@@ -0,0 +1,58 @@
+//! Offers that wait for the C caller to decide what to receive
+
+use std::{ffi::CString, os::raw::c_char, sync::Mutex};
+
+use magic_wormhole::transfer;
+
+use crate::mediator;
+
+/// A file to accept, and where to put it
+#[repr(C)]
+pub struct WormholeAcceptFile {
+    /// The path of the file within the offer, as given by `wormhole_request_file`
+    pub path: *const c_char,
+    /// Where to write the file
+    pub destination: *const c_char,
+}
+
+/// A received offer that has not been answered yet
+pub struct Request {
+    code: String,
+    files: Vec<(String, CString, u64)>,
+    request: Mutex<Option<transfer::ReceiveRequest>>,
+}
+
+impl Request {
+    pub fn new(code: String, request: transfer::ReceiveRequest) -> Self {
+        let files = mediator::offer_files(&request)
+            .into_iter()
+            .map(|(path, size)| {
+                let c_path = crate::to_c_string_lossy(&path);
+                (path, c_path, size)
+            })
+            .collect();
+        Self {
+            code,
+            files,
+            request: Mutex::new(Some(request)),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// The offered files with their path and size
+    pub fn files(&self) -> &[(String, CString, u64)] {
+        &self.files
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.files.iter().any(|(file, _, _)| file == path)
+    }
+
+    /// Take out the request for answering it, this only works once
+    pub fn take(&self) -> Option<transfer::ReceiveRequest> {
+        self.request.lock().unwrap().take()
+    }
+}
@@ -0,0 +1,200 @@
+//! Transfers running in the background, so that C callers don't have to block on them
+
+use std::{
+    ffi::CString,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
+
+use color_eyre::eyre;
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, FutureExt, Shared},
+};
+
+use crate::error::{self, WormholeErrorCode};
+use crate::memory::ReceivedFiles;
+
+/// The state of a [`Session`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WormholeSessionStatus {
+    /// The transfer is still going on
+    Running = 0,
+    /// The transfer completed successfully
+    Finished = 1,
+    /// The transfer failed
+    Failed = 2,
+    /// The transfer got cancelled with `wormhole_session_cancel`
+    Cancelled = 3,
+}
+
+/// A future that resolves once the session got cancelled or freed
+pub type CancelFuture = Shared<BoxFuture<'static, ()>>;
+
+struct SessionState {
+    status: Mutex<WormholeSessionStatus>,
+    finished: Condvar,
+    /// Why the session failed, with its message
+    error: Mutex<Option<(WormholeErrorCode, String)>>,
+}
+
+/// A transfer that runs on the async-std executor in the background
+pub struct Session {
+    code: CString,
+    state: Arc<SessionState>,
+    cancel: Mutex<Option<oneshot::Sender<()>>>,
+    received: ReceivedFiles,
+}
+
+impl Session {
+    /// Spawn `run` in the background
+    ///
+    /// `run` gets a future that resolves when the session gets cancelled. It should stop and clean up
+    /// once that happens, usually by passing it to the transfer functions.
+    pub fn spawn<F>(code: String, run: impl FnOnce(CancelFuture) -> F) -> Self
+    where
+        F: Future<Output = eyre::Result<()>> + Send + 'static,
+    {
+        let (cancel_sender, cancel_receiver) = oneshot::channel::<()>();
+        /* Only count the session as cancelled if the transfer noticed, and didn't finish before.
+         * Dropping the sender cancels too. */
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let task_cancelled = cancelled.clone();
+        let cancel = cancel_receiver
+            .map(move |_| cancelled.store(true, Ordering::SeqCst))
+            .boxed()
+            .shared();
+
+        let state = Arc::new(SessionState {
+            status: Mutex::new(WormholeSessionStatus::Running),
+            finished: Condvar::new(),
+            error: Mutex::new(None),
+        });
+        let run = run(cancel);
+        let task_state = state.clone();
+        async_std::task::spawn(async move {
+            let result = run.await;
+            let status = match result {
+                _ if task_cancelled.load(Ordering::SeqCst) => WormholeSessionStatus::Cancelled,
+                Ok(()) => WormholeSessionStatus::Finished,
+                Err(error_report) => {
+                    *task_state.error.lock().unwrap() = Some((
+                        WormholeErrorCode::from_report(&error_report),
+                        error::report_message(&error_report),
+                    ));
+                    WormholeSessionStatus::Failed
+                },
+            };
+            *task_state.status.lock().unwrap() = status;
+            task_state.finished.notify_all();
+        });
+
+        Self {
+            code: CString::new(code).expect("Wormhole codes don't contain null bytes"),
+            state,
+            cancel: Mutex::new(Some(cancel_sender)),
+            received: ReceivedFiles::default(),
+        }
+    }
+
+    /// Keep the files that the transfer receives into memory, so that they can be taken out
+    pub fn with_received(mut self, received: ReceivedFiles) -> Self {
+        self.received = received;
+        self
+    }
+
+    /// Take out a file that has been received into memory
+    pub fn take_file(&self, path: &str) -> Option<Vec<u8>> {
+        self.received.lock().unwrap().remove(path)
+    }
+
+    pub fn code(&self) -> &CString {
+        &self.code
+    }
+
+    pub fn status(&self) -> WormholeSessionStatus {
+        *self.state.status.lock().unwrap()
+    }
+
+    /// Block until the transfer is over
+    pub fn wait(&self) -> WormholeSessionStatus {
+        let status = self.state.status.lock().unwrap();
+        *self
+            .state
+            .finished
+            .wait_while(status, |status| *status == WormholeSessionStatus::Running)
+            .unwrap()
+    }
+
+    /// Why the transfer failed, if it did
+    pub fn error(&self) -> Option<(WormholeErrorCode, String)> {
+        self.state.error.lock().unwrap().clone()
+    }
+
+    /// Ask the transfer to stop, this does not wait for it and does nothing if it is already over
+    pub fn cancel(&self) {
+        if self.status() != WormholeSessionStatus::Running {
+            return;
+        }
+        if let Some(cancel) = self.cancel.lock().unwrap().take() {
+            let _ = cancel.send(());
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_session_status() {
+        let session = Session::spawn("1-foo-bar".into(), |_cancel| async { Ok(()) });
+        assert_eq!(session.wait(), WormholeSessionStatus::Finished);
+        assert_eq!(session.status(), WormholeSessionStatus::Finished);
+        assert_eq!(session.code().to_str().unwrap(), "1-foo-bar");
+        assert_eq!(session.error(), None);
+
+        let session = Session::spawn("1-foo-bar".into(), |_cancel| async {
+            eyre::bail!("Something went wrong")
+        });
+        assert_eq!(session.wait(), WormholeSessionStatus::Failed);
+        assert_eq!(
+            session.error(),
+            Some((WormholeErrorCode::Other, "Something went wrong".into()))
+        );
+
+        let session = Session::spawn("1-foo-bar".into(), |cancel| async {
+            cancel.await;
+            Ok(())
+        });
+        assert_eq!(session.status(), WormholeSessionStatus::Running);
+        session.cancel();
+        assert_eq!(session.wait(), WormholeSessionStatus::Cancelled);
+
+        /* Cancelling a finished session doesn't change its status */
+        let session = Session::spawn("1-foo-bar".into(), |_cancel| async { Ok(()) });
+        assert_eq!(session.wait(), WormholeSessionStatus::Finished);
+        session.cancel();
+        assert_eq!(session.status(), WormholeSessionStatus::Finished);
+
+        /* Neither does cancelling a transfer that finishes without noticing */
+        let (done, finish) = oneshot::channel::<()>();
+        let session = Session::spawn("1-foo-bar".into(), |_cancel| async {
+            let _ = finish.await;
+            Ok(())
+        });
+        session.cancel();
+        done.send(()).unwrap();
+        assert_eq!(session.wait(), WormholeSessionStatus::Finished);
+    }
+}
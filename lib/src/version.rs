@@ -0,0 +1,41 @@
+//! What this build of the library supports
+
+use std::{ffi::CStr, os::raw::c_char};
+
+use magic_wormhole::{transfer, transit};
+
+/// The protocol features that transfers of this library can use
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WormholeAbilities {
+    /// Direct connections to the peer
+    pub direct_tcp_v1: bool,
+    /// Connections through a relay server
+    pub relay_v1: bool,
+    /// The experimental noise encryption for transit connections
+    pub noise_v1: bool,
+    /// The transfer-v2 protocol, which supports folders without packing them into an archive
+    pub transfer_v2: bool,
+}
+
+const VERSION: &CStr = match CStr::from_bytes_with_nul(
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_bytes(),
+) {
+    Ok(version) => version,
+    Err(_) => panic!("The version contains a null byte"),
+};
+
+/// The version of this library, as a static string
+pub fn version() -> *const c_char {
+    VERSION.as_ptr()
+}
+
+pub fn abilities() -> WormholeAbilities {
+    let transit_abilities = transit::Abilities::ALL_ABILITIES;
+    WormholeAbilities {
+        direct_tcp_v1: transit_abilities.can_direct(),
+        relay_v1: transit_abilities.can_relay(),
+        noise_v1: transit_abilities.can_noise_crypto(),
+        transfer_v2: transfer::APP_CONFIG.app_version.supports_v2(),
+    }
+}
@@ -0,0 +1,652 @@
+/* Generated by cbindgen from wormhole-rs-lib, do not edit by hand */
+
+#ifndef WORMHOLE_H
+#define WORMHOLE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * How we are connected to the peer
+ */
+typedef enum WormholeConnectionType {
+  /**
+   * Directly connected to the peer
+   */
+  WORMHOLE_CONNECTION_TYPE_DIRECT = 0,
+  /**
+   * Connected through a relay server
+   */
+  WORMHOLE_CONNECTION_TYPE_RELAY = 1,
+} WormholeConnectionType;
+
+/**
+ * What kind of error happened
+ *
+ * The values are stable, new categories only get appended.
+ */
+typedef enum WormholeErrorCode {
+  /**
+   * No error happened
+   */
+  WORMHOLE_ERROR_CODE_OK = 0,
+  /**
+   * The code is wrong, or somebody tried to guess it
+   */
+  WORMHOLE_ERROR_CODE_WRONG_CODE = 1,
+  /**
+   * The peer rejected the transfer
+   */
+  WORMHOLE_ERROR_CODE_PEER_REJECTED = 2,
+  /**
+   * Something went wrong on the peer's side
+   */
+  WORMHOLE_ERROR_CODE_PEER_ERROR = 3,
+  /**
+   * Could not talk to the rendezvous server, the relay server or the peer
+   */
+  WORMHOLE_ERROR_CODE_NETWORK = 4,
+  /**
+   * The transfer got cancelled by the peer, or by us
+   */
+  WORMHOLE_ERROR_CODE_CANCELLED = 5,
+  /**
+   * Reading or writing the local files failed
+   */
+  WORMHOLE_ERROR_CODE_IO = 6,
+  /**
+   * The peer or a server sent something we did not understand
+   */
+  WORMHOLE_ERROR_CODE_PROTOCOL = 7,
+  /**
+   * An argument of the called function is invalid
+   */
+  WORMHOLE_ERROR_CODE_INVALID_ARGUMENT = 8,
+  /**
+   * Anything else
+   */
+  WORMHOLE_ERROR_CODE_OTHER = 9,
+} WormholeErrorCode;
+
+/**
+ * The state of a [`Session`]
+ */
+typedef enum WormholeSessionStatus {
+  /**
+   * The transfer is still going on
+   */
+  WORMHOLE_SESSION_STATUS_RUNNING = 0,
+  /**
+   * The transfer completed successfully
+   */
+  WORMHOLE_SESSION_STATUS_FINISHED = 1,
+  /**
+   * The transfer failed
+   */
+  WORMHOLE_SESSION_STATUS_FAILED = 2,
+  /**
+   * The transfer got cancelled with `wormhole_session_cancel`
+   */
+  WORMHOLE_SESSION_STATUS_CANCELLED = 3,
+} WormholeSessionStatus;
+
+/**
+ * Completes codes while the user types them
+ */
+typedef struct WormholeCompleter WormholeCompleter;
+
+/**
+ * A received offer that has not been answered yet
+ */
+typedef struct WormholeRequest WormholeRequest;
+
+/**
+ * A transfer that runs on the async-std executor in the background
+ */
+typedef struct WormholeSession WormholeSession;
+
+/**
+ * Settings for a transfer, all fields but `size` may be null or zero for the defaults
+ *
+ * New fields only ever get added at the end. Set `size` to `sizeof(WormholeOptions)`, so that
+ * programs built against an older header keep working: fields past `size` get their defaults.
+ */
+typedef struct WormholeOptions {
+  /**
+   * The size of this struct as the caller knows it, `sizeof(WormholeOptions)`
+   */
+  size_t size;
+  /**
+   * Use a custom rendezvous server, like `ws://example.org:4000/v1`. Both sides need to use
+   * the same one in order to find each other.
+   */
+  const char *rendezvous_url;
+  /**
+   * Use custom relay servers, as `tcp://HOSTNAME:PORT` or `ws://` and `wss://` URLs
+   */
+  const char *const *relay_urls;
+  size_t relay_urls_length;
+  /**
+   * Use a custom application ID. Both sides need to use the same one.
+   */
+  const char *app_id;
+  /**
+   * Disable the relay server support and force a direct connection
+   */
+  bool force_direct;
+  /**
+   * Always route traffic over a relay server, this hides your IP address from the peer
+   */
+  bool force_relay;
+  /**
+   * Generate and complete codes with this wordlist, either `pgp` (the default), `german`,
+   * `pinyin` or the path of a file with one word per line. Codes made of the words of the
+   * bundled wordlists are accepted either way.
+   */
+  const char *wordlist;
+} WormholeOptions;
+
+/**
+ * A file in an offer, only valid during the `on_offer` callback
+ */
+typedef struct WormholeOfferFile {
+  /**
+   * The path of the file within the offer, with `/` as separator
+   */
+  const char *path;
+  uint64_t size;
+} WormholeOfferFile;
+
+/**
+ * Callbacks for the events of a session
+ *
+ * All of them are optional and may be null. They get called from a background thread,
+ * together with `user_data`.
+ */
+typedef struct WormholeCallbacks {
+  void *user_data;
+  /**
+   * The code to tell the peer has been allocated
+   */
+  void (*on_code)(void *user_data, const char *code);
+  /**
+   * The transit connection to the peer has been established. `peer_address` is the address
+   * of the peer or relay server we connected to, as `ip:port`.
+   */
+  void (*on_connected)(void *user_data,
+                       enum WormholeConnectionType connection_type,
+                       const char *peer_address);
+  /**
+   * The peer offered us these files
+   */
+  void (*on_offer)(void *user_data, const struct WormholeOfferFile *files, size_t length);
+  /**
+   * `transferred` out of `total` bytes are done
+   */
+  void (*on_progress)(void *user_data, uint64_t transferred, uint64_t total);
+} WormholeCallbacks;
+
+/**
+ * When to stop sending to more recipients, zero fields mean the defaults
+ */
+typedef struct WormholeSendManyLimits {
+  /**
+   * Send to at most this many recipients, 30 by default. This is also the number of tries
+   * an attacker gets at guessing the code.
+   */
+  uint64_t max_recipients;
+  /**
+   * Stop waiting for new recipients after this many seconds, one hour by default
+   */
+  uint64_t timeout_seconds;
+} WormholeSendManyLimits;
+
+/**
+ * Callbacks for sending to many recipients
+ *
+ * All of them are optional and may be null. They get called from a background thread,
+ * together with `user_data`. Recipients are numbered from zero in the order in which they
+ * connect, the transfers to them may run at the same time.
+ */
+typedef struct WormholeSendManyCallbacks {
+  void *user_data;
+  /**
+   * The code to tell the recipients has been allocated
+   */
+  void (*on_code)(void *user_data, const char *code);
+  /**
+   * A recipient connected, and the transfer to it starts
+   */
+  void (*on_recipient_connected)(void *user_data, uint64_t recipient);
+  /**
+   * The transit connection to a recipient has been established, like `on_connected`
+   */
+  void (*on_recipient_transit)(void *user_data,
+                               uint64_t recipient,
+                               enum WormholeConnectionType connection_type,
+                               const char *peer_address);
+  /**
+   * `transferred` out of `total` bytes are done for a recipient
+   */
+  void (*on_recipient_progress)(void *user_data,
+                                uint64_t recipient,
+                                uint64_t transferred,
+                                uint64_t total);
+  /**
+   * The transfer to a recipient is over. `error` is `Ok` and `message` is null on success.
+   * This also gets called for recipients that entered a wrong code.
+   */
+  void (*on_recipient_finished)(void *user_data,
+                                uint64_t recipient,
+                                enum WormholeErrorCode error,
+                                const char *message);
+} WormholeSendManyCallbacks;
+
+/**
+ * A file to accept, and where to put it
+ */
+typedef struct WormholeAcceptFile {
+  /**
+   * The path of the file within the offer, as given by `wormhole_request_file`
+   */
+  const char *path;
+  /**
+   * Where to write the file
+   */
+  const char *destination;
+} WormholeAcceptFile;
+
+/**
+ * Gets called with every chunk of received data, together with the path of its file in the offer
+ */
+typedef void (*WormholeDataCallback)(void *user_data,
+                                     const char *path,
+                                     const uint8_t *data,
+                                     size_t length);
+
+/**
+ * A byte buffer owned by the caller, free it with `wormhole_buffer_free`
+ */
+typedef struct WormholeBuffer {
+  uint8_t *data;
+  size_t length;
+} WormholeBuffer;
+
+/**
+ * The protocol features that transfers of this library can use
+ */
+typedef struct WormholeAbilities {
+  /**
+   * Direct connections to the peer
+   */
+  bool direct_tcp_v1;
+  /**
+   * Connections through a relay server
+   */
+  bool relay_v1;
+  /**
+   * The experimental noise encryption for transit connections
+   */
+  bool noise_v1;
+  /**
+   * The transfer-v2 protocol, which supports folders without packing them into an archive
+   */
+  bool transfer_v2;
+} WormholeAbilities;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * # 发送文件
+ *
+ * Returns the code once the transfer is done, or null on failure. Free it with
+ * `wormhole_string_free`.
+ */
+char *send_files(const char *const *file_paths,
+                 size_t length,
+                 const char *new_name,
+                 size_t code_length);
+
+/**
+ * # 接受文件
+ *
+ * The code may also be a `wormhole-transfer:` link, which can bring its own rendezvous server.
+ */
+bool receive_files(const char *wormhole_code, const char *save_path);
+
+/**
+ * # 设置 wss:// 会合服务器的 TLS 参数
+ *
+ * All arguments may be null. `ca_cert_pem` contains extra trusted CA certificates,
+ * `pins` are public key pins in the form `sha256/<base64>`, and `client_cert_pem`
+ * together with `client_key_pem` enable mutual TLS. All of them are PEM contents,
+ * not file names. The settings apply to all following `send_files`/`receive_files` calls.
+ */
+bool set_rendezvous_tls(const char *ca_cert_pem,
+                        const char *const *pins,
+                        size_t pins_length,
+                        const char *client_cert_pem,
+                        const char *client_key_pem);
+
+/**
+ * # 在后台发送文件
+ *
+ * Like `send_files`, but returns as soon as the code has been allocated, while the transfer
+ * continues in the background. Returns null on failure. Otherwise, get the code with
+ * `wormhole_session_code` and free the session with `wormhole_session_free` once you are done.
+ * `options` and `callbacks` may be null, the structs get copied.
+ */
+struct WormholeSession *wormhole_send_files(const char *const *file_paths,
+                                            size_t length,
+                                            const char *new_name,
+                                            size_t code_length,
+                                            const struct WormholeOptions *options,
+                                            const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 在后台发送内存中的数据
+ *
+ * Like `wormhole_send_files`, but sends `length` bytes from `data` as a file called `name`.
+ * The data gets copied, so the buffer may be freed right after the call.
+ */
+struct WormholeSession *wormhole_send_bytes(const char *name,
+                                            const uint8_t *data,
+                                            size_t length,
+                                            size_t code_length,
+                                            const struct WormholeOptions *options,
+                                            const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 在后台向多人发送文件
+ *
+ * Like `wormhole_send_files`, but keeps sending the files to everyone who enters the code, until
+ * one of the `limits` is reached or the session gets cancelled. Every recipient gives an
+ * attacker another try at guessing the code, so consider a longer `code_length`. The session
+ * finishes once the last transfer is over, the outcome of each transfer goes to the callbacks.
+ * `limits`, `options` and `callbacks` may be null, the structs get copied.
+ */
+struct WormholeSession *wormhole_send_many_files(const char *const *file_paths,
+                                                 size_t length,
+                                                 const char *new_name,
+                                                 size_t code_length,
+                                                 const struct WormholeSendManyLimits *limits,
+                                                 const struct WormholeOptions *options,
+                                                 const struct WormholeSendManyCallbacks *callbacks);
+
+/**
+ * # 在后台接收文件
+ *
+ * Like `receive_files`, but returns right away while the transfer continues in the background.
+ * Free the session with `wormhole_session_free` once you are done. `options` and `callbacks`
+ * may be null, the structs get copied.
+ */
+struct WormholeSession *wormhole_receive_files(const char *wormhole_code,
+                                               const char *save_path,
+                                               const struct WormholeOptions *options,
+                                               const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 获取对方的报价
+ *
+ * Connects to the sender and blocks until it offered its files, without receiving anything yet.
+ * Gives up after `timeout_seconds`, or waits as long as it takes if that is 0. Returns null on
+ * failure, with `Cancelled` as error after the timeout. Otherwise, look at the offer with `wormhole_request_file_count` and
+ * `wormhole_request_file`, answer it with `wormhole_request_accept_all`, `wormhole_request_accept`
+ * or `wormhole_request_reject`, and free it with `wormhole_request_free` in any case.
+ * `options` may be null. The code may also be a `wormhole-transfer:` link, like for
+ * `receive_files`.
+ */
+struct WormholeRequest *wormhole_receive_request(const char *wormhole_code,
+                                                 const struct WormholeOptions *options,
+                                                 uint64_t timeout_seconds);
+
+/**
+ * # 报价中的文件数量
+ *
+ * Returns 0 if `request` is null.
+ */
+size_t wormhole_request_file_count(const struct WormholeRequest *request);
+
+/**
+ * # 获取报价中的文件
+ *
+ * The path belongs to the request and is valid until `wormhole_request_free`. Out of range
+ * indices and a null `request` return a null path.
+ */
+struct WormholeOfferFile wormhole_request_file(const struct WormholeRequest *request, size_t index);
+
+/**
+ * # 接受全部文件
+ *
+ * Receives everything into `save_path` in the background, like `wormhole_receive_files`.
+ * Returns null on failure, including when the request has already been answered.
+ */
+struct WormholeSession *wormhole_request_accept_all(const struct WormholeRequest *request,
+                                                    const char *save_path,
+                                                    const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 接受部分文件
+ *
+ * Receives only the given files in the background, each one into its own destination.
+ * Missing directories get created, existing files get overwritten. Accepting no files at
+ * all rejects the offer. Returns null on failure, including when the request has already
+ * been answered or a path is not part of the offer.
+ */
+struct WormholeSession *wormhole_request_accept(const struct WormholeRequest *request,
+                                                const struct WormholeAcceptFile *files,
+                                                size_t length,
+                                                const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 接收到内存
+ *
+ * Receives everything in the background without touching the disk. If `on_data` is given,
+ * every received chunk gets passed to it together with the `user_data` of `callbacks`, and
+ * nothing is kept. Otherwise, take the files out with `wormhole_session_take_file` once the
+ * session finished. Returns null on failure, including when the request has already been
+ * answered.
+ */
+struct WormholeSession *wormhole_request_accept_memory(const struct WormholeRequest *request,
+                                                       WormholeDataCallback on_data,
+                                                       const struct WormholeCallbacks *callbacks);
+
+/**
+ * # 拒绝报价
+ *
+ * Tells the sender that we don't want its files. Returns `false` on failure.
+ */
+bool wormhole_request_reject(const struct WormholeRequest *request);
+
+/**
+ * # 释放报价
+ *
+ * Frees the request. An offer that has not been answered gets dropped without telling the
+ * sender, use `wormhole_request_reject` for that. The request must not be used afterwards,
+ * but sessions started from it keep running.
+ */
+void wormhole_request_free(struct WormholeRequest *request);
+
+/**
+ * # 获取会话的代码
+ *
+ * The returned string belongs to the session and is valid until `wormhole_session_free`.
+ * Returns null if `session` is null.
+ */
+const char *wormhole_session_code(const struct WormholeSession *session);
+
+/**
+ * # 查询会话状态
+ *
+ * If the session failed, its error becomes the last error of the calling thread. A null
+ * `session` counts as failed.
+ */
+enum WormholeSessionStatus wormhole_session_status(const struct WormholeSession *session);
+
+/**
+ * # 等待会话结束
+ *
+ * Blocks until the transfer is over, and returns how it ended. If the session failed,
+ * its error becomes the last error of the calling thread. A null `session` counts as failed.
+ */
+enum WormholeSessionStatus wormhole_session_wait(const struct WormholeSession *session);
+
+/**
+ * # 取消会话
+ *
+ * Tells the peer that we are stopping and returns immediately. Use `wormhole_session_wait`
+ * to wait until that has happened. Does nothing if the transfer is already over.
+ */
+void wormhole_session_cancel(const struct WormholeSession *session);
+
+/**
+ * # 释放会话
+ *
+ * Cancels the transfer if it is still running. The session must not be used afterwards.
+ */
+void wormhole_session_free(struct WormholeSession *session);
+
+/**
+ * # 取出接收到内存的文件
+ *
+ * Takes out the file at `path`, as given by `wormhole_request_file`, after receiving with
+ * `wormhole_request_accept_memory`. The buffer belongs to the caller and must be freed with
+ * `wormhole_buffer_free`. Returns a null buffer if there is no such file, or it has already
+ * been taken out.
+ */
+struct WormholeBuffer wormhole_session_take_file(const struct WormholeSession *session,
+                                                 const char *path);
+
+/**
+ * # 释放缓冲区
+ *
+ * Frees buffers returned by the library. A null buffer is fine.
+ */
+void wormhole_buffer_free(struct WormholeBuffer buffer);
+
+/**
+ * # 生成 SVG 二维码
+ *
+ * Renders the `wormhole-transfer:` link for `code` as SVG image, with `module_size` pixels per
+ * QR module. A custom rendezvous server from `options` becomes part of the link, `options` may
+ * be null. Returns null on failure, free the string with `wormhole_string_free`.
+ */
+char *wormhole_qr_svg(const char *code,
+                      const struct WormholeOptions *options,
+                      uint32_t module_size);
+
+/**
+ * # 生成 PNG 二维码
+ *
+ * Like `wormhole_qr_svg`, but as grayscale PNG image. Returns a null buffer on failure, free the
+ * buffer with `wormhole_buffer_free`.
+ */
+struct WormholeBuffer wormhole_qr_png(const char *code,
+                                      const struct WormholeOptions *options,
+                                      uint32_t module_size);
+
+/**
+ * # 生成终端二维码
+ *
+ * Like `wormhole_qr_svg`, but drawn with Unicode half blocks, one line per two rows of modules.
+ * The blocks are the dark modules, set `invert` for light text on a dark background.
+ */
+char *wormhole_qr_text(const char *code, const struct WormholeOptions *options, bool invert);
+
+/**
+ * # 创建代码补全器
+ *
+ * Completes codes while the user types them, for example in a code entry field. The password
+ * gets completed from the wordlist right away, `code_length` is the number of words to expect.
+ * The nameplate only gets completed after `wormhole_completer_refresh`. `options` may be null.
+ * Returns null on failure, free the completer with `wormhole_completer_free`. A completer must
+ * not be used from several threads at the same time.
+ */
+struct WormholeCompleter *wormhole_completer_new(const struct WormholeOptions *options,
+                                                 size_t code_length);
+
+/**
+ * # 刷新名牌列表
+ *
+ * Blocks while fetching the nameplates that are currently in use from the rendezvous server.
+ * Call it again whenever the list might be outdated, for example when the entry field gets
+ * focused. Returns false on failure, the completer keeps working with the old list then.
+ */
+bool wormhole_completer_refresh(struct WormholeCompleter *completer);
+
+/**
+ * # 补全代码
+ *
+ * Completes the partially entered `prefix` and returns the number of completions, which are
+ * full codes up to the completed part. Get them with `wormhole_completer_get`. Returns 0 if
+ * `completer` or `prefix` is null.
+ */
+size_t wormhole_completer_complete(struct WormholeCompleter *completer, const char *prefix);
+
+/**
+ * # 获取补全结果
+ *
+ * The string belongs to the completer and is valid until the next `wormhole_completer_complete`
+ * or `wormhole_completer_free`. Out of range indices and a null `completer` return null.
+ */
+const char *wormhole_completer_get(const struct WormholeCompleter *completer, size_t index);
+
+/**
+ * # 释放代码补全器
+ *
+ * A null completer is fine.
+ */
+void wormhole_completer_free(struct WormholeCompleter *completer);
+
+/**
+ * # 计算词表的熵
+ *
+ * Returns how many bits of entropy each word of a code adds with the wordlist of `options`,
+ * for telling users how long their codes should be. `options` may be null for the PGP word
+ * list. Returns 0 on failure.
+ */
+double wormhole_wordlist_entropy(const struct WormholeOptions *options);
+
+/**
+ * # 获取最近的错误类型
+ *
+ * Returns the category of the last error on the calling thread, or `Ok` if there was none.
+ * Successful calls don't reset it, so only check it after a call failed.
+ */
+enum WormholeErrorCode wormhole_last_error(void);
+
+/**
+ * # 获取最近的错误信息
+ *
+ * Returns a human readable description of the last error on the calling thread, or null if
+ * there was none. The string stays valid until the next failing call on the same thread.
+ */
+const char *wormhole_last_error_message(void);
+
+/**
+ * # 释放字符串
+ *
+ * Frees strings that the library returned as `char *`. Null is fine.
+ */
+void wormhole_string_free(char *string);
+
+/**
+ * # 获取库的版本
+ *
+ * The returned string is static and must not be freed.
+ */
+const char *wormhole_version(void);
+
+/**
+ * # 查询支持的协议功能
+ */
+struct WormholeAbilities wormhole_abilities(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* WORMHOLE_H */
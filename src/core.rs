@@ -1,13 +1,15 @@
+mod completion;
 pub(super) mod key;
 pub mod rendezvous;
 mod server_messages;
 #[cfg(test)]
 mod test;
-mod wordlist;
+pub mod wordlist;
 
 use serde_derive::{Deserialize, Serialize};
 use std::borrow::Cow;
 
+pub use self::completion::CodeCompleter;
 use self::rendezvous::*;
 pub(self) use self::server_messages::EncryptedMessage;
 use log::*;
@@ -44,6 +46,17 @@ pub enum WormholeError {
     Crypto,
     #[error("Nameplate is unclaimed: {}", _0)]
     UnclaimedNameplate(Nameplate),
+    #[error("Invalid code")]
+    InvalidCode(
+        #[from]
+        #[source]
+        CodeParseError,
+    ),
+    #[error(
+        "The other side uses the wordlist '{}', which we don't have. Both sides need to select the same one",
+        _0
+    )]
+    UnknownWordlist(String),
 }
 
 impl WormholeError {
@@ -111,7 +124,7 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
     /// # Arguments
     ///
     /// * `config`: Application configuration
-    /// * `code_length`: number of words used for the password. The words are taken from the default wordlist.
+    /// * `code_length`: number of words used for the password. The words are taken from the wordlist of the `config`.
     ///
     /// # Examples
     ///
@@ -123,11 +136,8 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
     /// # Ok(()) })}
     /// ```
     pub async fn create(config: AppConfig<V>, code_length: usize) -> Result<Self, WormholeError> {
-        Self::create_with_password(
-            config,
-            &wordlist::default_wordlist(code_length).choose_words(),
-        )
-        .await
+        let password = config.code_wordlist(code_length).choose_words();
+        Self::create_with_password(config, &password).await
     }
 
     /// Create a connection to a mailbox which is configured with a `Code` containing the nameplate and the given password.
@@ -150,8 +160,8 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         config: AppConfig<V>,
         password: &str,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
-            RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        check_password(password, config.wordlist.as_deref())?;
+        let (mut server, welcome) = RendezvousServer::connect_with_config(&config).await?;
         let (nameplate, mailbox) = server.allocate_claim_open().await?;
         let code = Code::new(&nameplate, password);
 
@@ -173,6 +183,8 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
     /// * `allocate`: `true`: Allocates a `Nameplate` if it does not exist.
     ///               `false`: The call fails with a `WormholeError::UnclaimedNameplate` when the `Nameplate` does not exist.
     ///
+    /// The code is checked before connecting to the server, an invalid one fails with `WormholeError::InvalidCode`.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -188,8 +200,8 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         code: Code,
         allocate: bool,
     ) -> Result<Self, WormholeError> {
-        let (mut server, welcome) =
-            RendezvousServer::connect(&config.id, &config.rendezvous_url).await?;
+        let code = Code::parse_checked(&code, config.wordlist.as_deref())?;
+        let (mut server, welcome) = RendezvousServer::connect_with_config(&config).await?;
         let nameplate = code.nameplate();
         if !allocate {
             let nameplates = server.list_nameplates().await?;
@@ -209,6 +221,74 @@ impl<V: serde::Serialize + Send + Sync + 'static> MailboxConnection<V> {
         })
     }
 
+    /// Create a mailbox on the local network, without a rendezvous server.
+    ///
+    /// The nameplate is chosen randomly and announced via multicast once [`Wormhole::connect`]
+    /// waits for the peer. See [`rendezvous::lan`] for how this works.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Application configuration, the rendezvous server in it is not used
+    /// * `code_length`: number of words used for the password. The words are taken from the wordlist of the `config`.
+    /// * `lan_config`: How to find the peer on the local network
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    pub async fn create_lan(
+        config: AppConfig<V>,
+        code_length: usize,
+        lan_config: &rendezvous::lan::LanConfig,
+    ) -> Result<Self, WormholeError> {
+        use rand::Rng;
+
+        let nameplate = Nameplate(rand::thread_rng().gen_range(1..1000).to_string());
+        let (server, mailbox) =
+            RendezvousServer::listen_lan(&config, nameplate.clone(), lan_config).await?;
+        let code = Code::new(
+            &nameplate,
+            &config.code_wordlist(code_length).choose_words(),
+        );
+
+        Ok(MailboxConnection {
+            config,
+            server,
+            mailbox,
+            code,
+            welcome: None,
+        })
+    }
+
+    /// Connect to a mailbox on the local network, without a rendezvous server.
+    ///
+    /// This waits for the nameplate of the `code` to be announced by a peer, and fails with a
+    /// `WormholeError::UnclaimedNameplate` if that doesn't happen in time.
+    ///
+    /// # Arguments
+    ///
+    /// * `config`: Application configuration, the rendezvous server in it is not used
+    /// * `code`: The `Code` the other side got from [`MailboxConnection::create_lan`]
+    /// * `lan_config`: How to find the peer on the local network
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    pub async fn connect_lan(
+        config: AppConfig<V>,
+        code: Code,
+        lan_config: &rendezvous::lan::LanConfig,
+    ) -> Result<Self, WormholeError> {
+        let code = Code::parse_checked(&code, config.wordlist.as_deref())?;
+        let nameplate = code.nameplate();
+        let Some((server, mailbox)) =
+            RendezvousServer::connect_lan(&config, nameplate.clone(), lan_config).await?
+        else {
+            return Err(WormholeError::UnclaimedNameplate(nameplate));
+        };
+
+        Ok(MailboxConnection {
+            config,
+            server,
+            mailbox,
+            code,
+            welcome: None,
+        })
+    }
+
     /// Shut down the connection to the mailbox
     ///
     /// # Arguments
@@ -264,6 +344,14 @@ pub struct Wormhole {
      * (e.g. by the file transfer API).
      */
     pub peer_version: serde_json::Value,
+    /** Wormhole-level abilities the other side advertised in its versions message */
+    peer_abilities: Vec<String>,
+    /** How the other side can establish dilated connections, if it supports dilation at all */
+    #[cfg(feature = "dilation")]
+    peer_dilation_abilities: Option<crate::transit::Abilities>,
+    /** Dilation messages that arrived before the wormhole got dilated */
+    #[cfg(feature = "dilation")]
+    dilation_messages: std::collections::VecDeque<EncryptedMessage>,
 }
 
 impl Wormhole {
@@ -353,18 +441,38 @@ impl Wormhole {
         /* Send versions message */
         let mut versions = key::VersionsMessage::new();
         versions.set_app_versions(serde_json::to_value(&config.app_version).unwrap());
+        versions.set_wordlist(config.wordlist_id());
+        #[cfg(feature = "dilation")]
+        versions.add_dilation_ability();
         let (version_phase, version_msg) = key::build_version_msg(server.side(), &key, &versions);
         server.send_peer_message(version_phase, version_msg).await?;
         let peer_version = server.next_peer_message_some().await?;
 
         /* Handle received message */
-        let versions: key::VersionsMessage = peer_version
-            .decrypt(&key)
-            .ok_or(WormholeError::PakeFailed)
-            .and_then(|plaintext| {
-                serde_json::from_slice(&plaintext).map_err(WormholeError::ProtocolJson)
-            })?;
+        let Some(plaintext) = peer_version.decrypt(&key) else {
+            /* Release the nameplate, so that it can be claimed again (e.g. when sending to many) */
+            let _ = server.shutdown(Mood::Scared).await;
+            return Err(WormholeError::PakeFailed);
+        };
+        let versions: key::VersionsMessage =
+            serde_json::from_slice(&plaintext).map_err(WormholeError::ProtocolJson)?;
+        /* Other implementations don't send it, they only know the default list */
+        if let Some(wordlist) = versions.wordlist.filter(|id| !config.knows_wordlist(id)) {
+            let _ = server.shutdown(Mood::Errory).await;
+            return Err(WormholeError::UnknownWordlist(wordlist));
+        }
 
+        #[cfg(feature = "dilation")]
+        let peer_dilation_abilities = (versions
+            .abilities
+            .iter()
+            .any(|ability| ability == crate::dilation::ABILITY)
+            || versions
+                .can_dilate
+                .iter()
+                .any(|version| version == crate::dilation::VERSION))
+        .then(|| versions.dilation_abilities.unwrap_or_default());
+        let peer_abilities = versions.abilities;
         let peer_version = versions.app_versions;
 
         if server.needs_nameplate_release() {
@@ -382,14 +490,14 @@ impl Wormhole {
             verifier: Box::new(key::derive_verifier(&key)),
             our_version: Box::new(config.app_version),
             peer_version,
+            peer_abilities,
+            #[cfg(feature = "dilation")]
+            peer_dilation_abilities,
+            #[cfg(feature = "dilation")]
+            dilation_messages: Default::default(),
         })
     }
 
-    /** TODO */
-    pub async fn connect_with_seed() {
-        todo!()
-    }
-
     /** Send an encrypted message to peer */
     pub async fn send(&mut self, plaintext: Vec<u8>) -> Result<(), WormholeError> {
         let phase_string = Phase::numeric(self.phase);
@@ -427,8 +535,17 @@ impl Wormhole {
                 None => continue,
             };
             if peer_message.phase.to_num().is_none() {
-                // TODO: log and ignore, for future expansion
-                todo!("log and ignore, for future expansion");
+                #[cfg(feature = "dilation")]
+                if peer_message.phase.is_dilation() {
+                    /* The peer started dilating before we did, keep them for later */
+                    self.dilation_messages.push_back(peer_message);
+                    continue;
+                }
+                log::warn!(
+                    "Ignoring message with unknown phase '{}'",
+                    peer_message.phase
+                );
+                continue;
             }
 
             // TODO maybe reorder incoming messages by phase numeral?
@@ -483,6 +600,94 @@ impl Wormhole {
     pub fn key(&self) -> &key::Key<key::WormholeKey> {
         &self.key
     }
+
+    /**
+     * The wormhole-level abilities advertised by the other side, like the one for dilation.
+     *
+     * Unlike the [`peer_version`](Self::peer_version), these are independent of the [`AppID`].
+     */
+    pub fn peer_abilities(&self) -> &[String] {
+        &self.peer_abilities
+    }
+
+    /**
+     * Dilate this wormhole
+     *
+     * Forwards to [`dilation::dilate`](crate::dilation::dilate).
+     */
+    #[cfg(feature = "dilation")]
+    #[deprecated(
+        since = "0.7.0",
+        note = "please use 'dilation::dilate(wormhole, ...)' instead"
+    )]
+    pub async fn connect_with_seed(
+        self,
+        transit_abilities: crate::transit::Abilities,
+        relay_hints: Vec<crate::transit::RelayHint>,
+    ) -> Result<crate::dilation::DilatedWormhole, crate::dilation::DilationError> {
+        crate::dilation::dilate(self, transit_abilities, relay_hints).await
+    }
+
+    /** Our side identifier on the rendezvous server */
+    #[cfg(feature = "dilation")]
+    pub(crate) fn side(&self) -> &str {
+        &self.server.side().0 .0
+    }
+
+    /**
+     * How the other side can establish dilated connections
+     *
+     * `None` if it does not support dilation.
+     */
+    #[cfg(feature = "dilation")]
+    pub(crate) fn peer_dilation_abilities(&self) -> Option<crate::transit::Abilities> {
+        self.peer_dilation_abilities
+    }
+
+    /** Send an encrypted message to the peer using a dilation phase */
+    #[cfg(feature = "dilation")]
+    pub(crate) async fn send_dilation_message(
+        &mut self,
+        phase: u64,
+        plaintext: &[u8],
+    ) -> Result<(), WormholeError> {
+        let phase = Phase::dilation(phase);
+        let data_key = key::derive_phase_key(self.server.side(), &self.key, &phase);
+        let (_nonce, encrypted) = key::encrypt_data(&data_key, plaintext);
+        self.server.send_peer_message(phase, encrypted).await?;
+        Ok(())
+    }
+
+    /**
+     * Receive the next dilation message from the peer
+     *
+     * Messages with a numeric phase are dropped, because a dilated wormhole is not used
+     * for application messages anymore.
+     */
+    #[cfg(feature = "dilation")]
+    pub(crate) async fn receive_dilation_message(&mut self) -> Result<Vec<u8>, WormholeError> {
+        loop {
+            let peer_message = match self.dilation_messages.pop_front() {
+                Some(peer_message) => peer_message,
+                None => match self.server.next_peer_message().await? {
+                    Some(peer_message) => peer_message,
+                    None => continue,
+                },
+            };
+            if !peer_message.phase.is_dilation() {
+                log::warn!(
+                    "Ignoring message with phase '{}' on a dilated wormhole",
+                    peer_message.phase
+                );
+                continue;
+            }
+
+            let decrypted_message = peer_message
+                .decrypt(&self.key)
+                .ok_or(WormholeError::Crypto)?;
+            return Ok(decrypted_message);
+        }
+    }
 }
 
 // the serialized forms of these variants are part of the wire protocol, so
@@ -512,14 +717,59 @@ pub enum Mood {
  *
  * See [`crate::transfer::APP_CONFIG`], which entails
  */
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct AppConfig<V> {
     pub id: AppID,
     pub rendezvous_url: Cow<'static, str>,
     pub app_version: V,
+    /// TLS settings for `wss://` rendezvous servers
+    tls: rendezvous::tls::TlsConfig,
+    /// How to answer the rendezvous server's permission requests, if not the default way
+    permission_provider: Option<std::sync::Arc<dyn rendezvous::PermissionProvider>>,
+    /// The words for generating codes, and for completing and checking entered ones. `None`
+    /// is the PGP word list. Codes from the other side are accepted if their words are all
+    /// from this list or all from one of the [`wordlist::BUNDLED_WORDLISTS`]. The other side
+    /// must use one of these lists as well.
+    wordlist: Option<std::sync::Arc<wordlist::Wordlist>>,
 }
 
+/* Manual impl because of the `dyn` trait object. Two providers are equal if they are the same object. */
+impl<V: PartialEq> PartialEq for AppConfig<V> {
+    fn eq(&self, other: &Self) -> bool {
+        let provider_ptr = |config: &Self| {
+            config
+                .permission_provider
+                .as_ref()
+                .map(|provider| std::sync::Arc::as_ptr(provider) as *const ())
+        };
+        self.id == other.id
+            && self.rendezvous_url == other.rendezvous_url
+            && self.app_version == other.app_version
+            && self.tls == other.tls
+            && provider_ptr(self) == provider_ptr(other)
+            && self.wordlist == other.wordlist
+    }
+}
+
+impl<V: Eq> Eq for AppConfig<V> {}
+
 impl<V> AppConfig<V> {
+    /**
+     * A configuration with default settings for everything else
+     *
+     * Use the builder methods to change them, e.g. [`tls`](Self::tls).
+     */
+    pub const fn new(id: AppID, rendezvous_url: Cow<'static, str>, app_version: V) -> Self {
+        Self {
+            id,
+            rendezvous_url,
+            app_version,
+            tls: rendezvous::tls::TlsConfig::new(),
+            permission_provider: None,
+            wordlist: None,
+        }
+    }
+
     pub fn id(mut self, id: AppID) -> Self {
         self.id = id;
         self
@@ -529,6 +779,47 @@ impl<V> AppConfig<V> {
         self.rendezvous_url = rendezvous_url;
         self
     }
+
+    pub fn tls(mut self, tls: rendezvous::tls::TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn permission_provider(
+        mut self,
+        permission_provider: impl rendezvous::PermissionProvider + 'static,
+    ) -> Self {
+        self.permission_provider = Some(std::sync::Arc::new(permission_provider));
+        self
+    }
+
+    pub fn wordlist(mut self, wordlist: wordlist::Wordlist) -> Self {
+        self.wordlist = Some(std::sync::Arc::new(wordlist));
+        self
+    }
+
+    /** The [`id`](wordlist::Wordlist::id) of our wordlist, which we tell the other side */
+    fn wordlist_id(&self) -> String {
+        match &self.wordlist {
+            Some(wordlist) => wordlist.id(),
+            None => wordlist::DEFAULT_WORDLIST.into(),
+        }
+    }
+
+    /** Whether we know the wordlist the other side told us about */
+    fn knows_wordlist(&self, id: &str) -> bool {
+        id == self.wordlist_id() || wordlist::BUNDLED_WORDLISTS.contains(&id)
+    }
+
+    /** The wordlist for generating codes with `num_words` words */
+    fn code_wordlist(&self, num_words: usize) -> wordlist::Wordlist {
+        let mut wordlist = match &self.wordlist {
+            Some(wordlist) => wordlist::Wordlist::clone(wordlist),
+            None => wordlist::default_wordlist(num_words),
+        };
+        wordlist.num_words = num_words;
+        wordlist
+    }
 }
 
 impl<V: serde::Serialize> AppConfig<V> {
@@ -633,6 +924,14 @@ impl Phase {
     pub fn is_pake(&self) -> bool {
         self == &Self::PAKE
     }
+    #[cfg(feature = "dilation")]
+    pub fn dilation(phase: u64) -> Self {
+        Phase(format!("dilate-{}", phase).into())
+    }
+    #[cfg(feature = "dilation")]
+    pub fn is_dilation(&self) -> bool {
+        self.0.starts_with("dilate-")
+    }
     pub fn to_num(&self) -> Option<u64> {
         self.0.parse().ok()
     }
@@ -656,6 +955,17 @@ impl Nameplate {
     }
 }
 
+impl std::str::FromStr for Nameplate {
+    type Err = CodeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(CodeParseError::InvalidNameplate(s.into()));
+        }
+        Ok(Nameplate::new(s))
+    }
+}
+
 impl From<Nameplate> for String {
     fn from(value: Nameplate) -> Self {
         value.0
@@ -667,6 +977,9 @@ impl From<Nameplate> for String {
  * The part until the first dash is called the "nameplate" and is purely numeric.
  * The rest is the password and may be arbitrary, although dash-joining words from
  * a wordlist is a common convention.
+ *
+ * Codes entered by users should be parsed with [`str::parse`] or [`Code::parse_with_wordlist`],
+ * which catch typos before they cost a nameplate (see [`CodeParseError`]).
  */
 #[derive(PartialEq, Eq, Clone, Debug, derive_more::Display, derive_more::Deref)]
 #[display(fmt = "{}", _0)]
@@ -677,14 +990,102 @@ impl Code {
         Code(format!("{}-{}", nameplate, password))
     }
 
+    /** Nameplate and password. The password is empty if the code has no dash. */
     pub fn split(&self) -> (Nameplate, String) {
-        let mut iter = self.0.splitn(2, '-');
-        let nameplate = Nameplate::new(iter.next().unwrap());
-        let password = iter.next().unwrap();
-        (nameplate, password.to_string())
+        let (nameplate, password) = self.0.split_once('-').unwrap_or((&self.0, ""));
+        (Nameplate::new(nameplate), password.to_string())
     }
 
     pub fn nameplate(&self) -> Nameplate {
         Nameplate::new(self.0.split('-').next().unwrap())
     }
+
+    /** Like [`str::parse`], but also accepts codes made of the words from `wordlist` */
+    pub fn parse_with_wordlist(
+        code: &str,
+        wordlist: &wordlist::Wordlist,
+    ) -> Result<Self, CodeParseError> {
+        Self::parse_checked(code, Some(wordlist))
+    }
+
+    /** Like [`str::parse`], but also accepts codes made of the words from the wordlist of `config` */
+    pub fn parse_with_config<V>(code: &str, config: &AppConfig<V>) -> Result<Self, CodeParseError> {
+        Self::parse_checked(code, config.wordlist.as_deref())
+    }
+
+    fn parse_checked(
+        code: &str,
+        wordlist: Option<&wordlist::Wordlist>,
+    ) -> Result<Self, CodeParseError> {
+        let (nameplate, password) = code.split_once('-').unwrap_or((code, ""));
+        let nameplate: Nameplate = nameplate.parse()?;
+        check_password(password, wordlist)?;
+        Ok(Code::new(&nameplate, password))
+    }
+}
+
+impl std::str::FromStr for Code {
+    type Err = CodeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_checked(s, None)
+    }
+}
+
+/** Passwords shorter than this are too easy to guess, even with only one try */
+pub const MIN_PASSWORD_LENGTH: usize = 4;
+
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CodeParseError {
+    #[error(
+        "The nameplate must be a number, but was '{}'. Codes look like 4-purple-sausages",
+        _0
+    )]
+    InvalidNameplate(String),
+    #[error("The code is missing the password after the nameplate")]
+    MissingPassword,
+    #[error(
+        "The password must be at least {} characters long",
+        MIN_PASSWORD_LENGTH
+    )]
+    TooShort,
+    #[error("'{}' is not in the wordlist, is there a typo?", _0)]
+    UnknownWord(String),
+}
+
+/**
+ * Check a password against the rules of [`CodeParseError`]
+ *
+ * Passwords don't have to come from a wordlist, so entirely custom ones are fine. But if some
+ * of the words are from a wordlist and others aren't, the latter are most likely typos. Next to
+ * the given `wordlist`, all bundled ones are accepted, so that both sides don't need to use the
+ * same one. Which lists the sides use gets checked once they are connected, see
+ * [`WormholeError::UnknownWordlist`].
+ */
+fn check_password(
+    password: &str,
+    wordlist: Option<&wordlist::Wordlist>,
+) -> Result<(), CodeParseError> {
+    if password.is_empty() {
+        return Err(CodeParseError::MissingPassword);
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(CodeParseError::TooShort);
+    }
+    let words = password.split('-').collect::<Vec<_>>();
+    let mut unknown_word = None;
+    for wordlist in wordlist.into_iter().chain(wordlist::bundled_wordlists()) {
+        match words.iter().find(|word| !wordlist.contains(word)) {
+            None => return Ok(()),
+            Some(word) if unknown_word.is_none() && words.iter().any(|w| wordlist.contains(w)) => {
+                unknown_word = Some(word.to_string());
+            },
+            Some(_) => {},
+        }
+    }
+    match unknown_word {
+        Some(word) => Err(CodeParseError::UnknownWord(word)),
+        None => Ok(()),
+    }
 }
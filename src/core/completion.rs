@@ -0,0 +1,147 @@
+//! Tab completion for entering codes
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{transfer::APP_CONFIG, CodeCompleter};
+//!
+//! let mut completer = CodeCompleter::new(2);
+//! completer.fetch_nameplates(&APP_CONFIG).await?;
+//! for completion in completer.complete("4-ar") {
+//!     println!("{}", completion);
+//! }
+//! # Ok(()) })}
+//! ```
+
+use super::{rendezvous::RendezvousServer, wordlist, AppConfig, Mood, Nameplate, WormholeError};
+
+/**
+ * Completes partially entered codes
+ *
+ * The nameplate gets completed from the nameplates that are currently in use on the rendezvous
+ * server, the password from the wordlist. The list of nameplates is empty until it gets fetched
+ * with [`CodeCompleter::fetch_nameplates`], which may be done again whenever it might be outdated.
+ */
+#[derive(Debug)]
+pub struct CodeCompleter {
+    wordlist: wordlist::Wordlist,
+    nameplates: Vec<Nameplate>,
+}
+
+impl CodeCompleter {
+    /**
+     * `code_length` is the expected number of words in the password. A dash gets appended to the
+     * completed words until there are that many.
+     */
+    pub fn new(code_length: usize) -> Self {
+        Self {
+            wordlist: wordlist::default_wordlist(code_length),
+            nameplates: Vec::new(),
+        }
+    }
+
+    /** Like [`new`](Self::new), but with the wordlist of `config` */
+    pub fn from_config<V>(config: &AppConfig<V>, code_length: usize) -> Self {
+        let completer = Self::new(code_length);
+        match &config.wordlist {
+            Some(wordlist) => completer.with_wordlist(wordlist::Wordlist::clone(wordlist)),
+            None => completer,
+        }
+    }
+
+    /** Complete the password from `wordlist` instead of the PGP word list */
+    pub fn with_wordlist(mut self, mut wordlist: wordlist::Wordlist) -> Self {
+        wordlist.num_words = self.wordlist.num_words;
+        self.wordlist = wordlist;
+        self
+    }
+
+    /** Use these nameplates instead of the ones from the rendezvous server */
+    pub fn with_nameplates(mut self, nameplates: Vec<Nameplate>) -> Self {
+        self.nameplates = nameplates;
+        self
+    }
+
+    /** Replace the known nameplates with the ones currently in use on the rendezvous server */
+    pub async fn fetch_nameplates<V>(
+        &mut self,
+        config: &AppConfig<V>,
+    ) -> Result<(), WormholeError> {
+        let (mut server, _welcome) = RendezvousServer::connect_with_config(config).await?;
+        let nameplates = server.list_nameplates().await;
+        server.shutdown(Mood::Happy).await?;
+        self.nameplates = nameplates?;
+        Ok(())
+    }
+
+    pub fn nameplates(&self) -> &[Nameplate] {
+        &self.nameplates
+    }
+
+    /**
+     * All completions for `prefix`, sorted
+     *
+     * Each completion is the full code up to the end of the completed part. The completion of a
+     * nameplate or a word that is not the last one ends with a dash, so that the user can
+     * continue typing right away.
+     */
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        match prefix.split_once('-') {
+            None => {
+                let mut completions = self
+                    .nameplates
+                    .iter()
+                    .filter(|nameplate| nameplate.starts_with(prefix))
+                    .map(|nameplate| format!("{}-", nameplate))
+                    .collect::<Vec<_>>();
+                completions.sort();
+                completions.dedup();
+                completions
+            },
+            Some((nameplate, password)) => self
+                .wordlist
+                .get_completions(password)
+                .into_iter()
+                .map(|password| format!("{}-{}", nameplate, password))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let completer = CodeCompleter::new(2).with_nameplates(vec![
+            Nameplate::new("4"),
+            Nameplate::new("42"),
+            Nameplate::new("7"),
+        ]);
+        assert_eq!(completer.complete(""), vec!["4-", "42-", "7-"]);
+        assert_eq!(completer.complete("4"), vec!["4-", "42-"]);
+        assert_eq!(completer.complete("42"), vec!["42-"]);
+        assert!(completer.complete("5").is_empty());
+
+        assert_eq!(completer.complete("4-armis"), vec!["4-armistice-"]);
+        assert_eq!(completer.complete("4-armistice-").len(), 256);
+        assert_eq!(
+            completer.complete("42-armistice-bab"),
+            vec!["42-armistice-baboon"]
+        );
+        assert!(completer.complete("4-xyz").is_empty());
+
+        let completer = CodeCompleter::new(3);
+        assert!(completer.complete("4").is_empty());
+        assert_eq!(
+            completer.complete("4-armistice-bab"),
+            vec!["4-armistice-baboon-"]
+        );
+
+        let completer =
+            CodeCompleter::new(3).with_wordlist(wordlist::Wordlist::bundled("german").unwrap());
+        assert_eq!(completer.complete("4-apf"), vec!["4-apfel-"]);
+        assert_eq!(completer.complete("4-apfel-adl"), vec!["4-apfel-adler-"]);
+        assert!(completer.complete("4-armis").is_empty());
+    }
+}
@@ -106,6 +106,21 @@ pub fn make_pake(password: &str, appid: &AppID) -> (Spake2<Ed25519Group>, Vec<u8
 pub struct VersionsMessage {
     #[serde(default)]
     pub abilities: Vec<String>,
+    /** The versions of the Dilation protocol that are supported */
+    #[cfg(feature = "dilation")]
+    #[serde(default, rename = "can-dilate", skip_serializing_if = "Vec::is_empty")]
+    pub can_dilate: Vec<String>,
+    /** How the dilated connections may be established */
+    #[cfg(feature = "dilation")]
+    #[serde(
+        default,
+        rename = "dilation-abilities",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dilation_abilities: Option<crate::transit::Abilities>,
+    /** The [`id`](crate::wordlist::Wordlist::id) of the wordlist that codes are made of */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wordlist: Option<String>,
     #[serde(default)]
     pub app_versions: serde_json::Value,
     // resume: Option<WormholeResume>,
@@ -120,6 +135,17 @@ impl VersionsMessage {
         self.app_versions = versions;
     }
 
+    pub fn set_wordlist(&mut self, id: String) {
+        self.wordlist = Some(id);
+    }
+
+    #[cfg(feature = "dilation")]
+    pub fn add_dilation_ability(&mut self) {
+        self.abilities.push(crate::dilation::ABILITY.into());
+        self.can_dilate.push(crate::dilation::VERSION.into());
+        self.dilation_abilities = Some(crate::transit::Abilities::ALL_ABILITIES);
+    }
+
     // pub fn add_resume_ability(&mut self, _resume: ()) {
     //     self.abilities.push("resume-v1".into())
     // }
@@ -7,10 +7,23 @@ use async_tungstenite::tungstenite as ws2;
 use futures::prelude::*;
 use std::collections::VecDeque;
 
+#[cfg(all(feature = "lan", not(target_family = "wasm")))]
+pub mod lan;
+#[cfg(all(feature = "server", not(target_family = "wasm")))]
+pub mod server;
+pub mod tls;
+
+use self::tls::{TlsConfig, TlsError};
 use crate::core::{
-    server_messages::{InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission},
-    AppID, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
+    server_messages::{InboundMessage, OutboundMessage, WelcomeMessage},
+    AppConfig, AppID, EncryptedMessage, Mailbox, Mood, MySide, Nameplate, Phase,
 };
+use std::sync::Arc;
+
+pub use crate::core::server_messages::{HashcashPermission, PermissionRequired, SubmitPermission};
+
+/// How often we try to get back a lost connection to the rendezvous server before giving up
+const RECONNECT_ATTEMPTS: u32 = 10;
 
 /// Some rendezvous server you might use.
 ///
@@ -39,6 +52,12 @@ pub enum RendezvousError {
         _0
     )]
     Login(Vec<String>),
+    #[error("Invalid TLS settings for the rendezvous server")]
+    Tls(
+        #[from]
+        #[source]
+        TlsError,
+    ),
     #[cfg(not(target_family = "wasm"))]
     #[error("Websocket IO error")]
     IO(
@@ -53,6 +72,13 @@ pub enum RendezvousError {
         #[source]
         ws_stream_wasm::WsErr,
     ),
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    #[error("IO error on the local network connection")]
+    Lan(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
 }
 
 impl RendezvousError {
@@ -70,6 +96,111 @@ impl RendezvousError {
     pub(self) fn server(error: impl Into<Box<str>>) -> Self {
         Self::Server(error.into())
     }
+
+    /** The connection to the server broke, but might come back */
+    fn is_connection_lost(&self) -> bool {
+        #[cfg(not(target_family = "wasm"))]
+        {
+            use ws2::error::ProtocolError;
+            matches!(
+                self,
+                Self::IO(
+                    ws2::Error::Io(_)
+                        | ws2::Error::ConnectionClosed
+                        | ws2::Error::AlreadyClosed
+                        | ws2::Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
+                )
+            )
+        }
+        #[cfg(target_family = "wasm")]
+        {
+            matches!(
+                self,
+                Self::IO(
+                    ws_stream_wasm::WsErr::ConnectionNotOpen
+                        | ws_stream_wasm::WsErr::ConnectionFailed { .. }
+                )
+            )
+        }
+    }
+}
+
+/**
+ * Answers a rendezvous server's request for permission to use it
+ *
+ * Servers may ask clients to prove something before letting them in, by listing one or more
+ * methods in their welcome message. Out of the box, we only speak `hashcash` and `none` (see
+ * [`DefaultPermissionProvider`]). Implement this to support other methods, and pass it to
+ * [`AppConfig::permission_provider`](crate::AppConfig::permission_provider).
+ *
+ * ```
+ * use magic_wormhole::rendezvous::{
+ *     DefaultPermissionProvider, PermissionProvider, PermissionRequired, RendezvousError,
+ *     SubmitPermission,
+ * };
+ *
+ * #[derive(Debug)]
+ * struct BearerToken(String);
+ *
+ * impl PermissionProvider for BearerToken {
+ *     fn submit_permission(
+ *         &self,
+ *         permission_required: &PermissionRequired,
+ *     ) -> Result<Option<SubmitPermission>, RendezvousError> {
+ *         if permission_required.other.contains_key("bearer") {
+ *             Ok(Some(SubmitPermission::Other {
+ *                 method: "bearer".into(),
+ *                 fields: [("token".to_string(), self.0.clone().into())]
+ *                     .into_iter()
+ *                     .collect(),
+ *             }))
+ *         } else {
+ *             DefaultPermissionProvider.submit_permission(permission_required)
+ *         }
+ *     }
+ * }
+ * ```
+ */
+pub trait PermissionProvider: std::fmt::Debug + Send + Sync {
+    /**
+     * Pick one of the offered methods and answer it
+     *
+     * Return `None` to submit nothing, which is what servers offering `none` accept. If none
+     * of the methods is supported, fail with [`RendezvousError::Login`].
+     */
+    #[allow(clippy::result_large_err)]
+    fn submit_permission(
+        &self,
+        permission_required: &PermissionRequired,
+    ) -> Result<Option<SubmitPermission>, RendezvousError>;
+}
+
+/** Solves hashcash challenges, and otherwise only gets along with servers that accept `none` */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultPermissionProvider;
+
+impl PermissionProvider for DefaultPermissionProvider {
+    fn submit_permission(
+        &self,
+        permission_required: &PermissionRequired,
+    ) -> Result<Option<SubmitPermission>, RendezvousError> {
+        match permission_required {
+            PermissionRequired {
+                hashcash: Some(hashcash),
+                ..
+            } => {
+                let token = crate::util::hashcash(hashcash.resource.clone(), hashcash.bits);
+                Ok(Some(SubmitPermission::Hashcash {
+                    stamp: token.to_string(),
+                }))
+            },
+            PermissionRequired { none: true, .. } => Ok(None),
+            PermissionRequired { other, .. } => {
+                /* We can't actually log in :/ */
+                Err(RendezvousError::Login(other.keys().cloned().collect()))
+            },
+        }
+    }
 }
 
 type MessageQueue = VecDeque<EncryptedMessage>;
@@ -90,6 +221,70 @@ struct WsConnection {
 }
 
 impl WsConnection {
+    /** Open a connection to the server and wait for its welcome */
+    async fn connect(
+        relay_url: &str,
+        tls: &TlsConfig,
+    ) -> Result<(Self, WelcomeMessage), RendezvousError> {
+        let mut connection;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            /* Boxed, because these futures are huge */
+            let (stream, _) = if tls.is_default() {
+                Box::pin(async_tungstenite::async_std::connect_async(relay_url)).await?
+            } else {
+                Box::pin(
+                    async_tungstenite::async_std::connect_async_with_tls_connector(
+                        relay_url,
+                        Some(tls.connector()?),
+                    ),
+                )
+                .await?
+            };
+            connection = WsConnection { connection: stream };
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            if !tls.is_default() {
+                tls.connector()?;
+            }
+            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
+            connection = WsConnection {
+                meta,
+                connection: stream,
+            };
+        }
+
+        let welcome = match connection.receive_message_some().await? {
+            InboundMessage::Welcome { welcome } => welcome,
+            other => {
+                return Err(RendezvousError::protocol(format!(
+                    "First message server sends must be 'welcome', but was '{}'",
+                    other
+                )))
+            },
+        };
+
+        Ok((connection, welcome))
+    }
+
+    /** Do the permission negotiation part if required */
+    async fn submit_permission(
+        &mut self,
+        permission_required: Option<PermissionRequired>,
+        permission_provider: &dyn PermissionProvider,
+    ) -> Result<(), RendezvousError> {
+        if let Some(permission_required) = permission_required {
+            if let Some(permission) = permission_provider.submit_permission(&permission_required)? {
+                self.send_message(&OutboundMessage::SubmitPermission(permission), None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
     #[cfg(not(target_family = "wasm"))]
     async fn send_message(
         &mut self,
@@ -206,7 +401,7 @@ impl WsConnection {
             .connection
             .next()
             .await
-            .expect("TODO this should always be Some")?;
+            .ok_or(ws2::Error::AlreadyClosed)??;
         match message {
             ws2::Message::Text(message_plain) => {
                 let message = serde_json::from_str(&message_plain)?;
@@ -243,7 +438,7 @@ impl WsConnection {
             .connection
             .next()
             .await
-            .expect("TODO this should always be Some");
+            .ok_or(ws_stream_wasm::WsErr::ConnectionNotOpen)?;
         match message {
             ws_stream_wasm::WsMessage::Text(message_plain) => {
                 let message = serde_json::from_str(&message_plain)?;
@@ -274,6 +469,59 @@ impl WsConnection {
     }
 }
 
+/** Where our messages go: to the rendezvous server, or directly to the peer */
+/* Not boxing the websocket, as that is what we use nearly all of the time */
+#[allow(clippy::large_enum_variant)]
+enum Connection {
+    WebSocket(WsConnection),
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    Lan(lan::LanConnection),
+}
+
+impl Connection {
+    async fn send_message(
+        &mut self,
+        message: &OutboundMessage,
+        queue: Option<&mut MessageQueue>,
+    ) -> Result<(), RendezvousError> {
+        match self {
+            Self::WebSocket(connection) => connection.send_message(message, queue).await,
+            #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+            Self::Lan(connection) => connection.send_message(message).await,
+        }
+    }
+
+    async fn receive_reply(
+        &mut self,
+        queue: Option<&mut MessageQueue>,
+    ) -> Result<RendezvousReply, RendezvousError> {
+        match self {
+            Self::WebSocket(connection) => connection.receive_reply(queue).await,
+            #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+            Self::Lan(connection) => connection.receive_reply(queue).await,
+        }
+    }
+
+    async fn receive_message(&mut self) -> Result<Option<InboundMessage>, RendezvousError> {
+        match self {
+            Self::WebSocket(connection) => connection.receive_message().await,
+            #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+            Self::Lan(connection) => connection.receive_message().await,
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), RendezvousError> {
+        match self {
+            Self::WebSocket(connection) => {
+                connection.close().await?;
+            },
+            #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+            Self::Lan(connection) => connection.close().await?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, derive_more::Display)]
 enum RendezvousReply {
     Allocated(Nameplate),
@@ -313,10 +561,38 @@ impl MailboxMachine {
     }
 }
 
+/** Everything we need to know to (re)connect to the server */
+#[derive(Debug)]
+struct ConnectionSettings {
+    appid: AppID,
+    relay_url: String,
+    tls: TlsConfig,
+    permission_provider: Arc<dyn PermissionProvider>,
+}
+
+impl ConnectionSettings {
+    fn from_config<V>(config: &AppConfig<V>) -> Self {
+        Self {
+            appid: config.id.clone(),
+            relay_url: config.rendezvous_url.to_string(),
+            tls: config.tls.clone(),
+            permission_provider: config
+                .permission_provider
+                .clone()
+                .unwrap_or_else(|| Arc::new(DefaultPermissionProvider)),
+        }
+    }
+}
+
 pub struct RendezvousServer {
-    connection: WsConnection,
+    connection: Connection,
     state: Option<MailboxMachine>,
     side: MySide,
+    /* Boxed, because the server gets moved around inside of some rather large futures */
+    settings: Box<ConnectionSettings>,
+    /* Set while the connection is broken. If a reconnection attempt gets interrupted,
+     * the next operation will start over. */
+    reconnecting: bool,
 }
 
 impl std::fmt::Debug for RendezvousServer {
@@ -324,6 +600,7 @@ impl std::fmt::Debug for RendezvousServer {
         fmt.debug_struct("RendezvousServer")
             .field("state", &self.state)
             .field("side", &self.side)
+            .field("settings", &self.settings)
             .finish()
     }
 }
@@ -339,76 +616,205 @@ impl RendezvousServer {
         appid: &AppID,
         relay_url: &str,
     ) -> Result<(Self, Option<String>), RendezvousError> {
-        let side = MySide::generate();
-        let mut connection;
+        Self::connect_with_tls(appid, relay_url, TlsConfig::new()).await
+    }
 
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let (stream, _) = async_tungstenite::async_std::connect_async(relay_url).await?;
-            connection = WsConnection { connection: stream };
-        }
+    /**
+     * Connect to the rendezvous server, using custom TLS settings
+     *
+     * See [`connect`](Self::connect). The TLS settings only apply to `wss://` URLs.
+     */
+    pub async fn connect_with_tls(
+        appid: &AppID,
+        relay_url: &str,
+        tls: TlsConfig,
+    ) -> Result<(Self, Option<String>), RendezvousError> {
+        Self::connect_with_settings(ConnectionSettings {
+            appid: appid.clone(),
+            relay_url: relay_url.to_owned(),
+            tls,
+            permission_provider: Arc::new(DefaultPermissionProvider),
+        })
+        .await
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            let (meta, stream) = ws_stream_wasm::WsMeta::connect(relay_url, None).await?;
-            connection = WsConnection {
-                meta,
-                connection: stream,
-            };
-        }
+    /**
+     * Connect to the rendezvous server of an [`AppConfig`]
+     *
+     * Unlike [`connect`](Self::connect), this takes the TLS settings and the
+     * permission provider of the configuration into account.
+     */
+    pub async fn connect_with_config<V>(
+        config: &AppConfig<V>,
+    ) -> Result<(Self, Option<String>), RendezvousError> {
+        Self::connect_with_settings(ConnectionSettings::from_config(config)).await
+    }
 
-        let welcome = match connection.receive_message_some().await? {
-            InboundMessage::Welcome { welcome } => welcome,
-            other => {
-                return Err(RendezvousError::protocol(format!(
-                    "First message server sends must be 'welcome', but was '{}'",
-                    other
-                )))
-            },
-        };
+    /**
+     * Wait for a peer on the local network instead of connecting to a server
+     *
+     * The nameplate gets announced as soon as we wait for the first message from the peer,
+     * see the [`lan`] module. The mailbox is already open.
+     */
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    pub async fn listen_lan<V>(
+        config: &AppConfig<V>,
+        nameplate: Nameplate,
+        lan_config: &lan::LanConfig,
+    ) -> Result<(Self, Mailbox), RendezvousError> {
+        let side = MySide::generate();
+        let connection =
+            lan::LanConnection::listen(&config.id, &nameplate, &side, lan_config).await?;
+        Ok(Self::with_lan_connection(
+            config, nameplate, side, connection,
+        ))
+    }
 
-        match welcome.permission_required {
-            Some(PermissionRequired {
-                hashcash: Some(hashcash),
-                ..
-            }) => {
-                let token = crate::util::hashcash(hashcash.resource, hashcash.bits);
-                connection
-                    .send_message(
-                        &OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
-                            stamp: token.to_string(),
-                        }),
-                        None,
-                    )
-                    .await?;
-            },
-            Some(PermissionRequired { none: true, .. }) => (),
-            Some(PermissionRequired { other, .. }) => {
-                /* We can't actually log in :/ */
-                return Err(RendezvousError::Login(
-                    // TODO use `into_keys` once stable and remove the `cloned`
-                    other.keys().cloned().collect(),
-                ));
-            },
-            None => (),
-        }
+    /**
+     * Find the peer announcing a nameplate on the local network and connect to it
+     *
+     * Returns `None` if nobody announced the nameplate within the discovery timeout of the
+     * [`LanConfig`](lan::LanConfig). The mailbox is already open.
+     */
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    pub async fn connect_lan<V>(
+        config: &AppConfig<V>,
+        nameplate: Nameplate,
+        lan_config: &lan::LanConfig,
+    ) -> Result<Option<(Self, Mailbox)>, RendezvousError> {
+        let side = MySide::generate();
+        let connection =
+            lan::LanConnection::discover(&config.id, &nameplate, &side, lan_config).await?;
+        Ok(connection
+            .map(|connection| Self::with_lan_connection(config, nameplate, side, connection)))
+    }
 
-        connection
-            .send_message(&OutboundMessage::bind(appid.clone(), side.clone()), None)
-            .await?;
+    #[cfg(all(feature = "lan", not(target_family = "wasm")))]
+    fn with_lan_connection<V>(
+        config: &AppConfig<V>,
+        nameplate: Nameplate,
+        side: MySide,
+        connection: lan::LanConnection,
+    ) -> (Self, Mailbox) {
+        let mailbox = Mailbox(format!("lan-{}", nameplate));
+        let server = Self {
+            connection: Connection::Lan(connection),
+            state: Some(MailboxMachine {
+                nameplate: Some(nameplate),
+                mailbox: mailbox.clone(),
+                queue: Default::default(),
+                processed: Default::default(),
+            }),
+            side,
+            /* Only needed for reconnecting, which never happens without a server */
+            settings: Box::new(ConnectionSettings::from_config(config)),
+            reconnecting: false,
+        };
+        (server, mailbox)
+    }
+
+    async fn connect_with_settings(
+        settings: ConnectionSettings,
+    ) -> Result<(Self, Option<String>), RendezvousError> {
+        let side = MySide::generate();
+        let (connection, motd) = Self::establish(&settings, &side).await?;
 
         //log::info!("Connected to rendezvous server.");
 
         Ok((
             Self {
-                connection,
+                connection: Connection::WebSocket(connection),
                 state: None,
                 side,
+                settings: Box::new(settings),
+                reconnecting: false,
             },
-            welcome.motd,
+            motd,
         ))
     }
 
+    /** Connect, negotiate permissions and bind to our side */
+    async fn establish(
+        settings: &ConnectionSettings,
+        side: &MySide,
+    ) -> Result<(WsConnection, Option<String>), RendezvousError> {
+        let (mut connection, welcome) =
+            WsConnection::connect(&settings.relay_url, &settings.tls).await?;
+        connection
+            .submit_permission(welcome.permission_required, &*settings.permission_provider)
+            .await?;
+        connection
+            .send_message(
+                &OutboundMessage::bind(settings.appid.clone(), side.clone()),
+                None,
+            )
+            .await?;
+        Ok((connection, welcome.motd))
+    }
+
+    /**
+     * Replace a broken connection to the server
+     *
+     * We bind with the same side again, so that the server still knows who we are, and then
+     * claim and open our nameplate and mailbox again. The server will re-send all messages in
+     * the mailbox, and the [`MailboxMachine`] takes care that we see each of them only once.
+     */
+    async fn reconnect(&mut self) -> Result<(), RendezvousError> {
+        let mut attempt = 0;
+        loop {
+            log::info!("Reconnecting to the rendezvous server …");
+            /* Boxed, to keep this out of the (already quite large) futures of our callers */
+            match Box::pin(self.try_reconnect()).await {
+                Ok(()) => break,
+                Err(err) if err.is_connection_lost() && attempt + 1 < RECONNECT_ATTEMPTS => {
+                    let delay = std::time::Duration::from_secs(1 << attempt.min(5));
+                    log::warn!(
+                        "Failed to reconnect to the rendezvous server: {}. Trying again in {:?}",
+                        err,
+                        delay
+                    );
+                    crate::util::sleep(delay).await;
+                    attempt += 1;
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        log::info!("Reconnected to the rendezvous server");
+        self.reconnecting = false;
+        Ok(())
+    }
+
+    async fn try_reconnect(&mut self) -> Result<(), RendezvousError> {
+        let (mut connection, _motd) = Self::establish(&self.settings, &self.side).await?;
+
+        if let Some(state) = &mut self.state {
+            if let Some(nameplate) = &state.nameplate {
+                connection
+                    .send_message(
+                        &OutboundMessage::claim(nameplate.clone()),
+                        Some(&mut state.queue),
+                    )
+                    .await?;
+                match connection.receive_reply(Some(&mut state.queue)).await? {
+                    RendezvousReply::Claimed(mailbox) => ensure!(
+                        mailbox == state.mailbox,
+                        RendezvousError::protocol("Nameplate points to a different mailbox now")
+                    ),
+                    other => return Err(RendezvousError::invalid_message("claimed", other)),
+                }
+            }
+            connection
+                .send_message(
+                    &OutboundMessage::open(state.mailbox.clone()),
+                    Some(&mut state.queue),
+                )
+                .await?;
+        }
+
+        self.connection = Connection::WebSocket(connection);
+        Ok(())
+    }
+
     /** A random unique string for this session */
     pub fn side(&self) -> &MySide {
         &self.side
@@ -426,13 +832,29 @@ impl RendezvousServer {
             .await
     }
 
+    /**
+     * Send a message to our peer
+     *
+     * If the connection to the server gets lost, we try to reconnect and send it again.
+     */
     pub async fn send_peer_message(
         &mut self,
         phase: Phase,
         body: Vec<u8>,
     ) -> Result<(), RendezvousError> {
-        self.send_message(&OutboundMessage::Add { body, phase })
-            .await
+        let message = OutboundMessage::Add { body, phase };
+        loop {
+            if self.reconnecting {
+                self.reconnect().await?;
+            }
+            match self.send_message(&message).await {
+                Err(err) if err.is_connection_lost() => {
+                    log::warn!("Lost connection to the rendezvous server: {}", err);
+                    self.reconnecting = true;
+                },
+                result => return result,
+            }
+        }
     }
 
     pub async fn next_peer_message_some(&mut self) -> Result<EncryptedMessage, RendezvousError> {
@@ -443,7 +865,15 @@ impl RendezvousServer {
         }
     }
 
+    /**
+     * Receive the next message from our peer, or `None` if there was nothing new
+     *
+     * If the connection to the server gets lost, we try to reconnect.
+     */
     pub async fn next_peer_message(&mut self) -> Result<Option<EncryptedMessage>, RendezvousError> {
+        if self.reconnecting {
+            self.reconnect().await?;
+        }
         let machine = &mut self
             .state
             .as_mut()
@@ -455,19 +885,25 @@ impl RendezvousServer {
                 return Ok(None);
             }
         }
-        match self.connection.receive_message().await? {
-            Some(InboundMessage::Message(message)) => {
+        match self.connection.receive_message().await {
+            Err(err) if err.is_connection_lost() => {
+                log::warn!("Lost connection to the rendezvous server: {}", err);
+                self.reconnecting = true;
+                Ok(None)
+            },
+            Err(err) => Err(err),
+            Ok(Some(InboundMessage::Message(message))) => {
                 if machine.receive_message(&message, &self.side) {
                     Ok(Some(message))
                 } else {
                     Ok(None)
                 }
             },
-            Some(other) => Err(RendezvousError::protocol(format!(
+            Ok(Some(other)) => Err(RendezvousError::protocol(format!(
                 "Expected message from peer, got '{}' instead",
                 other
             ))),
-            None => Ok(None),
+            Ok(None) => Ok(None),
         }
     }
 
@@ -589,6 +1025,9 @@ impl RendezvousServer {
     }
 
     pub async fn shutdown(mut self, mood: Mood) -> Result<(), RendezvousError> {
+        if self.reconnecting {
+            self.reconnect().await?;
+        }
         if let Some(MailboxMachine {
             nameplate,
             mailbox,
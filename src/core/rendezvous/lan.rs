@@ -0,0 +1,518 @@
+//! Rendezvous over the local network, without a server
+//!
+//! When both sides are on the same network, they can find each other without the rendezvous
+//! server: the side that allocates the code announces its nameplate via UDP multicast, and the
+//! side that enters the code listens for that announcement and then connects to its peer over
+//! TCP. Both sides exchange the very same messages they would otherwise send through the
+//! server's mailbox, so the PAKE and everything after it works exactly like with a server.
+//!
+//! ```no_run
+//! # fn main() -> eyre::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{
+//!     rendezvous::lan::LanConfig, transfer::APP_CONFIG, MailboxConnection, Wormhole,
+//! };
+//!
+//! let mailbox_connection =
+//!     MailboxConnection::create_lan(APP_CONFIG, 2, &LanConfig::default()).await?;
+//! println!("Code: {}", mailbox_connection.code);
+//! let wormhole = Wormhole::connect(mailbox_connection).await?;
+//! # Ok(()) })}
+//! ```
+//!
+//! Anybody on the network can see the announced nameplate, and the first one to connect with
+//! the right app ID and nameplate gets to try the code. Connections that don't introduce
+//! themselves that way are dropped. As with the server, a wrong guess makes the PAKE fail on
+//! both sides.
+
+use async_std::net::{TcpListener, TcpStream, UdpSocket};
+use futures::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use super::{MessageQueue, RendezvousError, RendezvousReply};
+use crate::core::{
+    server_messages::{InboundMessage, OutboundMessage},
+    AppID, EncryptedMessage, MySide, Nameplate, TheirSide,
+};
+
+/// The multicast group and port used for announcing nameplates, unless configured otherwise
+pub const DEFAULT_LAN_DISCOVERY_ADDRESS: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 77), 4010);
+
+/// Messages between the peers are limited to this size, like the websocket messages of the server
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+/// The hello is tiny, anything bigger than this is not one
+const MAX_HELLO_SIZE: usize = 1024;
+
+/// How long a connecting peer has to send its hello before we drop it
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/** How to find peers on the local network. Both sides need to use the same discovery address. */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanConfig {
+    /// The multicast group and port on which nameplates get announced
+    pub discovery_address: SocketAddrV4,
+    /// The address of the network interface to use, or `0.0.0.0` to let the OS pick one
+    pub interface: Ipv4Addr,
+    /// How often the side with the nameplate announces it
+    pub announce_interval: Duration,
+    /// How long to wait for the announcement of a nameplate before giving up
+    pub discovery_timeout: Duration,
+}
+
+impl Default for LanConfig {
+    fn default() -> Self {
+        Self {
+            discovery_address: DEFAULT_LAN_DISCOVERY_ADDRESS,
+            interface: Ipv4Addr::UNSPECIFIED,
+            announce_interval: Duration::from_secs(1),
+            discovery_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl LanConfig {
+    pub fn discovery_address(mut self, discovery_address: SocketAddrV4) -> Self {
+        self.discovery_address = discovery_address;
+        self
+    }
+
+    pub fn interface(mut self, interface: Ipv4Addr) -> Self {
+        self.interface = interface;
+        self
+    }
+
+    pub fn announce_interval(mut self, announce_interval: Duration) -> Self {
+        self.announce_interval = announce_interval;
+        self
+    }
+
+    pub fn discovery_timeout(mut self, discovery_timeout: Duration) -> Self {
+        self.discovery_timeout = discovery_timeout;
+        self
+    }
+}
+
+/** What gets multicast: where to connect to for a nameplate */
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Announcement {
+    appid: AppID,
+    nameplate: Nameplate,
+    port: u16,
+}
+
+/** What the connecting side sends first: which nameplate of which application it is looking for */
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Hello {
+    appid: AppID,
+    nameplate: Nameplate,
+}
+
+enum LanState {
+    /** Waiting for the peer to connect, while announcing our nameplate */
+    Listening {
+        listener: TcpListener,
+        socket: UdpSocket,
+        announcement: Vec<u8>,
+        /** The hello we expect from our peer */
+        hello: Hello,
+    },
+    Connected(TcpStream),
+    Closed,
+}
+
+/**
+ * A direct connection to the peer, standing in for the connection to the rendezvous server
+ *
+ * It answers everything the server would answer itself. Peer messages we send before the peer
+ * connected are kept until it does, just like the server's mailbox would.
+ */
+pub(super) struct LanConnection {
+    state: LanState,
+    config: LanConfig,
+    side: MySide,
+    /** Our messages to the peer, until it connects */
+    outbox: Vec<EncryptedMessage>,
+    /** The server replies we owe to ourselves */
+    replies: VecDeque<RendezvousReply>,
+}
+
+impl LanConnection {
+    /** Listen for the peer on a random port and announce it under our nameplate */
+    pub(super) async fn listen(
+        appid: &AppID,
+        nameplate: &Nameplate,
+        side: &MySide,
+        config: &LanConfig,
+    ) -> std::io::Result<Self> {
+        let listener = TcpListener::bind((config.interface, 0)).await?;
+        let announcement = serde_json::to_vec(&Announcement {
+            appid: appid.clone(),
+            nameplate: nameplate.clone(),
+            port: listener.local_addr()?.port(),
+        })?;
+
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+        socket.set_multicast_if_v4(&config.interface)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.bind(&SocketAddrV4::new(config.interface, 0).into())?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            state: LanState::Listening {
+                listener,
+                socket: std::net::UdpSocket::from(socket).into(),
+                announcement,
+                hello: Hello {
+                    appid: appid.clone(),
+                    nameplate: nameplate.clone(),
+                },
+            },
+            config: config.clone(),
+            side: side.clone(),
+            outbox: Vec::new(),
+            replies: VecDeque::new(),
+        })
+    }
+
+    /** Wait for the announcement of a nameplate and connect to its peer, `None` if there is none */
+    pub(super) async fn discover(
+        appid: &AppID,
+        nameplate: &Nameplate,
+        side: &MySide,
+        config: &LanConfig,
+    ) -> std::io::Result<Option<Self>> {
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+        /* Other wormholes on this machine may be waiting for announcements as well */
+        socket.set_reuse_address(true)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+        socket.bind(
+            &SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, config.discovery_address.port()).into(),
+        )?;
+        socket.join_multicast_v4(config.discovery_address.ip(), &config.interface)?;
+        socket.set_nonblocking(true)?;
+        let socket: UdpSocket = std::net::UdpSocket::from(socket).into();
+
+        let peer_address = async {
+            let mut buffer = [0; 1024];
+            loop {
+                let (length, sender) = socket.recv_from(&mut buffer).await?;
+                match serde_json::from_slice::<Announcement>(&buffer[..length]) {
+                    Ok(announcement)
+                        if announcement.appid == *appid && announcement.nameplate == *nameplate =>
+                    {
+                        break Ok::<_, std::io::Error>(SocketAddr::new(
+                            sender.ip(),
+                            announcement.port,
+                        ));
+                    },
+                    Ok(_) => {},
+                    Err(err) => {
+                        log::debug!("Ignoring invalid announcement from {}: {}", sender, err)
+                    },
+                }
+            }
+        };
+        let Ok(peer_address) = crate::util::timeout(config.discovery_timeout, peer_address).await
+        else {
+            return Ok(None);
+        };
+        let peer_address = peer_address?;
+
+        log::debug!("Found nameplate {} at {}", nameplate, peer_address);
+        let mut stream = TcpStream::connect(peer_address).await?;
+        stream.set_nodelay(true)?;
+        write_frame(
+            &mut stream,
+            &Hello {
+                appid: appid.clone(),
+                nameplate: nameplate.clone(),
+            },
+        )
+        .await?;
+
+        Ok(Some(Self {
+            state: LanState::Connected(stream),
+            config: config.clone(),
+            side: side.clone(),
+            outbox: Vec::new(),
+            replies: VecDeque::new(),
+        }))
+    }
+
+    /** Get the connection to the peer, waiting for it to connect if necessary */
+    async fn stream(&mut self) -> Result<&mut TcpStream, RendezvousError> {
+        if let LanState::Listening {
+            listener,
+            socket,
+            announcement,
+            hello,
+        } = &self.state
+        {
+            let config = &self.config;
+            let announce = async {
+                loop {
+                    if let Err(err) = socket.send_to(announcement, config.discovery_address).await {
+                        log::warn!("Failed to announce our nameplate: {}", err);
+                    }
+                    crate::util::sleep(config.announce_interval).await;
+                }
+            };
+            /* Anybody may connect, so wait for the one that knows what we are here for */
+            let accept = async {
+                loop {
+                    let (mut stream, peer_address) = listener.accept().await?;
+                    match crate::util::timeout(HELLO_TIMEOUT, read_hello(&mut stream)).await {
+                        Ok(Ok(their_hello)) if their_hello == *hello => {
+                            log::debug!("Peer connected from {}", peer_address);
+                            break Ok::<_, std::io::Error>(stream);
+                        },
+                        Ok(Ok(their_hello)) => log::debug!(
+                            "Dropping connection from {}, which is looking for {:?}",
+                            peer_address,
+                            their_hello
+                        ),
+                        Ok(Err(err)) => log::debug!(
+                            "Dropping connection from {} without a valid hello: {}",
+                            peer_address,
+                            err
+                        ),
+                        Err(_) => log::debug!(
+                            "Dropping connection from {}, which did not say hello",
+                            peer_address
+                        ),
+                    }
+                }
+            };
+            let mut stream = {
+                let announce = announce.fuse();
+                let accept = accept.fuse();
+                futures::pin_mut!(announce, accept);
+                futures::select! {
+                    () = announce => unreachable!(),
+                    result = accept => result?,
+                }
+            };
+            stream.set_nodelay(true)?;
+            for message in std::mem::take(&mut self.outbox) {
+                write_frame(&mut stream, &message).await?;
+            }
+            self.state = LanState::Connected(stream);
+        }
+        match &mut self.state {
+            LanState::Connected(stream) => Ok(stream),
+            _ => Err(RendezvousError::protocol(
+                "Connection to the peer is closed",
+            )),
+        }
+    }
+
+    /** Do what the server would do with a message */
+    pub(super) async fn send_message(
+        &mut self,
+        message: &OutboundMessage,
+    ) -> Result<(), RendezvousError> {
+        log::debug!("Sending {}", message);
+        match message {
+            OutboundMessage::Add { phase, body } => {
+                let message = EncryptedMessage {
+                    side: TheirSide::from(self.side.0 .0.clone()),
+                    phase: phase.clone(),
+                    body: body.clone(),
+                };
+                match &mut self.state {
+                    LanState::Listening { .. } => self.outbox.push(message),
+                    LanState::Connected(stream) => write_frame(stream, &message).await?,
+                    LanState::Closed => {
+                        return Err(RendezvousError::protocol(
+                            "Connection to the peer is closed",
+                        ))
+                    },
+                }
+            },
+            OutboundMessage::Release { .. } => self.replies.push_back(RendezvousReply::Released),
+            OutboundMessage::Close { .. } => self.replies.push_back(RendezvousReply::Closed),
+            other => {
+                return Err(RendezvousError::protocol(format!(
+                    "'{}' is not supported without a rendezvous server",
+                    other
+                )))
+            },
+        }
+        Ok(())
+    }
+
+    pub(super) async fn receive_reply(
+        &mut self,
+        _queue: Option<&mut MessageQueue>,
+    ) -> Result<RendezvousReply, RendezvousError> {
+        self.replies
+            .pop_front()
+            .ok_or_else(|| RendezvousError::protocol("Waiting for a reply that will never come"))
+    }
+
+    pub(super) async fn receive_message(
+        &mut self,
+    ) -> Result<Option<InboundMessage>, RendezvousError> {
+        let stream = self.stream().await?;
+        let message = read_frame(stream, MAX_MESSAGE_SIZE).await?;
+        let message = serde_json::from_slice(&message)?;
+        log::debug!("Received {}", message);
+        Ok(Some(InboundMessage::Message(message)))
+    }
+
+    pub(super) async fn close(&mut self) -> Result<(), RendezvousError> {
+        if let LanState::Connected(stream) = std::mem::replace(&mut self.state, LanState::Closed) {
+            match stream.shutdown(std::net::Shutdown::Both) {
+                /* The peer might have hung up first */
+                Err(err) if err.kind() != std::io::ErrorKind::NotConnected => {
+                    return Err(err.into())
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+/** Messages are sent as JSON, prefixed with their length */
+async fn write_frame(
+    stream: &mut TcpStream,
+    message: &impl serde::Serialize,
+) -> std::io::Result<()> {
+    let message = serde_json::to_vec(message)?;
+    stream
+        .write_all(&(message.len() as u32).to_be_bytes())
+        .await?;
+    stream.write_all(&message).await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut TcpStream, max_size: usize) -> Result<Vec<u8>, RendezvousError> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length).await?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > max_size {
+        return Err(RendezvousError::protocol(format!(
+            "Message from peer is too large ({} bytes)",
+            length
+        )));
+    }
+    let mut message = vec![0; length];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+async fn read_hello(stream: &mut TcpStream) -> Result<Hello, RendezvousError> {
+    let hello = read_frame(stream, MAX_HELLO_SIZE).await?;
+    Ok(serde_json::from_slice(&hello)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_lan_connection() {
+        let appid = AppID::new("lothar.com/wormhole/rendezvous-lan-test");
+        let nameplate = Nameplate::new("42");
+        let config = LanConfig::default()
+            .discovery_address(SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 78), 4011))
+            .interface(Ipv4Addr::LOCALHOST)
+            .announce_interval(Duration::from_millis(50))
+            .discovery_timeout(Duration::from_millis(500));
+
+        /* Nobody announces this nameplate */
+        let other = Nameplate::new("43");
+        assert!(
+            LanConnection::discover(&appid, &other, &MySide::generate(), &config)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let mut leader = LanConnection::listen(&appid, &nameplate, &MySide::generate(), &config)
+            .await
+            .unwrap();
+        let message = OutboundMessage::Add {
+            phase: crate::core::Phase::PAKE,
+            body: b"hello".to_vec(),
+        };
+        leader.send_message(&message).await.unwrap();
+        let leader_address = match &leader.state {
+            LanState::Listening { listener, .. } => {
+                SocketAddr::from((Ipv4Addr::LOCALHOST, listener.local_addr().unwrap().port()))
+            },
+            _ => unreachable!(),
+        };
+        let leader = async_std::task::spawn(async move {
+            let reply = leader.receive_message().await.unwrap();
+            leader
+                .send_message(&OutboundMessage::release("42"))
+                .await
+                .unwrap();
+            assert!(matches!(
+                leader.receive_reply(None).await.unwrap(),
+                RendezvousReply::Released
+            ));
+            leader.close().await.unwrap();
+            reply
+        });
+
+        /* Strangers get dropped, and don't take the place of our peer */
+        let mut stranger = TcpStream::connect(leader_address).await.unwrap();
+        write_frame(
+            &mut stranger,
+            &Hello {
+                appid: appid.clone(),
+                nameplate: other.clone(),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(stranger.read(&mut [0; 16]).await.unwrap(), 0);
+        let mut stranger = TcpStream::connect(leader_address).await.unwrap();
+        stranger.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        /* Closing with our request unread resets the connection */
+        assert!(!matches!(stranger.read(&mut [0; 16]).await, Ok(length) if length > 0));
+
+        let mut follower =
+            LanConnection::discover(&appid, &nameplate, &MySide::generate(), &config)
+                .await
+                .unwrap()
+                .unwrap();
+        match follower.receive_message().await.unwrap() {
+            Some(InboundMessage::Message(message)) => {
+                assert_eq!(message.phase, crate::core::Phase::PAKE);
+                assert_eq!(message.body, b"hello");
+            },
+            other => panic!("Unexpected message {:?}", other),
+        }
+        follower
+            .send_message(&OutboundMessage::Add {
+                phase: crate::core::Phase::VERSION,
+                body: b"world".to_vec(),
+            })
+            .await
+            .unwrap();
+        match leader.await {
+            Some(InboundMessage::Message(message)) => assert_eq!(message.body, b"world"),
+            other => panic!("Unexpected message {:?}", other),
+        }
+        assert!(follower.send_message(&OutboundMessage::List).await.is_err());
+    }
+}
@@ -0,0 +1,490 @@
+//! An embeddable rendezvous (mailbox) server
+//!
+//! This implements the server side of the client-server protocol, i.e. everything the Python
+//! `magic-wormhole-mailbox-server` does for a client: bind, list, allocate, claim, release,
+//! open, add, close and ping. All state is kept in memory and lost once the server stops,
+//! so this is meant for tests and small private deployments rather than as a public server.
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::{rendezvous::server::MailboxServer, transfer::APP_CONFIG};
+//! let server = MailboxServer::bind("127.0.0.1:0").await?;
+//! let config = APP_CONFIG.rendezvous_url(server.url().into());
+//! async_std::task::spawn(server.run());
+//! # Ok(()) })}
+//! ```
+
+use async_std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use async_tungstenite::tungstenite as ws2;
+use futures::{channel::mpsc, prelude::*};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::core::{
+    server_messages::{
+        InboundMessage, OutboundMessage, PermissionRequired, SubmitPermission, WelcomeMessage,
+    },
+    EncryptedMessage, Mailbox, Nameplate, TheirSide,
+};
+
+type Sender = mpsc::UnboundedSender<InboundMessage>;
+type PermissionCheck = dyn Fn(&SubmitPermission) -> bool + Send + Sync;
+
+/**
+ * A rendezvous server listening on a local socket
+ *
+ * Create one with [`MailboxServer::bind`], point clients to [`MailboxServer::url`] and then drive
+ * it with [`MailboxServer::run`] (usually in a background task).
+ */
+pub struct MailboxServer {
+    listener: TcpListener,
+    welcome: WelcomeMessage,
+    permission_check: Option<Arc<PermissionCheck>>,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl std::fmt::Debug for MailboxServer {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("MailboxServer")
+            .field("listener", &self.listener)
+            .field("welcome", &self.welcome)
+            .finish()
+    }
+}
+
+impl MailboxServer {
+    /** Bind the server to a local address, use port 0 to let the OS pick one */
+    pub async fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address).await?,
+            welcome: Default::default(),
+            permission_check: None,
+            state: Default::default(),
+        })
+    }
+
+    /** Set a "message of the day" that will be sent to every client */
+    pub fn motd(mut self, motd: impl Into<String>) -> Self {
+        self.welcome.motd = Some(motd.into());
+        self
+    }
+
+    /**
+     * Only let clients in that can prove their permission
+     *
+     * The methods in `permission_required` are announced in the welcome message. Clients must
+     * then submit a permission that passes `check` before they may bind, unless `none` is among
+     * the offered methods.
+     */
+    pub fn require_permission(
+        mut self,
+        permission_required: PermissionRequired,
+        check: impl Fn(&SubmitPermission) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.welcome.permission_required = Some(permission_required);
+        self.permission_check = Some(Arc::new(check));
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /**
+     * The URL clients should use as `rendezvous_url`
+     *
+     * This is derived from the bound address, so it is only useful if the server
+     * has not been bound to a wildcard address.
+     */
+    pub fn url(&self) -> String {
+        format!(
+            "ws://{}/v1",
+            self.local_addr()
+                .expect("A bound listener should always have a local address")
+        )
+    }
+
+    /**
+     * Accept and serve clients until the listener fails
+     *
+     * Every client connection is handled in its own task.
+     */
+    pub async fn run(self) -> std::io::Result<()> {
+        let Self {
+            listener,
+            welcome,
+            permission_check,
+            state,
+        } = self;
+        let welcome = Arc::new(welcome);
+        let mut incoming = listener.incoming();
+        while let Some(stream) = incoming.next().await {
+            let stream = stream?;
+            let peer_addr = stream.peer_addr().ok();
+            let state = state.clone();
+            let welcome = welcome.clone();
+            let permission_check = permission_check.clone();
+            async_std::task::spawn(async move {
+                if let Err(e) = handle_connection(state, welcome, permission_check, stream).await {
+                    log::debug!("Connection to {:?} failed: {}", peer_addr, e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+async fn handle_connection(
+    state: Arc<Mutex<ServerState>>,
+    welcome: Arc<WelcomeMessage>,
+    permission_check: Option<Arc<PermissionCheck>>,
+    stream: TcpStream,
+) -> Result<(), ws2::Error> {
+    let websocket = async_tungstenite::accept_async(stream).await?;
+    let (mut sink, mut stream) = websocket.split();
+    let (tx, mut rx) = mpsc::unbounded();
+
+    let writer = async move {
+        while let Some(message) = rx.next().await {
+            let message = serde_json::to_string(&message).unwrap();
+            sink.send(ws2::Message::Text(message)).await?;
+        }
+        /* The client might already be gone, that's fine */
+        let _ = sink.close().await;
+        Result::<(), ws2::Error>::Ok(())
+    };
+
+    let reader = async move {
+        /* Clients need no permission if we don't check any, or if they may choose `none` */
+        let permitted = match &welcome.permission_required {
+            Some(permission_required) => permission_required.none,
+            None => true,
+        };
+        let permission_check = permission_check.filter(|_| !permitted);
+        let mut connection = Connection::new(state, tx, permission_check);
+        connection.send(InboundMessage::Welcome {
+            welcome: WelcomeMessage {
+                motd: welcome.motd.clone(),
+                permission_required: welcome.permission_required.clone(),
+                ..Default::default()
+            },
+        });
+        while let Some(message) = stream.next().await {
+            match message? {
+                ws2::Message::Text(message) => connection.receive(&message),
+                ws2::Message::Close(_) => break,
+                /* Ping and pong are handled by tungstenite */
+                _ => (),
+            }
+        }
+        Result::<(), ws2::Error>::Ok(())
+        /* Dropping the connection unsubscribes it and thus also ends the writer */
+    };
+
+    let (reader, writer) = futures::join!(reader, writer);
+    reader.and(writer)
+}
+
+#[derive(Default)]
+struct ServerState {
+    /* Indexed by AppID */
+    apps: HashMap<String, App>,
+    next_connection: u64,
+}
+
+#[derive(Default)]
+struct App {
+    nameplates: HashMap<String, NameplateEntry>,
+    mailboxes: HashMap<String, MailboxEntry>,
+}
+
+impl App {
+    fn new_mailbox(&mut self) -> String {
+        use rand::{rngs::OsRng, RngCore};
+
+        let mut bytes: [u8; 8] = [0; 8];
+        OsRng.fill_bytes(&mut bytes);
+        let mailbox = hex::encode(bytes);
+        self.mailboxes.insert(mailbox.clone(), Default::default());
+        mailbox
+    }
+
+    fn free_nameplate(&self) -> String {
+        (1u64..)
+            .map(|id| id.to_string())
+            .find(|id| !self.nameplates.contains_key(id))
+            .unwrap()
+    }
+
+    fn claim(&mut self, nameplate: &str, side: &str) -> Result<String, String> {
+        if !self.nameplates.contains_key(nameplate) {
+            let mailbox = self.new_mailbox();
+            self.nameplates.insert(
+                nameplate.to_owned(),
+                NameplateEntry {
+                    mailbox,
+                    sides: Default::default(),
+                },
+            );
+        }
+        let entry = self.nameplates.get_mut(nameplate).unwrap();
+        match entry.sides.get(side) {
+            Some(true) => (),
+            Some(false) => bail!("reclaimed"),
+            None => {
+                ensure!(entry.sides.len() < 2, "crowded");
+                entry.sides.insert(side.to_owned(), true);
+            },
+        }
+        Ok(entry.mailbox.clone())
+    }
+
+    fn release(&mut self, nameplate: &str, side: &str) {
+        if let Some(entry) = self.nameplates.get_mut(nameplate) {
+            entry.sides.insert(side.to_owned(), false);
+            if entry.sides.values().all(|claimed| !claimed) {
+                log::debug!("Nameplate {} is free again", nameplate);
+                self.nameplates.remove(nameplate);
+            }
+        }
+    }
+}
+
+struct NameplateEntry {
+    mailbox: String,
+    /* Side → still claimed? */
+    sides: HashMap<String, bool>,
+}
+
+#[derive(Default)]
+struct MailboxEntry {
+    /* Side → still open? */
+    sides: HashMap<String, bool>,
+    messages: Vec<EncryptedMessage>,
+    /* Connections that opened the mailbox and thus want to get all new messages */
+    listeners: HashMap<u64, Sender>,
+}
+
+/** Server side state of a single client */
+struct Connection {
+    id: u64,
+    state: Arc<Mutex<ServerState>>,
+    tx: Sender,
+    /* Must be passed before binding, if set */
+    permission_check: Option<Arc<PermissionCheck>>,
+    /* (AppID, side) */
+    binding: Option<(String, String)>,
+    allocated: Option<String>,
+    claimed: Option<String>,
+    mailbox: Option<String>,
+}
+
+impl Connection {
+    fn new(
+        state: Arc<Mutex<ServerState>>,
+        tx: Sender,
+        permission_check: Option<Arc<PermissionCheck>>,
+    ) -> Self {
+        let id = {
+            let mut state = state.lock().unwrap();
+            state.next_connection += 1;
+            state.next_connection
+        };
+        Self {
+            id,
+            state,
+            tx,
+            permission_check,
+            binding: None,
+            allocated: None,
+            claimed: None,
+            mailbox: None,
+        }
+    }
+
+    fn send(&self, message: InboundMessage) {
+        log::trace!("Sending {} to connection {}", message, self.id);
+        /* This only fails if the writer is gone, in which case the reader will notice soon */
+        let _ = self.tx.unbounded_send(message);
+    }
+
+    fn receive(&mut self, message: &str) {
+        let orig: serde_json::Value = match serde_json::from_str(message) {
+            Ok(orig) => orig,
+            Err(_) => {
+                self.send(InboundMessage::Error {
+                    error: "message is not valid JSON".into(),
+                    orig: Box::new(message.into()),
+                });
+                return;
+            },
+        };
+        if orig.get("type").is_none() {
+            self.send(InboundMessage::Error {
+                error: "missing 'type'".into(),
+                orig: Box::new(orig),
+            });
+            return;
+        }
+
+        self.send(InboundMessage::Ack);
+        let result = match serde_json::from_value::<OutboundMessage>(orig.clone()) {
+            Ok(message) => {
+                log::debug!("Connection {} received {}", self.id, message);
+                self.handle_message(message)
+            },
+            Err(e) => Err(format!("unknown or malformed message: {}", e)),
+        };
+        if let Err(error) = result {
+            log::debug!("Connection {} got refused: {}", self.id, error);
+            self.send(InboundMessage::Error {
+                error,
+                orig: Box::new(orig),
+            });
+        }
+    }
+
+    fn handle_message(&mut self, message: OutboundMessage) -> Result<(), String> {
+        if let OutboundMessage::Ping { ping } = message {
+            self.send(InboundMessage::Pong { pong: ping });
+            return Ok(());
+        }
+        if let OutboundMessage::SubmitPermission(permission) = message {
+            if let Some(check) = &self.permission_check {
+                ensure!(check(&permission), "permission denied");
+                self.permission_check = None;
+            }
+            return Ok(());
+        }
+        if let OutboundMessage::Bind { appid, side } = message {
+            ensure!(self.permission_check.is_none(), "permission required");
+            ensure!(self.binding.is_none(), "already bound");
+            self.binding = Some((appid.0.into_owned(), side.0 .0));
+            return Ok(());
+        }
+
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        let (appid, side) = self.binding.clone().ok_or("must bind first")?;
+        let app = state.apps.entry(appid).or_default();
+
+        match message {
+            OutboundMessage::List => {
+                let mut nameplates = app.nameplates.keys().cloned().collect::<Vec<_>>();
+                nameplates.sort();
+                self.send(InboundMessage::Nameplates {
+                    nameplates: nameplates.into_iter().map(Nameplate).collect(),
+                });
+            },
+            OutboundMessage::Allocate => {
+                ensure!(
+                    self.allocated.is_none(),
+                    "you already allocated one, don't be greedy"
+                );
+                let nameplate = app.free_nameplate();
+                app.claim(&nameplate, &side)?;
+                self.allocated = Some(nameplate.clone());
+                self.send(InboundMessage::Allocated {
+                    nameplate: Nameplate(nameplate),
+                });
+            },
+            OutboundMessage::Claim { nameplate } => {
+                ensure!(self.claimed.is_none(), "only one claim per connection");
+                let mailbox = app.claim(&nameplate, &side)?;
+                self.claimed = Some(nameplate);
+                self.send(InboundMessage::Claimed {
+                    mailbox: Mailbox(mailbox),
+                });
+            },
+            OutboundMessage::Release { nameplate } => {
+                let claimed = self
+                    .claimed
+                    .take()
+                    .ok_or("must claim a nameplate before releasing it")?;
+                ensure!(
+                    claimed == nameplate,
+                    "release() and claim() use mismatched nameplates"
+                );
+                app.release(&nameplate, &side);
+                self.send(InboundMessage::Released);
+            },
+            OutboundMessage::Open { mailbox } => {
+                ensure!(self.mailbox.is_none(), "only one open per connection");
+                let entry = app.mailboxes.entry(mailbox.0.clone()).or_default();
+                if !entry.sides.contains_key(&side) {
+                    ensure!(entry.sides.len() < 2, "crowded");
+                }
+                entry.sides.insert(side, true);
+                entry.listeners.insert(self.id, self.tx.clone());
+                for message in &entry.messages {
+                    self.send(InboundMessage::Message(message.clone()));
+                }
+                self.mailbox = Some(mailbox.0);
+            },
+            OutboundMessage::Add { phase, body } => {
+                let mailbox = self
+                    .mailbox
+                    .as_ref()
+                    .ok_or("must open mailbox before adding")?;
+                let entry = app
+                    .mailboxes
+                    .get_mut(mailbox)
+                    .ok_or("mailbox has been closed")?;
+                let message = EncryptedMessage {
+                    side: TheirSide::from(side),
+                    phase,
+                    body,
+                };
+                /* Everybody gets a copy, including the sender */
+                entry.listeners.retain(|_, listener| {
+                    listener
+                        .unbounded_send(InboundMessage::Message(message.clone()))
+                        .is_ok()
+                });
+                entry.messages.push(message);
+            },
+            OutboundMessage::Close { mailbox, mood } => {
+                let opened = self
+                    .mailbox
+                    .take()
+                    .ok_or("must open mailbox before closing")?;
+                ensure!(
+                    opened == mailbox.0,
+                    "open() and close() use mismatched mailbox ids"
+                );
+                log::debug!("Side {} closed mailbox {} ({})", side, mailbox, mood);
+                if let Some(entry) = app.mailboxes.get_mut(&mailbox.0) {
+                    entry.listeners.remove(&self.id);
+                    entry.sides.insert(side, false);
+                    if entry.sides.values().all(|open| !open) {
+                        app.mailboxes.remove(&mailbox.0);
+                        app.nameplates.retain(|_, entry| entry.mailbox != mailbox.0);
+                    }
+                }
+                self.send(InboundMessage::Closed);
+            },
+            OutboundMessage::Ping { .. }
+            | OutboundMessage::SubmitPermission(_)
+            | OutboundMessage::Bind { .. } => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let (Some((appid, _)), Some(mailbox)) = (&self.binding, &self.mailbox) {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state
+                .apps
+                .get_mut(appid)
+                .and_then(|app| app.mailboxes.get_mut(mailbox))
+            {
+                entry.listeners.remove(&self.id);
+            }
+        }
+    }
+}
@@ -0,0 +1,430 @@
+//! TLS settings for `wss://` rendezvous servers
+//!
+//! By default, the certificate of a rendezvous server is checked against the usual web PKI roots,
+//! like a browser would do. A [`TlsConfig`] in the [`AppConfig`](crate::AppConfig) allows to
+//! trust private certificate authorities (additionally or exclusively), to pin the public keys a
+//! server may use and to present a client certificate for mutual TLS.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use magic_wormhole::{rendezvous::tls::TlsConfig, transfer::APP_CONFIG};
+//!
+//! let tls = TlsConfig::new()
+//!     .default_roots(false)
+//!     .add_root_certificates_pem(&std::fs::read("company-ca.pem")?)?
+//!     .spki_pin("sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".parse()?);
+//! let config = APP_CONFIG
+//!     .rendezvous_url("wss://wormhole.example.org/v1".into())
+//!     .tls(tls);
+//! # Ok(()) }
+//! ```
+//!
+//! Custom TLS settings are not available on WASM, where the browser is in charge of TLS.
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum TlsError {
+    #[error("Failed to read PEM data")]
+    Pem(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("No certificate found in PEM data")]
+    NoCertificate,
+    #[error("Expected exactly one private key in PEM data, but found {}", _0)]
+    PrivateKeyCount(usize),
+    #[error("Invalid certificate: {}", _0)]
+    InvalidCertificate(Box<str>),
+    #[error(
+        "Invalid public key pin '{}', expected 'sha256/' followed by a base64 encoded SHA-256 hash",
+        _0
+    )]
+    InvalidPin(Box<str>),
+    #[cfg(not(target_family = "wasm"))]
+    #[error("Invalid TLS configuration")]
+    Rustls(
+        #[from]
+        #[source]
+        rustls::Error,
+    ),
+    #[cfg(target_family = "wasm")]
+    #[error("Custom TLS settings are not supported on WASM")]
+    Unsupported,
+}
+
+/**
+ * TLS settings for connecting to the rendezvous server
+ *
+ * They only apply to `wss://` URLs. The default value does not change anything compared to
+ * not having a configuration at all.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TlsConfig {
+    /// Trust the usual web PKI root certificates. Disable to only trust `extra_roots`.
+    pub default_roots: bool,
+    /// Additional DER encoded root certificates to trust
+    pub extra_roots: Vec<Vec<u8>>,
+    /**
+     * Public keys the server must use
+     *
+     * If not empty, the certificate chain presented by the server must contain at least one
+     * certificate with one of these keys, in addition to being trusted. Only the certificates
+     * sent by the server are considered, so in order to pin a CA that is not part of the chain,
+     * trust it exclusively via `extra_roots` instead.
+     */
+    pub spki_pins: Vec<SpkiPin>,
+    /// Authenticate ourselves to the server (mutual TLS)
+    pub client_identity: Option<ClientIdentity>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlsConfig {
+    pub const fn new() -> Self {
+        Self {
+            default_roots: true,
+            extra_roots: Vec::new(),
+            spki_pins: Vec::new(),
+            client_identity: None,
+        }
+    }
+
+    pub fn default_roots(mut self, default_roots: bool) -> Self {
+        self.default_roots = default_roots;
+        self
+    }
+
+    pub fn add_root_certificate(mut self, certificate_der: Vec<u8>) -> Self {
+        self.extra_roots.push(certificate_der);
+        self
+    }
+
+    /** Trust all certificates found in some PEM data, for example the contents of a `ca.pem` */
+    pub fn add_root_certificates_pem(mut self, pem: &[u8]) -> Result<Self, TlsError> {
+        let certificates = rustls_pemfile::certs(&mut &*pem)?;
+        ensure!(!certificates.is_empty(), TlsError::NoCertificate);
+        self.extra_roots.extend(certificates);
+        Ok(self)
+    }
+
+    pub fn spki_pin(mut self, pin: SpkiPin) -> Self {
+        self.spki_pins.push(pin);
+        self
+    }
+
+    pub fn client_identity(mut self, client_identity: ClientIdentity) -> Self {
+        self.client_identity = Some(client_identity);
+        self
+    }
+
+    /** Whether we can simply use the TLS defaults */
+    pub(crate) fn is_default(&self) -> bool {
+        *self == Self::new()
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub(crate) fn connector(&self) -> Result<async_tls::TlsConnector, TlsError> {
+        use std::sync::Arc;
+
+        let mut roots = rustls::RootCertStore::empty();
+        if self.default_roots {
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+                rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        }
+        for root in &self.extra_roots {
+            roots.add(&rustls::Certificate(root.clone()))?;
+        }
+
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(PinningVerifier {
+                inner: rustls::client::WebPkiVerifier::new(roots, None),
+                pins: self.spki_pins.clone(),
+            }));
+        let config = match &self.client_identity {
+            Some(identity) => builder.with_client_auth_cert(
+                identity
+                    .certificate_chain
+                    .iter()
+                    .cloned()
+                    .map(rustls::Certificate)
+                    .collect(),
+                rustls::PrivateKey(identity.private_key.clone()),
+            )?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(config.into())
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub(crate) fn connector(&self) -> Result<std::convert::Infallible, TlsError> {
+        Err(TlsError::Unsupported)
+    }
+}
+
+/**
+ * A certificate and its private key, to authenticate ourselves to the server
+ */
+#[derive(Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// DER encoded certificates, starting with our own and followed by its intermediates
+    pub certificate_chain: Vec<Vec<u8>>,
+    /// DER encoded private key, either PKCS#8, PKCS#1 (RSA) or SEC1 (EC)
+    pub private_key: Vec<u8>,
+}
+
+impl std::fmt::Debug for ClientIdentity {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("ClientIdentity")
+            .field("certificate_chain", &self.certificate_chain.len())
+            .field("private_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl ClientIdentity {
+    /** Load the identity from a PEM certificate chain and a PEM private key */
+    pub fn from_pem(certificate_chain: &[u8], private_key: &[u8]) -> Result<Self, TlsError> {
+        use rustls_pemfile::Item;
+
+        let certificate_chain = rustls_pemfile::certs(&mut &*certificate_chain)?;
+        ensure!(!certificate_chain.is_empty(), TlsError::NoCertificate);
+        let mut keys = rustls_pemfile::read_all(&mut &*private_key)?
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(key),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        ensure!(keys.len() == 1, TlsError::PrivateKeyCount(keys.len()));
+
+        Ok(Self {
+            certificate_chain,
+            private_key: keys.remove(0),
+        })
+    }
+}
+
+/**
+ * A pinned public key
+ *
+ * This is the SHA-256 hash of a DER encoded SubjectPublicKeyInfo, the same thing HPKP and
+ * `curl --pinnedpubkey` use. As a string, it is written as `sha256/` followed by the base64
+ * encoded hash. One way to get it for an existing certificate is:
+ *
+ * ```text
+ * openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der \
+ *     | openssl dgst -sha256 -binary | base64
+ * ```
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin(pub [u8; 32]);
+
+impl SpkiPin {
+    /** Pin a DER encoded SubjectPublicKeyInfo */
+    pub fn from_public_key_info(public_key_info: &[u8]) -> Self {
+        Self(Sha256::digest(public_key_info).into())
+    }
+
+    /** Pin the public key of a DER encoded X.509 certificate */
+    pub fn from_certificate(certificate: &[u8]) -> Result<Self, TlsError> {
+        subject_public_key_info(certificate)
+            .map(Self::from_public_key_info)
+            .ok_or_else(|| TlsError::InvalidCertificate("failed to find the public key".into()))
+    }
+}
+
+impl std::str::FromStr for SpkiPin {
+    type Err = TlsError;
+
+    fn from_str(pin: &str) -> Result<Self, Self::Err> {
+        /* `curl` writes them with two slashes */
+        pin.strip_prefix("sha256//")
+            .or_else(|| pin.strip_prefix("sha256/"))
+            .and_then(|hash| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(hash)
+                    .ok()?
+                    .try_into()
+                    .ok()
+            })
+            .map(Self)
+            .ok_or_else(|| TlsError::InvalidPin(pin.into()))
+    }
+}
+
+impl std::fmt::Display for SpkiPin {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            fmt,
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode(self.0)
+        )
+    }
+}
+
+impl std::fmt::Debug for SpkiPin {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "SpkiPin({})", self)
+    }
+}
+
+/**
+ * Checks the certificate like usual, and then additionally requires one of the pinned keys (if any)
+ */
+#[cfg(not(target_family = "wasm"))]
+struct PinningVerifier {
+    inner: rustls::client::WebPkiVerifier,
+    pins: Vec<SpkiPin>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl rustls::client::ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        if self.pins.is_empty() {
+            return Ok(verified);
+        }
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|certificate| SpkiPin::from_certificate(&certificate.0).ok())
+            .any(|pin| self.pins.contains(&pin));
+        if pinned {
+            Ok(verified)
+        } else {
+            log::warn!("The rendezvous server's certificate does not match any pinned key");
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+}
+
+/**
+ * Split off the first DER element, returning the whole element, its contents and the rest
+ *
+ * Only the subset needed for X.509 is supported: single byte tags and definite lengths.
+ */
+fn der_element(input: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    fn split_at(input: &[u8], mid: usize) -> Option<(&[u8], &[u8])> {
+        (mid <= input.len()).then(|| input.split_at(mid))
+    }
+
+    let (&length, rest) = input.get(1..)?.split_first()?;
+    let (length, rest) = match length {
+        0..=0x7f => (length as usize, rest),
+        0x81..=0x84 => {
+            let (bytes, rest) = split_at(rest, (length & 0x7f) as usize)?;
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+            (length, rest)
+        },
+        _ => return None,
+    };
+    let (contents, rest) = split_at(rest, length)?;
+    let header = input.len() - rest.len() - contents.len();
+    Some((&input[..header + length], contents, rest))
+}
+
+/** Find the DER encoded SubjectPublicKeyInfo within a DER encoded X.509 certificate */
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    /* Certificate ::= SEQUENCE { tbsCertificate, … } */
+    let (_, certificate, _) = der_element(certificate)?;
+    let (_, mut tbs_certificate, _) = der_element(certificate)?;
+    /* Skip the optional version, which is explicitly tagged [0] */
+    if tbs_certificate.first() == Some(&0xa0) {
+        tbs_certificate = der_element(tbs_certificate)?.2;
+    }
+    /* Skip serialNumber, signature, issuer, validity and subject */
+    for _ in 0..5 {
+        tbs_certificate = der_element(tbs_certificate)?.2;
+    }
+    let (public_key_info, _, _) = der_element(tbs_certificate)?;
+    Some(public_key_info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CERTIFICATE: &[u8] = include_bytes!("../../../tests/tls/server.pem");
+    const KEY: &[u8] = include_bytes!("../../../tests/tls/server-key.pem");
+
+    #[test]
+    fn test_spki_pin() {
+        let certificate = rustls_pemfile::certs(&mut &*CERTIFICATE).unwrap().remove(0);
+        let public_key_info = subject_public_key_info(&certificate).unwrap();
+        /* An EC P-256 public key: SEQUENCE { SEQUENCE { id-ecPublicKey, prime256v1 }, BIT STRING } */
+        assert_eq!(public_key_info.len(), 91);
+        assert_eq!(&public_key_info[..2], &[0x30, 0x59]);
+
+        let pin = SpkiPin::from_certificate(&certificate).unwrap();
+        assert_eq!(pin, SpkiPin::from_public_key_info(public_key_info));
+        assert_eq!(pin.to_string().parse::<SpkiPin>().unwrap(), pin);
+        assert_eq!(
+            pin.to_string()
+                .replace("sha256/", "sha256//")
+                .parse::<SpkiPin>()
+                .unwrap(),
+            pin
+        );
+        assert!("sha256/AAAA".parse::<SpkiPin>().is_err());
+        assert!("sha1/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+            .parse::<SpkiPin>()
+            .is_err());
+        assert!(SpkiPin::from_certificate(&certificate[..100]).is_err());
+    }
+
+    #[test]
+    fn test_pem_loading() {
+        let config = TlsConfig::new()
+            .add_root_certificates_pem(CERTIFICATE)
+            .unwrap();
+        assert_eq!(config.extra_roots.len(), 1);
+        assert!(!config.is_default());
+        assert!(TlsConfig::default().is_default());
+
+        assert!(matches!(
+            TlsConfig::new().add_root_certificates_pem(KEY),
+            Err(TlsError::NoCertificate)
+        ));
+
+        let identity = ClientIdentity::from_pem(CERTIFICATE, KEY).unwrap();
+        assert_eq!(identity.certificate_chain, config.extra_roots);
+        assert!(!format!("{:?}", identity).contains(&format!("{:?}", identity.private_key)));
+        assert!(matches!(
+            ClientIdentity::from_pem(CERTIFICATE, CERTIFICATE),
+            Err(TlsError::PrivateKeyCount(0))
+        ));
+    }
+}
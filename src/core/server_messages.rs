@@ -17,7 +17,7 @@ impl Nameplate_ {
         Ok(value.into_iter().map(|value| Nameplate(value.id)).collect())
     }
 
-    #[allow(clippy::all, dead_code)]
+    #[allow(clippy::ptr_arg)]
     fn serialize<S>(value: &Vec<Nameplate>, ser: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
@@ -28,22 +28,38 @@ impl Nameplate_ {
     }
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq, derive_more::Display)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, derive_more::Display)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "method")]
 pub enum SubmitPermission {
     #[display(fmt = "Hashcash {{ stamp: '{}' }}", stamp)]
     Hashcash { stamp: String },
+    /**
+     * Any other method, together with whatever fields it requires
+     *
+     * The fields are not displayed, as they will likely contain some secret.
+     */
+    #[serde(untagged)]
+    #[display(fmt = "{} {{ .. }}", method)]
+    Other {
+        method: String,
+        #[serde(flatten)]
+        fields: serde_json::Map<String, serde_json::Value>,
+    },
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
 pub struct WelcomeMessage {
     #[deprecated(note = "This is for the Python client")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub current_cli_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub motd: Option<String>,
     #[deprecated(note = "Servers should send a proper error message instead")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
     #[serde(rename = "permission-required")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub permission_required: Option<PermissionRequired>,
 }
 
@@ -61,10 +77,16 @@ impl std::fmt::Display for WelcomeMessage {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
 pub struct PermissionRequired {
-    #[serde(deserialize_with = "PermissionRequired::deserialize_none")]
+    #[serde(
+        default,
+        deserialize_with = "PermissionRequired::deserialize_none",
+        serialize_with = "PermissionRequired::serialize_none",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
     pub none: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub hashcash: Option<HashcashPermission>,
     #[serde(flatten)]
     pub other: HashMap<String, serde_json::Value>,
@@ -79,6 +101,13 @@ impl PermissionRequired {
             serde::Deserialize::deserialize(de)?;
         Ok(value.is_some())
     }
+
+    fn serialize_none<S>(_value: &bool, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ser.collect_map(std::iter::empty::<((), ())>())
+    }
 }
 
 impl std::fmt::Display for PermissionRequired {
@@ -94,7 +123,7 @@ impl std::fmt::Display for PermissionRequired {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, derive_more::Display)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, derive_more::Display)]
 #[display(
     fmt = "HashcashPermission {{ bits: {}, resource: '{}' }}",
     bits,
@@ -106,7 +135,7 @@ pub struct HashcashPermission {
     pub resource: String,
 }
 
-#[derive(Debug, PartialEq, Clone, Deserialize, derive_more::Display)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, derive_more::Display)]
 #[display(
     fmt = "EncryptedMessage {{ side: {}, phase: {}, body: {}",
     side,
@@ -116,7 +145,7 @@ pub struct HashcashPermission {
 pub struct EncryptedMessage {
     pub side: TheirSide,
     pub phase: Phase,
-    #[serde(deserialize_with = "hex::serde::deserialize")]
+    #[serde(with = "hex::serde")]
     pub body: Vec<u8>,
 }
 
@@ -129,7 +158,7 @@ impl EncryptedMessage {
 }
 
 // Client sends only these
-#[derive(Serialize, Debug, PartialEq, derive_more::Display)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, derive_more::Display)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
 #[allow(dead_code)]
@@ -162,7 +191,7 @@ pub enum OutboundMessage {
     )]
     Add {
         phase: Phase,
-        #[serde(serialize_with = "hex::serde::serialize")]
+        #[serde(with = "hex::serde")]
         body: Vec<u8>,
     },
     #[display(fmt = "Close {{ mailbox: {}, mood: {} }}", mailbox, mood)]
@@ -208,7 +237,7 @@ impl OutboundMessage {
 }
 
 // Server sends only these
-#[derive(Deserialize, Debug, PartialEq, derive_more::Display)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, derive_more::Display)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
 pub enum InboundMessage {
@@ -436,6 +465,28 @@ mod test {
         )
     }
 
+    /// The server side needs to write what the client reads
+    #[test]
+    fn test_welcome_serialization() {
+        let m = InboundMessage::Welcome {
+            welcome: WelcomeMessage {
+                motd: Some("hello world".into()),
+                permission_required: Some(PermissionRequired {
+                    none: true,
+                    hashcash: None,
+                    other: Default::default(),
+                }),
+                ..Default::default()
+            },
+        };
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(
+            s,
+            json!({"type": "welcome", "welcome": {"motd": "hello world", "permission-required": {"none": {}}}})
+        );
+        assert_eq!(serde_json::from_value::<InboundMessage>(s).unwrap(), m);
+    }
+
     #[test]
     fn test_submit_permissions() {
         let m = OutboundMessage::SubmitPermission(SubmitPermission::Hashcash {
@@ -446,6 +497,26 @@ mod test {
             s,
             r#"{"type":"submit-permission","method":"hashcash","stamp":"stamp"}"#
         );
+
+        let m = OutboundMessage::SubmitPermission(SubmitPermission::Other {
+            method: "bearer".into(),
+            fields: [("token".to_string(), json!("secret"))]
+                .into_iter()
+                .collect(),
+        });
+        let s = serde_json::to_value(&m).unwrap();
+        assert_eq!(
+            s,
+            json!({"type": "submit-permission", "method": "bearer", "token": "secret"})
+        );
+        assert_eq!(serde_json::from_value::<OutboundMessage>(s).unwrap(), m);
+        assert!(!m.to_string().contains("secret"));
+
+        let s = json!({"type": "submit-permission", "method": "hashcash", "stamp": "stamp"});
+        assert!(matches!(
+            serde_json::from_value::<OutboundMessage>(s).unwrap(),
+            OutboundMessage::SubmitPermission(SubmitPermission::Hashcash { .. })
+        ));
     }
 
     #[test]
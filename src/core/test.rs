@@ -2,6 +2,8 @@ use super::{Mood, Phase};
 use rand::Rng;
 use std::{borrow::Cow, time::Duration};
 
+#[cfg(feature = "dilation")]
+use crate::dilation;
 use crate::{
     self as magic_wormhole,
     core::{MailboxConnection, Nameplate},
@@ -14,11 +16,11 @@ pub const TEST_APPID: AppID = AppID(std::borrow::Cow::Borrowed(
     "piegames.de/wormhole/rusty-wormhole-test",
 ));
 
-pub const APP_CONFIG: AppConfig<()> = AppConfig::<()> {
-    id: TEST_APPID,
-    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
-    app_version: (),
-};
+pub const APP_CONFIG: AppConfig<()> = AppConfig::new(
+    TEST_APPID,
+    Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    (),
+);
 
 const TIMEOUT: Duration = Duration::from_secs(60);
 
@@ -539,6 +541,1130 @@ pub async fn test_connect_with_code_expecting_nameplate() -> eyre::Result<()> {
     Ok(())
 }
 
+/** Spawn a fresh in-process rendezvous server and return its URL */
+#[cfg(feature = "server")]
+async fn local_rendezvous_server() -> eyre::Result<Cow<'static, str>> {
+    let server = crate::rendezvous::server::MailboxServer::bind("127.0.0.1:0")
+        .await?
+        .motd("Hello from the test server");
+    let url = server.url();
+    async_std::task::spawn(server.run());
+    Ok(url.into())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_nameplates() -> eyre::Result<()> {
+    use crate::rendezvous::RendezvousServer;
+    init_logger();
+
+    let url = local_rendezvous_server().await?;
+    let (mut server, motd) = RendezvousServer::connect(&TEST_APPID, &url).await?;
+    assert_eq!(motd.as_deref(), Some("Hello from the test server"));
+    assert!(server.list_nameplates().await?.is_empty());
+
+    let (nameplate, _mailbox) = server.allocate_claim_open().await?;
+    let (mut other, _) = RendezvousServer::connect(&TEST_APPID, &url).await?;
+    assert_eq!(other.list_nameplates().await?, vec![nameplate.clone()]);
+
+    /* Nameplates are scoped to the AppID */
+    let (mut foreign, _) = RendezvousServer::connect(&AppID::new("foreign"), &url).await?;
+    assert!(foreign.list_nameplates().await?.is_empty());
+    foreign.shutdown(Mood::Happy).await?;
+
+    server.release_nameplate().await?;
+    assert!(other.list_nameplates().await?.is_empty());
+
+    server.shutdown(Mood::Happy).await?;
+    other.shutdown(Mood::Happy).await?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_completion() -> eyre::Result<()> {
+    use crate::CodeCompleter;
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let mut completer = CodeCompleter::new(2);
+    completer.fetch_nameplates(&config).await?;
+    assert!(completer.nameplates().is_empty());
+
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let nameplate = mailbox.code.nameplate();
+    completer.fetch_nameplates(&config).await?;
+    assert_eq!(completer.nameplates(), std::slice::from_ref(&nameplate));
+    assert_eq!(completer.complete(""), vec![format!("{}-", nameplate)]);
+
+    mailbox.shutdown(Mood::Happy).await?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_wordlist() -> eyre::Result<()> {
+    use crate::wordlist::Wordlist;
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let wordlist = Wordlist::parse("11111 abacus\n11112 abdomen\n11113 zulu")?;
+    let sender_config = config.clone().wordlist(wordlist.clone());
+    let mailbox = MailboxConnection::create(sender_config, 3).await?;
+    let (_, password) = mailbox.code.split();
+    assert_eq!(password.split('-').count(), 3);
+    assert!(password.split('-').all(|word| wordlist.contains(word)));
+
+    /* The receiver checks the code against its own wordlist */
+    let code = mailbox.code.clone();
+    let sender_task = async_std::task::spawn(Wormhole::connect(mailbox));
+    let receiver_config = config.clone().wordlist(wordlist.clone());
+    let wormhole =
+        Wormhole::connect(MailboxConnection::connect(receiver_config, code, false).await?).await?;
+    wormhole.close().await?;
+    async_std::future::timeout(TIMEOUT, sender_task)
+        .await??
+        .close()
+        .await?;
+
+    /* Without the list, the code looks like a custom password, but the sender tells us about it */
+    let mailbox = MailboxConnection::create_with_password(
+        config.clone().wordlist(wordlist),
+        "abacus-abdomen",
+    )
+    .await?;
+    let code = mailbox.code.clone();
+    let sender_task = async_std::task::spawn(Wormhole::connect(mailbox));
+    let result = Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await;
+    assert!(
+        matches!(&result, Err(WormholeError::UnknownWordlist(id)) if id.starts_with("custom-")),
+        "{:?}",
+        result
+    );
+    async_std::future::timeout(TIMEOUT, sender_task)
+        .await??
+        .close()
+        .await?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_wormhole() -> eyre::Result<()> {
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect(mailbox).await?;
+        wormhole.send(b"ping".to_vec()).await?;
+        assert_eq!(wormhole.receive().await?, b"pong");
+        let verifier = wormhole.verifier.clone();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(verifier)
+    });
+
+    let mut wormhole =
+        Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await?;
+    assert_eq!(wormhole.receive().await?, b"ping");
+    wormhole.send(b"pong".to_vec()).await?;
+    let verifier = wormhole.verifier.clone();
+    wormhole.close().await?;
+
+    assert_eq!(
+        async_std::future::timeout(TIMEOUT, sender_task).await??,
+        verifier
+    );
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_wrong_code() -> eyre::Result<()> {
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let nameplate = mailbox.code.nameplate();
+
+    let sender_task = async_std::task::spawn(Wormhole::connect(mailbox));
+    let result = Wormhole::connect(
+        MailboxConnection::connect(config, Code::new(&nameplate, "foo-bar"), false).await?,
+    )
+    .await;
+
+    assert!(matches!(result, Err(WormholeError::PakeFailed)));
+    assert!(matches!(
+        async_std::future::timeout(TIMEOUT, sender_task).await?,
+        Err(WormholeError::PakeFailed)
+    ));
+    Ok(())
+}
+
+/** Find each other via multicast on the loopback interface, with no rendezvous server reachable */
+#[cfg(feature = "lan")]
+#[async_std::test]
+pub async fn test_lan_wormhole() -> eyre::Result<()> {
+    use crate::rendezvous::lan::LanConfig;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url("ws://127.0.0.1:1/v1".into());
+    let lan_config = LanConfig::default()
+        .discovery_address(SocketAddrV4::new(Ipv4Addr::new(239, 255, 77, 79), 4012))
+        .interface(Ipv4Addr::LOCALHOST)
+        .announce_interval(Duration::from_millis(100))
+        .discovery_timeout(Duration::from_secs(1));
+
+    /* Nobody announces this one */
+    let result = MailboxConnection::connect_lan(
+        config.clone(),
+        Code::new(&Nameplate::new("1000"), "foo-bar"),
+        &lan_config,
+    )
+    .await;
+    assert!(matches!(result, Err(WormholeError::UnclaimedNameplate(_))));
+
+    let mailbox = MailboxConnection::create_lan(config.clone(), 2, &lan_config).await?;
+    let code = mailbox.code.clone();
+    assert!(mailbox.welcome.is_none());
+
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect(mailbox).await?;
+        wormhole.send(b"ping".to_vec()).await?;
+        assert_eq!(wormhole.receive().await?, b"pong");
+        let verifier = wormhole.verifier.clone();
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(verifier)
+    });
+
+    let mut wormhole =
+        Wormhole::connect(MailboxConnection::connect_lan(config, code, &lan_config).await?).await?;
+    assert_eq!(wormhole.receive().await?, b"ping");
+    wormhole.send(b"pong".to_vec()).await?;
+    let verifier = wormhole.verifier.clone();
+    wormhole.close().await?;
+
+    assert_eq!(
+        async_std::future::timeout(TIMEOUT, sender_task).await??,
+        verifier
+    );
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_crowded() -> eyre::Result<()> {
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let initial_mailbox_connection = MailboxConnection::create(config.clone(), 2).await?;
+    let code = initial_mailbox_connection.code.clone();
+
+    let _mailbox_connection_1 =
+        MailboxConnection::connect(config.clone(), code.clone(), false).await?;
+    match MailboxConnection::connect(config, code, false).await {
+        Err(WormholeError::ServerError(crate::rendezvous::RendezvousError::Server(error))) => {
+            assert_eq!(&*error, "crowded")
+        },
+        Err(other) => panic!("Got wrong error message: {}, wanted 'crowded'", other),
+        Ok(_) => panic!("A third side must not be able to claim the nameplate"),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_unclaimed_nameplate() -> eyre::Result<()> {
+    init_logger();
+
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let code = generate_random_code();
+    match MailboxConnection::connect(config.clone(), code.clone(), false).await {
+        Err(WormholeError::UnclaimedNameplate(nameplate)) => {
+            assert_eq!(nameplate, code.nameplate())
+        },
+        other => panic!("Expected UnclaimedNameplate, got {:?}", other.err()),
+    }
+
+    /* With allocation, an unknown nameplate is fine */
+    MailboxConnection::connect(config, code, true)
+        .await?
+        .shutdown(Mood::Happy)
+        .await?;
+    Ok(())
+}
+
+/** Send files through a local relay without touching the network */
+#[cfg(all(feature = "server", feature = "transfer"))]
+#[async_std::test]
+pub async fn test_local_server_force_relay() -> eyre::Result<()> {
+    init_logger();
+
+    let config = transfer::APP_CONFIG
+        .id(TEST_APPID)
+        .rendezvous_url(local_rendezvous_server().await?);
+    let relay = transit::server::RelayServer::new()
+        .listen_tcp("127.0.0.1:0")
+        .await?;
+    let relay_hints = vec![relay.relay_hint()];
+    async_std::task::spawn(relay.run());
+
+    fn expect_relay(info: transit::TransitInfo) {
+        assert!(matches!(
+            info.conn_type,
+            transit::ConnectionType::Relay { .. }
+        ));
+    }
+
+    for (offer, answer) in file_offers().await? {
+        let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+        let code = mailbox.code.clone();
+
+        let sender_relay_hints = relay_hints.clone();
+        let sender_task = async_std::task::spawn(async move {
+            transfer::send(
+                Wormhole::connect(mailbox).await?,
+                sender_relay_hints,
+                transit::Abilities::FORCE_RELAY,
+                offer,
+                expect_relay,
+                |_sent, _total| {},
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        let receiver_config = config.clone();
+        let receiver_relay_hints = relay_hints.clone();
+        let receiver_task = async_std::task::spawn(async move {
+            let wormhole =
+                Wormhole::connect(MailboxConnection::connect(receiver_config, code, false).await?)
+                    .await?;
+            let mut answer = (answer.into_iter_files().next().unwrap().1.content)(false).await?;
+            let transfer::ReceiveRequest::V1(req) = transfer::request(
+                wormhole,
+                receiver_relay_hints,
+                transit::Abilities::FORCE_RELAY,
+                futures::future::pending(),
+            )
+            .await?
+            .unwrap() else {
+                panic!("v2 should be disabled for now")
+            };
+            req.accept(
+                expect_relay,
+                &mut answer,
+                |_received, _total| {},
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        async_std::future::timeout(TIMEOUT, sender_task).await??;
+        async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    }
+    Ok(())
+}
+
+/** Interrupted v2 transfers continue where they left off */
+#[cfg(all(feature = "server", feature = "transfer"))]
+#[async_std::test]
+pub async fn test_local_server_resume() -> eyre::Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    init_logger();
+
+    let config = transfer::APP_CONFIG
+        .id(TEST_APPID)
+        .rendezvous_url(local_rendezvous_server().await?)
+        .app_version(transfer::AppVersion::default().enable_v2());
+    let relay = transit::server::RelayServer::new()
+        .listen_tcp("127.0.0.1:0")
+        .await?;
+    let relay_hints = vec![relay.relay_hint()];
+    async_std::task::spawn(relay.run());
+
+    let content = async_std::fs::read("tests/example-file.bin").await?;
+    let target_dir = tempfile::tempdir()?;
+    let target = target_dir.path().join("example-file.bin");
+
+    /* A matching prefix gets resumed, a corrupted one gets replaced, a complete file is kept */
+    let mut corrupted = content[..4096].to_vec();
+    corrupted[0] ^= 0xff;
+    let half = content.len() / 2;
+    for (partial, resume_at) in [
+        (&content[..half], half),
+        (&corrupted[..], 0),
+        (&content[..], content.len()),
+    ] {
+        async_std::fs::write(&target, partial).await?;
+        let partial_len = partial.len() as u64;
+
+        let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+        let code = mailbox.code.clone();
+
+        let sender_progress = Arc::new(Mutex::new(Vec::new()));
+        let sender_relay_hints = relay_hints.clone();
+        let progress = sender_progress.clone();
+        let sender_task = async_std::task::spawn(async move {
+            transfer::send(
+                Wormhole::connect(mailbox).await?,
+                sender_relay_hints,
+                transit::Abilities::FORCE_RELAY,
+                transfer::OfferSend::new_file_or_folder(
+                    "example-file.bin".into(),
+                    "tests/example-file.bin",
+                )
+                .await?,
+                |_info| {},
+                move |sent, _total| progress.lock().unwrap().push(sent),
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        let receiver_progress = Arc::new(Mutex::new(Vec::new()));
+        let receiver_config = config.clone();
+        let receiver_relay_hints = relay_hints.clone();
+        let receiver_target_dir = target_dir.path().to_owned();
+        let progress = receiver_progress.clone();
+        let receiver_task = async_std::task::spawn(async move {
+            let wormhole =
+                Wormhole::connect(MailboxConnection::connect(receiver_config, code, false).await?)
+                    .await?;
+            let transfer::ReceiveRequest::V2(req) = transfer::request(
+                wormhole,
+                receiver_relay_hints,
+                transit::Abilities::FORCE_RELAY,
+                futures::future::pending(),
+            )
+            .await?
+            .unwrap() else {
+                panic!("Both sides enabled v2")
+            };
+            let answer = req
+                .offer()
+                .accept_all_resumable(&receiver_target_dir)
+                .await?;
+            assert_eq!(answer.iter_files().next().unwrap().1.offset, partial_len);
+            req.accept(
+                |_info| {},
+                answer,
+                move |received, _total| progress.lock().unwrap().push(received),
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        async_std::future::timeout(TIMEOUT, sender_task).await??;
+        async_std::future::timeout(TIMEOUT, receiver_task).await??;
+
+        assert!(async_std::fs::read(&target).await? == content);
+        for progress in [sender_progress, receiver_progress] {
+            let progress = progress.lock().unwrap();
+            assert_eq!(progress.first(), Some(&(resume_at as u64)));
+            assert_eq!(progress.last(), Some(&(content.len() as u64)));
+        }
+    }
+
+    Ok(())
+}
+
+/** Text messages go through the Wormhole, without a transit connection */
+#[cfg(all(feature = "server", feature = "transfer"))]
+#[async_std::test]
+pub async fn test_local_server_text() -> eyre::Result<()> {
+    init_logger();
+
+    let config = transfer::APP_CONFIG
+        .id(TEST_APPID)
+        .rendezvous_url(local_rendezvous_server().await?);
+
+    /* The receiver may have v2 enabled, the sender doesn't use it for text */
+    for receiver_version in [
+        transfer::AppVersion::default(),
+        transfer::AppVersion::default().enable_v2(),
+    ] {
+        let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+        let code = mailbox.code.clone();
+        let sender_task = async_std::task::spawn(async move {
+            transfer::send_text(
+                Wormhole::connect(mailbox).await?,
+                "correct horse battery staple",
+                futures::future::pending(),
+            )
+            .await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        let receiver_config = config.clone().app_version(receiver_version);
+        let receiver_task = async_std::task::spawn(async move {
+            let wormhole =
+                Wormhole::connect(MailboxConnection::connect(receiver_config, code, false).await?)
+                    .await?;
+            let transfer::ReceiveRequest::Text(req) = transfer::request(
+                wormhole,
+                vec![],
+                transit::Abilities::ALL_ABILITIES,
+                futures::future::pending(),
+            )
+            .await?
+            .unwrap() else {
+                panic!("Expected a text message")
+            };
+            assert_eq!(req.text, "correct horse battery staple");
+            req.accept().await?;
+            eyre::Result::<_>::Ok(())
+        });
+
+        async_std::future::timeout(TIMEOUT, sender_task).await??;
+        async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    }
+
+    Ok(())
+}
+
+/** Send to multiple recipients with the same code, while somebody tries to guess it */
+#[cfg(all(feature = "server", feature = "transfer"))]
+#[async_std::test]
+pub async fn test_local_server_send_many() -> eyre::Result<()> {
+    use std::sync::{Arc, Mutex};
+
+    init_logger();
+
+    let config = transfer::APP_CONFIG
+        .id(TEST_APPID)
+        .rendezvous_url(local_rendezvous_server().await?);
+    let relay = transit::server::RelayServer::new()
+        .listen_tcp("127.0.0.1:0")
+        .await?;
+    let relay_hints = vec![relay.relay_hint()];
+    async_std::task::spawn(relay.run());
+
+    #[derive(Default)]
+    struct Handler {
+        connected: Mutex<Vec<u64>>,
+        finished: Arc<Mutex<Vec<(u64, bool)>>>,
+    }
+
+    impl transfer::SendManyHandler for Handler {
+        fn connected(&self, recipient: u64) {
+            self.connected.lock().unwrap().push(recipient);
+        }
+
+        fn finished(&self, recipient: u64, result: Result<(), transfer::TransferError>) {
+            assert_eq!(
+                self.connected.lock().unwrap().contains(&recipient),
+                result.is_ok()
+            );
+            self.finished
+                .lock()
+                .unwrap()
+                .push((recipient, result.is_ok()));
+        }
+    }
+
+    let handler = Handler::default();
+    let finished = handler.finished.clone();
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+
+    let sender_config = config.clone();
+    let sender_code = code.clone();
+    let sender_relay_hints = relay_hints.clone();
+    let sender_task = async_std::task::spawn(async move {
+        transfer::send_many(
+            Wormhole::connect(mailbox).await?,
+            sender_config,
+            sender_code,
+            sender_relay_hints,
+            transit::Abilities::FORCE_RELAY,
+            || async {
+                Ok(transfer::OfferSend::new_file_bytes(
+                    "hello.txt".into(),
+                    &b"Hello"[..],
+                ))
+            },
+            transfer::SendManyLimits {
+                max_recipients: 3,
+                ..Default::default()
+            },
+            handler,
+            futures::future::pending(),
+        )
+        .await?;
+        eyre::Result::<_>::Ok(())
+    });
+
+    let receiver_task = async_std::task::spawn(async move {
+        for (recipient, code) in [
+            code.clone(),
+            Code::new(&code.nameplate(), "foo-bar"),
+            code.clone(),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            /* Wait for the sender to claim the nameplate again */
+            let mailbox = loop {
+                match MailboxConnection::connect(config.clone(), code.clone(), false).await {
+                    Err(WormholeError::UnclaimedNameplate(_)) => {
+                        async_std::task::sleep(Duration::from_millis(50)).await
+                    },
+                    mailbox => break mailbox?,
+                }
+            };
+            let wormhole = Wormhole::connect(mailbox).await;
+            if recipient == 1 {
+                assert!(matches!(wormhole, Err(WormholeError::PakeFailed)));
+                continue;
+            }
+            let transfer::ReceiveRequest::V1(req) = transfer::request(
+                wormhole?,
+                relay_hints.clone(),
+                transit::Abilities::FORCE_RELAY,
+                futures::future::pending(),
+            )
+            .await?
+            .unwrap() else {
+                panic!("v2 should be disabled for now")
+            };
+            let mut content = Vec::new();
+            req.accept(
+                |_info| {},
+                &mut content,
+                |_received, _total| {},
+                futures::future::pending(),
+            )
+            .await?;
+            assert_eq!(content, b"Hello");
+        }
+        eyre::Result::<_>::Ok(())
+    });
+
+    async_std::future::timeout(TIMEOUT, receiver_task).await??;
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    let mut finished = finished.lock().unwrap().clone();
+    finished.sort();
+    assert_eq!(finished, [(0, true), (1, false), (2, true)]);
+    Ok(())
+}
+
+/** Dilate both sides of a fresh wormhole, using a relay server at `relay_hint` */
+#[cfg(all(feature = "server", feature = "dilation"))]
+async fn dilated_pair(
+    relay_hint: transit::RelayHint,
+) -> eyre::Result<(dilation::DilatedWormhole, dilation::DilatedWormhole)> {
+    let config = APP_CONFIG.rendezvous_url(local_rendezvous_server().await?);
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+
+    let relay_hints = vec![relay_hint.clone()];
+    let leader_task = async_std::task::spawn(async move {
+        let wormhole = Wormhole::connect(mailbox).await?;
+        assert!(wormhole
+            .peer_abilities()
+            .iter()
+            .any(|ability| ability == dilation::ABILITY));
+        eyre::Result::<_>::Ok(
+            dilation::dilate(wormhole, transit::Abilities::FORCE_RELAY, relay_hints).await?,
+        )
+    });
+    let follower_task = async_std::task::spawn(async move {
+        let wormhole =
+            Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await?;
+        eyre::Result::<_>::Ok(
+            dilation::dilate(wormhole, transit::Abilities::FORCE_RELAY, vec![relay_hint]).await?,
+        )
+    });
+
+    Ok((leader_task.await?, follower_task.await?))
+}
+
+#[cfg(all(feature = "server", feature = "dilation"))]
+#[async_std::test]
+pub async fn test_local_server_dilation() -> eyre::Result<()> {
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    init_logger();
+
+    let relay = transit::server::RelayServer::new()
+        .listen_tcp("127.0.0.1:0")
+        .await?;
+    let relay_hint = relay.relay_hint();
+    async_std::task::spawn(relay.run());
+    let (mut a, mut b) = dilated_pair(relay_hint).await?;
+
+    /* Larger than the flow control window */
+    let payload: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    let a_task = async_std::task::spawn({
+        let payload = payload.clone();
+        async move {
+            let mut subchannel = a.open_subchannel().await?;
+            subchannel.write_all(&payload).await?;
+            subchannel.close().await?;
+            let mut echo = Vec::new();
+            subchannel.read_to_end(&mut echo).await?;
+            assert_eq!(echo, payload);
+
+            let mut incoming = a.accept_subchannel().await?;
+            let mut hello = String::new();
+            incoming.read_to_string(&mut hello).await?;
+            assert_eq!(hello, "Hello from the other side");
+            assert_ne!(incoming.id() % 2, subchannel.id() % 2);
+
+            drop((subchannel, incoming));
+            a.close().await?;
+            eyre::Result::<_>::Ok(())
+        }
+    });
+    let b_task = async_std::task::spawn(async move {
+        let mut subchannel = b.accept_subchannel().await?;
+        let mut received = Vec::new();
+        subchannel.read_to_end(&mut received).await?;
+        subchannel.write_all(&received).await?;
+        subchannel.close().await?;
+
+        let mut outgoing = b.open_subchannel().await?;
+        outgoing.write_all(b"Hello from the other side").await?;
+        outgoing.close().await?;
+
+        drop((subchannel, outgoing));
+        b.close().await?;
+        eyre::Result::<_>::Ok(())
+    });
+
+    async_std::future::timeout(TIMEOUT, a_task).await??;
+    async_std::future::timeout(TIMEOUT, b_task).await??;
+    Ok(())
+}
+
+/** A TCP proxy in front of `target`, which can cut all of its connections at once */
+#[cfg(feature = "server")]
+async fn flaky_proxy(
+    target: std::net::SocketAddr,
+) -> eyre::Result<(
+    std::net::SocketAddr,
+    std::sync::Arc<std::sync::Mutex<Vec<async_std::net::TcpStream>>>,
+)> {
+    use async_std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    let connections = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let connections2 = connections.clone();
+    async_std::task::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let Ok(server) = TcpStream::connect(target).await else {
+                continue;
+            };
+            connections2
+                .lock()
+                .unwrap()
+                .extend([client.clone(), server.clone()]);
+            let (upstream, downstream) = (client.clone(), server.clone());
+            async_std::task::spawn(async move { futures::io::copy(upstream, &mut &server).await });
+            async_std::task::spawn(
+                async move { futures::io::copy(downstream, &mut &client).await },
+            );
+        }
+    });
+    Ok((address, connections))
+}
+
+#[cfg(all(feature = "server", feature = "dilation"))]
+#[async_std::test]
+pub async fn test_local_server_dilation_reconnect() -> eyre::Result<()> {
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    init_logger();
+
+    let relay = transit::server::RelayServer::new()
+        .listen_tcp("127.0.0.1:0")
+        .await?;
+    let (proxy, connections) = flaky_proxy(relay.tcp_addr().unwrap()).await?;
+    async_std::task::spawn(relay.run());
+    let relay_hint = transit::RelayHint::new(
+        None,
+        [transit::DirectHint::new("127.0.0.1", proxy.port())],
+        [],
+    );
+    let (a, mut b) = dilated_pair(relay_hint).await?;
+
+    let mut sender = a.open_subchannel().await?;
+    let mut receiver = b.accept_subchannel().await?;
+    let mut buffer = [0; 6];
+
+    sender.write_all(b"before").await?;
+    async_std::future::timeout(TIMEOUT, receiver.read_exact(&mut buffer)).await??;
+    assert_eq!(&buffer, b"before");
+
+    /* Kill the connection. Both sides must notice, reconnect and retransmit */
+    cut_connections(&connections);
+    sender.write_all(b"after!").await?;
+    async_std::future::timeout(TIMEOUT, receiver.read_exact(&mut buffer)).await??;
+    assert_eq!(&buffer, b"after!");
+
+    receiver.write_all(b"replay").await?;
+    async_std::future::timeout(TIMEOUT, sender.read_exact(&mut buffer)).await??;
+    assert_eq!(&buffer, b"replay");
+
+    drop((sender, receiver));
+    let (a, b) = futures::join!(a.close(), b.close());
+    a?;
+    b?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn cut_connections(connections: &std::sync::Mutex<Vec<async_std::net::TcpStream>>) {
+    for connection in connections.lock().unwrap().drain(..) {
+        let _ = connection.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_rendezvous_reconnect() -> eyre::Result<()> {
+    init_logger();
+
+    let server = crate::rendezvous::server::MailboxServer::bind("127.0.0.1:0").await?;
+    let (proxy, connections) = flaky_proxy(server.local_addr()?).await?;
+    async_std::task::spawn(server.run());
+    let config = APP_CONFIG.rendezvous_url(format!("ws://{}/v1", proxy).into());
+
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect(mailbox).await?;
+        wormhole.send(b"ping".to_vec()).await?;
+        assert_eq!(wormhole.receive().await?, b"pong");
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(())
+    });
+
+    /* Lose the connection while the sender is waiting for us in the key exchange */
+    async_std::task::sleep(std::time::Duration::from_millis(200)).await;
+    cut_connections(&connections);
+
+    let mut wormhole =
+        Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await?;
+    assert_eq!(wormhole.receive().await?, b"ping");
+
+    /* And once more, while both sides are connected. Messages that get re-delivered
+     * after reconnecting must not show up twice. */
+    cut_connections(&connections);
+    wormhole.send(b"pong".to_vec()).await?;
+    wormhole.close().await?;
+
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    Ok(())
+}
+
+/** A TLS terminating proxy in front of `target`, which acts as a `wss://` rendezvous server */
+#[cfg(feature = "server")]
+async fn tls_proxy(
+    target: std::net::SocketAddr,
+    require_client_auth: bool,
+) -> eyre::Result<std::net::SocketAddr> {
+    use async_std::net::{TcpListener, TcpStream};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    /*
+     * Writing to a TLS stream may also read from the socket, and the data read that way won't
+     * wake up a concurrent reader. So poll both directions again after every write.
+     */
+    async fn forward(
+        mut client: async_tls::server::TlsStream<TcpStream>,
+        mut server: TcpStream,
+    ) -> std::io::Result<()> {
+        use futures::future::Either;
+
+        let (mut from_client, mut from_server) = ([0; 4096], [0; 4096]);
+        loop {
+            let read = match futures::future::select(
+                client.read(&mut from_client),
+                server.read(&mut from_server),
+            )
+            .await
+            {
+                Either::Left((read, _)) => Either::Left(read?),
+                Either::Right((read, _)) => Either::Right(read?),
+            };
+            match read {
+                Either::Left(0) | Either::Right(0) => return Ok(()),
+                Either::Left(read) => server.write_all(&from_client[..read]).await?,
+                Either::Right(read) => {
+                    client.write_all(&from_server[..read]).await?;
+                    client.flush().await?;
+                },
+            }
+        }
+    }
+
+    let chain = rustls_pemfile::certs(&mut &include_bytes!("../../tests/tls/server.pem")[..])?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(
+        &mut &include_bytes!("../../tests/tls/server-key.pem")[..],
+    )?
+    .remove(0);
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = if require_client_auth {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_parsable_certificates(&rustls_pemfile::certs(
+            &mut &include_bytes!("../../tests/tls/ca.pem")[..],
+        )?);
+        builder.with_client_cert_verifier(
+            rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+        )
+    } else {
+        builder.with_no_client_auth()
+    };
+    let acceptor =
+        async_tls::TlsAcceptor::from(builder.with_single_cert(chain, rustls::PrivateKey(key))?);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let address = listener.local_addr()?;
+    async_std::task::spawn(async move {
+        while let Ok((client, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            async_std::task::spawn(async move {
+                let client = match acceptor.accept(client).await {
+                    Ok(client) => client,
+                    Err(err) => return log::debug!("TLS handshake failed: {}", err),
+                };
+                let Ok(server) = TcpStream::connect(target).await else {
+                    return;
+                };
+                let _ = forward(client, server).await;
+            });
+        }
+    });
+    Ok(address)
+}
+
+#[cfg(feature = "server")]
+fn test_ca() -> crate::rendezvous::tls::TlsConfig {
+    crate::rendezvous::tls::TlsConfig::new()
+        .default_roots(false)
+        .add_root_certificates_pem(include_bytes!("../../tests/tls/ca.pem"))
+        .unwrap()
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_tls() -> eyre::Result<()> {
+    use crate::rendezvous::{tls::SpkiPin, RendezvousServer};
+    init_logger();
+
+    let server = crate::rendezvous::server::MailboxServer::bind("127.0.0.1:0").await?;
+    let proxy = tls_proxy(server.local_addr()?, false).await?;
+    async_std::task::spawn(server.run());
+    let url = format!("wss://localhost:{}/v1", proxy.port());
+    let connect = |tls| {
+        let config = APP_CONFIG.rendezvous_url(url.clone().into()).tls(tls);
+        async move { RendezvousServer::connect_with_config(&config).await }
+    };
+
+    /* Our test CA is not trusted by default */
+    assert!(connect(Default::default()).await.is_err());
+
+    /* Pinning the key of the server works, pinning some other key doesn't */
+    let pin = |pem: &[u8]| {
+        SpkiPin::from_certificate(&rustls_pemfile::certs(&mut &*pem).unwrap()[0]).unwrap()
+    };
+    let server_pin = pin(include_bytes!("../../tests/tls/server.pem"));
+    let other_pin = pin(include_bytes!("../../tests/tls/client.pem"));
+    let (connection, _) = connect(test_ca().spki_pin(server_pin)).await?;
+    connection.shutdown(Mood::Happy).await?;
+    assert!(connect(test_ca().spki_pin(other_pin)).await.is_err());
+
+    /* The same without an `AppConfig` */
+    let (connection, _) =
+        RendezvousServer::connect_with_tls(&APP_CONFIG.id, &url, test_ca().spki_pin(server_pin))
+            .await?;
+    connection.shutdown(Mood::Happy).await?;
+
+    /* And finally, a whole wormhole through it */
+    let config = APP_CONFIG
+        .rendezvous_url(url.into())
+        .tls(test_ca().spki_pin(other_pin).spki_pin(server_pin));
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect(mailbox).await?;
+        wormhole.send(b"hello".to_vec()).await?;
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(())
+    });
+    let mut wormhole =
+        Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await?;
+    assert_eq!(wormhole.receive().await?, b"hello");
+    wormhole.close().await?;
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_mutual_tls() -> eyre::Result<()> {
+    use crate::rendezvous::{tls::ClientIdentity, RendezvousServer};
+    init_logger();
+
+    let server = crate::rendezvous::server::MailboxServer::bind("127.0.0.1:0").await?;
+    let proxy = tls_proxy(server.local_addr()?, true).await?;
+    async_std::task::spawn(server.run());
+    let url = format!("wss://127.0.0.1:{}/v1", proxy.port());
+    let connect = |tls| {
+        let config = APP_CONFIG.rendezvous_url(url.clone().into()).tls(tls);
+        async move { RendezvousServer::connect_with_config(&config).await }
+    };
+
+    assert!(connect(test_ca()).await.is_err());
+
+    let identity = ClientIdentity::from_pem(
+        include_bytes!("../../tests/tls/client.pem"),
+        include_bytes!("../../tests/tls/client-key.pem"),
+    )?;
+    let (mut connection, _) = connect(test_ca().client_identity(identity)).await?;
+    assert!(connection.list_nameplates().await?.is_empty());
+    connection.shutdown(Mood::Happy).await?;
+    Ok(())
+}
+
+/** Answers the `bearer` permission method that some private servers use */
+#[cfg(feature = "server")]
+#[derive(Debug)]
+struct BearerToken(&'static str);
+
+#[cfg(feature = "server")]
+impl crate::rendezvous::PermissionProvider for BearerToken {
+    fn submit_permission(
+        &self,
+        permission_required: &crate::rendezvous::PermissionRequired,
+    ) -> Result<Option<crate::rendezvous::SubmitPermission>, crate::rendezvous::RendezvousError>
+    {
+        use crate::rendezvous::{DefaultPermissionProvider, SubmitPermission};
+
+        if permission_required.other.contains_key("bearer") {
+            Ok(Some(SubmitPermission::Other {
+                method: "bearer".into(),
+                fields: [("token".to_string(), self.0.into())].into_iter().collect(),
+            }))
+        } else {
+            DefaultPermissionProvider.submit_permission(permission_required)
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_permission() -> eyre::Result<()> {
+    use crate::rendezvous::{
+        server::MailboxServer, PermissionRequired, RendezvousError, RendezvousServer,
+        SubmitPermission,
+    };
+    init_logger();
+
+    let server = MailboxServer::bind("127.0.0.1:0")
+        .await?
+        .require_permission(
+            PermissionRequired {
+                other: [("bearer".to_string(), serde_json::json!({}))]
+                    .into_iter()
+                    .collect(),
+                ..Default::default()
+            },
+            |permission| {
+                matches!(permission, SubmitPermission::Other { method, fields }
+                if method == "bearer" && fields.get("token") == Some(&"secret".into()))
+            },
+        );
+    let config = APP_CONFIG.rendezvous_url(server.url().into());
+    async_std::task::spawn(server.run());
+
+    /* We don't know how to answer by default */
+    match RendezvousServer::connect_with_config(&config).await {
+        Err(RendezvousError::Login(methods)) => assert_eq!(methods, ["bearer"]),
+        other => panic!("Expected a login error, got {:?}", other),
+    }
+
+    /* The server refuses wrong tokens */
+    assert!(matches!(
+        RendezvousServer::connect_with_config(
+            &config.clone().permission_provider(BearerToken("guess"))
+        )
+        .await,
+        Err(RendezvousError::Server(_))
+    ));
+
+    /* And lets us in with the right one */
+    let config = config.permission_provider(BearerToken("secret"));
+    let (mut connection, _) = RendezvousServer::connect_with_config(&config).await?;
+    assert!(connection.list_nameplates().await?.is_empty());
+    connection.shutdown(Mood::Happy).await?;
+
+    let mailbox = MailboxConnection::create(config.clone(), 2).await?;
+    let code = mailbox.code.clone();
+    let sender_task = async_std::task::spawn(async move {
+        let mut wormhole = Wormhole::connect(mailbox).await?;
+        wormhole.send(b"hello".to_vec()).await?;
+        wormhole.close().await?;
+        eyre::Result::<_>::Ok(())
+    });
+    let mut wormhole =
+        Wormhole::connect(MailboxConnection::connect(config, code, false).await?).await?;
+    assert_eq!(wormhole.receive().await?, b"hello");
+    wormhole.close().await?;
+    async_std::future::timeout(TIMEOUT, sender_task).await??;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[async_std::test]
+pub async fn test_local_server_hashcash() -> eyre::Result<()> {
+    use crate::rendezvous::{
+        server::MailboxServer, HashcashPermission, PermissionRequired, RendezvousServer,
+        SubmitPermission,
+    };
+    init_logger();
+
+    let server = MailboxServer::bind("127.0.0.1:0")
+        .await?
+        .require_permission(
+            PermissionRequired {
+                hashcash: Some(HashcashPermission {
+                    bits: 6,
+                    resource: "test".into(),
+                }),
+                ..Default::default()
+            },
+            |permission| {
+                matches!(permission, SubmitPermission::Hashcash { stamp }
+                if stamp.split(':').nth(3) == Some("test"))
+            },
+        );
+    let config = APP_CONFIG.rendezvous_url(server.url().into());
+    async_std::task::spawn(server.run());
+
+    let (mut connection, _) = RendezvousServer::connect_with_config(&config).await?;
+    assert!(connection.list_nameplates().await?.is_empty());
+    connection.shutdown(Mood::Happy).await?;
+    Ok(())
+}
+
 fn generate_random_code() -> Code {
     let mut rng = rand::thread_rng();
     let nameplate_string = format!("{}-guitarist-revenge", rng.gen_range(1000..10000));
@@ -580,3 +1706,75 @@ fn test_mood() {
         serde_json::to_string(&Mood::Unwelcome).unwrap()
     );
 }
+
+#[test]
+fn test_code_parse() {
+    use crate::CodeParseError;
+
+    let code: Code = "4-purple-sausages".parse().unwrap();
+    assert_eq!(
+        code.split(),
+        (Nameplate::new("4"), "purple-sausages".into())
+    );
+    assert_eq!(
+        "15-armistice-baboon".parse::<Code>().unwrap().nameplate(),
+        Nameplate::new("15")
+    );
+    /* Custom passwords, if none of the words are from the wordlist */
+    assert!("8-🙈-🙉-🙊".parse::<Code>().is_ok());
+    assert!("1000-foo-bar".parse::<Code>().is_ok());
+
+    assert_eq!(
+        "purple-sausages".parse::<Code>(),
+        Err(CodeParseError::InvalidNameplate("purple".into()))
+    );
+    assert_eq!(
+        "".parse::<Code>(),
+        Err(CodeParseError::InvalidNameplate("".into()))
+    );
+    assert_eq!("4".parse::<Code>(), Err(CodeParseError::MissingPassword));
+    assert_eq!("4-".parse::<Code>(), Err(CodeParseError::MissingPassword));
+    assert_eq!("4-abc".parse::<Code>(), Err(CodeParseError::TooShort));
+    assert_eq!(
+        "4-armistice-baboom".parse::<Code>(),
+        Err(CodeParseError::UnknownWord("baboom".into()))
+    );
+
+    /* Codes from the other bundled wordlists, and from the given one */
+    assert!("4-apfel-adler".parse::<Code>().is_ok());
+    assert!("4-pengyou-xiongmao".parse::<Code>().is_ok());
+    assert_eq!(
+        "4-apfel-adlr".parse::<Code>(),
+        Err(CodeParseError::UnknownWord("adlr".into()))
+    );
+    /* Words that are not in the list are typos, even if they are from a list we don't know */
+    let wordlist = crate::wordlist::Wordlist::parse("11111 abacus\n11112 zulu").unwrap();
+    assert_eq!(
+        "4-abacus-zulu".parse::<Code>(),
+        Err(CodeParseError::UnknownWord("abacus".into()))
+    );
+    assert_eq!(
+        Code::parse_with_wordlist("4-abacus-zuul", &wordlist),
+        Err(CodeParseError::UnknownWord("zuul".into()))
+    );
+    assert!(Code::parse_with_wordlist("4-abacus-zulu", &wordlist).is_ok());
+    assert!(Code::parse_with_wordlist("4-armistice-baboon", &wordlist).is_ok());
+    assert!(Code::parse_with_wordlist("4-armistice-baboom", &wordlist).is_err());
+
+    assert!("0042".parse::<Nameplate>().is_ok());
+    assert!("4a".parse::<Nameplate>().is_err());
+    assert!("-4".parse::<Nameplate>().is_err());
+}
+
+#[async_std::test]
+async fn test_connect_with_invalid_code() {
+    /* This must fail before even trying to reach the server */
+    let result =
+        MailboxConnection::connect(APP_CONFIG, Code("4-armistice-baboom".into()), false).await;
+    assert!(matches!(
+        result,
+        Err(WormholeError::InvalidCode(
+            crate::CodeParseError::UnknownWord(_)
+        ))
+    ));
+}
@@ -1,9 +1,46 @@
+//! The words that codes are made of
+//!
+//! By default, codes use the [PGP word list](https://en.wikipedia.org/wiki/PGP_word_list). Other
+//! lists are bundled (see [`BUNDLED_WORDLISTS`]) or can be loaded from a file, and get used by
+//! setting them in the [`AppConfig`](crate::AppConfig).
+
 use rand::{rngs::OsRng, seq::SliceRandom};
 use serde_json::{self, Value};
-use std::fmt;
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, fmt, path::Path, sync::OnceLock};
+
+/** The names of the wordlists that come with this library, for [`Wordlist::bundled`] */
+pub const BUNDLED_WORDLISTS: &[&str] = &["pgp", "german", "pinyin"];
+
+/** The bundled wordlist that gets used unless set otherwise, the PGP word list */
+pub(crate) const DEFAULT_WORDLIST: &str = "pgp";
+
+/** Number of words in a code, unless set otherwise */
+const DEFAULT_NUM_WORDS: usize = 2;
 
-#[derive(PartialEq)]
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum WordlistError {
+    #[error("Failed to read the wordlist")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("A wordlist needs at least two words")]
+    TooFewWords,
+    #[error(
+        "Invalid word '{}', words must not be empty or contain dashes or whitespace",
+        _0
+    )]
+    InvalidWord(String),
+    #[error("The word '{}' is in the wordlist more than once", _0)]
+    DuplicateWord(String),
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Wordlist {
+    /** Number of words that get chosen for a code */
     pub num_words: usize,
     words: Vec<Vec<String>>,
 }
@@ -20,7 +57,86 @@ impl Wordlist {
         Wordlist { num_words, words }
     }
 
-    #[allow(dead_code)] // TODO make this API public one day
+    /**
+     * A wordlist with the given words, for two words per code
+     *
+     * The words must be unique, and they must not contain dashes or whitespace.
+     */
+    pub fn from_words(words: Vec<String>) -> Result<Self, WordlistError> {
+        let mut seen = HashSet::new();
+        for word in &words {
+            if word.is_empty() || word.contains(|c: char| c == '-' || c.is_whitespace()) {
+                return Err(WordlistError::InvalidWord(word.clone()));
+            }
+            if !seen.insert(word) {
+                return Err(WordlistError::DuplicateWord(word.clone()));
+            }
+        }
+        if words.len() < 2 {
+            return Err(WordlistError::TooFewWords);
+        }
+        Ok(Wordlist {
+            num_words: DEFAULT_NUM_WORDS,
+            words: vec![words],
+        })
+    }
+
+    /**
+     * Parse a wordlist with one word per line
+     *
+     * Only the last column of each line is taken, so lists that number their words (like the
+     * diceware lists of the EFF) work as well. Empty lines and lines starting with `#` get skipped.
+     */
+    pub fn parse(text: &str) -> Result<Self, WordlistError> {
+        let words = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_whitespace().last())
+            .map(str::to_owned)
+            .collect();
+        Self::from_words(words)
+    }
+
+    /** Load a wordlist from a file, see [`Wordlist::parse`] for the format */
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, WordlistError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /** One of the [`BUNDLED_WORDLISTS`], for two words per code */
+    pub fn bundled(name: &str) -> Option<Self> {
+        match name {
+            "pgp" => Some(default_wordlist(DEFAULT_NUM_WORDS)),
+            "german" => Some(Self::parse(include_str!("germanwords.txt")).unwrap()),
+            "pinyin" => Some(Self::parse(include_str!("pinyinwords.txt")).unwrap()),
+            _ => None,
+        }
+    }
+
+    /**
+     * How many bits of entropy each word adds to a code
+     *
+     * This is the same for all words, unless the list uses different words depending on the
+     * position (like the PGP word list does), then it is the average.
+     */
+    pub fn entropy_per_word(&self) -> f64 {
+        self.words
+            .iter()
+            .map(|words| (words.len() as f64).log2())
+            .sum::<f64>()
+            / self.words.len() as f64
+    }
+
+    /** How many bits of entropy the password of a code with `num_words` words has */
+    pub fn entropy(&self) -> f64 {
+        self.words
+            .iter()
+            .cycle()
+            .take(self.num_words)
+            .map(|words| (words.len() as f64).log2())
+            .sum()
+    }
+
     pub fn get_completions(&self, prefix: &str) -> Vec<String> {
         let count_dashes = prefix.matches('-').count();
         let mut completions = Vec::new();
@@ -55,6 +171,39 @@ impl Wordlist {
         completions
     }
 
+    /** Whether `word` may appear in a code, at any position */
+    pub fn contains(&self, word: &str) -> bool {
+        self.words
+            .iter()
+            .any(|words| words.iter().any(|w| w == word))
+    }
+
+    /**
+     * An identifier for the words of this list
+     *
+     * Both sides exchange it, to check that they use compatible lists. Bundled lists are
+     * identified by their name (see [`BUNDLED_WORDLISTS`]), others by a hash of their words.
+     * The number of words per code does not matter.
+     */
+    pub fn id(&self) -> String {
+        if let Some((name, _)) = BUNDLED_WORDLISTS
+            .iter()
+            .zip(bundled_wordlists())
+            .find(|(_, bundled)| bundled.words == self.words)
+        {
+            return name.to_string();
+        }
+        let mut hasher = Sha256::new();
+        for words in &self.words {
+            for word in words {
+                hasher.update(word.as_bytes());
+                hasher.update(b"\n");
+            }
+            hasher.update(b"\n");
+        }
+        format!("custom-{}", hex::encode(&hasher.finalize()[..8]))
+    }
+
     pub fn choose_words(&self) -> String {
         let mut rng = OsRng;
         let components: Vec<String> = self
@@ -103,6 +252,17 @@ pub fn default_wordlist(num_words: usize) -> Wordlist {
     }
 }
 
+/** All of the [`BUNDLED_WORDLISTS`], loaded once */
+pub(crate) fn bundled_wordlists() -> &'static [Wordlist] {
+    static WORDLISTS: OnceLock<Vec<Wordlist>> = OnceLock::new();
+    WORDLISTS.get_or_init(|| {
+        BUNDLED_WORDLISTS
+            .iter()
+            .map(|name| Wordlist::bundled(name).unwrap())
+            .collect()
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -153,6 +313,84 @@ mod test {
         assert_eq!(w.get_completions("purple-sa"), vec!["purple-sausages"]);
     }
 
+    #[test]
+    fn test_bundled() {
+        for name in BUNDLED_WORDLISTS {
+            let w = Wordlist::bundled(name).unwrap();
+            assert_eq!(w.num_words, 2);
+            assert_eq!(w.entropy_per_word(), 8.0);
+            assert_eq!(w.entropy(), 16.0);
+        }
+        assert_eq!(bundled_wordlists().len(), BUNDLED_WORDLISTS.len());
+        assert!(Wordlist::bundled("klingon").is_none());
+
+        let w = Wordlist::bundled("german").unwrap();
+        assert!(w.contains("adler") && w.contains("apfel"));
+        assert_eq!(w.get_completions("apf"), vec!["apfel-"]);
+        let w = Wordlist::bundled("pinyin").unwrap();
+        assert!(w.contains("pengyou") && w.contains("xiongmao"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut w = Wordlist::parse(
+            "# The first words of a diceware list\n\n11111\tabacus\n11112 abdomen\n11113\tabdominal\n\n",
+        )
+        .unwrap();
+        assert_eq!(w.words, vec![vecstrings("abacus abdomen abdominal")]);
+        assert!((w.entropy_per_word() - 3f64.log2()).abs() < 1e-9);
+        w.num_words = 4;
+        assert!((w.entropy() - 4.0 * 3f64.log2()).abs() < 1e-9);
+        assert_eq!(w.choose_words().split('-').count(), 4);
+
+        assert!(matches!(
+            Wordlist::parse("foo\nbar\nfoo"),
+            Err(WordlistError::DuplicateWord(word)) if word == "foo"
+        ));
+        assert!(matches!(
+            Wordlist::parse("foo\nbar-baz"),
+            Err(WordlistError::InvalidWord(word)) if word == "bar-baz"
+        ));
+        assert!(matches!(
+            Wordlist::parse("# Nothing but foo\nfoo"),
+            Err(WordlistError::TooFewWords)
+        ));
+        assert!(matches!(
+            Wordlist::load("/does/not/exist"),
+            Err(WordlistError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn test_contains() {
+        let w = default_wordlist(2);
+        assert!(w.contains("adroitness"));
+        assert!(w.contains("zulu"));
+        assert!(!w.contains("Zulu"));
+        assert!(!w.contains(""));
+    }
+
+    #[test]
+    fn test_id() {
+        for name in BUNDLED_WORDLISTS {
+            assert_eq!(Wordlist::bundled(name).unwrap().id(), *name);
+        }
+        assert_eq!(default_wordlist(3).id(), "pgp");
+
+        let w = Wordlist::new(2, vec![vecstrings("purple sausages")]);
+        assert!(w.id().starts_with("custom-"));
+        assert_eq!(w.id(), Wordlist::new(3, w.words.clone()).id());
+        assert_ne!(
+            w.id(),
+            Wordlist::new(2, vec![vecstrings("purple sausage")]).id()
+        );
+        /* The position of the words matters */
+        assert_ne!(
+            w.id(),
+            Wordlist::new(2, vec![vecstrings("purple"), vecstrings("sausages")]).id()
+        );
+    }
+
     #[test]
     fn test_choose_words() {
         let few_words: Vec<Vec<String>> = vec![vecstrings("purple"), vecstrings("sausages")];
@@ -0,0 +1,1284 @@
+//! Durable, multiplexed connections between two peers
+//!
+//! "Dilating" a [`Wormhole`] turns it into a long-lived connection to the other side. Once dilated,
+//! the peers use their mailbox only to coordinate the underlying [`transit`] connections. If a
+//! connection breaks (for example because one side changed networks), a new one is negotiated
+//! over the mailbox and all data that was in flight gets retransmitted. Applications won't notice.
+//!
+//! On top of that connection, any number of independent [`Subchannel`]s can be opened by either side.
+//! Each subchannel is a reliable, ordered byte stream implementing [`AsyncRead`] and [`AsyncWrite`].
+//!
+//! Both sides must support dilation (it is advertised as the [`ABILITY`] in the versions message)
+//! and both sides must call [`dilate`] on their wormhole.
+//!
+//! ## Protocol
+//!
+//! This implements the Dilation protocol of the Python implementation. Its versions message
+//! contains `"can-dilate": ["1"]` and the `dilation-abilities`, which we send and understand as well.
+//!
+//! The mailbox messages use the phases `dilate-0`, `dilate-1` and so on. Both sides start by sending
+//! `please` with their side identifier, the side with the larger identifier is the leader. Both
+//! sides then send their `connection-hints`. If the connection gets lost, the leader sends
+//! `reconnect` and waits for the follower to answer `reconnecting`, before both sides send new
+//! hints.
+//!
+//! The connections are [`transit`] connections with their own handshake: each side sends
+//! `Magic-Wormhole Dilation Handshake v1 Leader\n\n` or `… Follower\n\n`, followed by a
+//! `Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s` handshake keyed with the dilation key. Afterwards,
+//! all records are Noise messages with a four byte length prefix. The follower sends a key
+//! confirmation message (KCM) on every connection, and the leader answers it on the one it picked.
+//!
+//! Each record carries a single frame, which starts with its type:
+//!
+//! - `0x00` KCM
+//! - `0x01` PING and `0x02` PONG, with a four byte ping ID
+//! - `0x03` OPEN, `0x04` DATA and `0x05` CLOSE, with a four byte subchannel ID and
+//!   a four byte sequence number. DATA frames carry the payload afterwards.
+//! - `0x06` ACK, with the four byte sequence number that it acknowledges
+//!
+//! OPEN, DATA and CLOSE frames are numbered and must be acknowledged by the other side.
+//! Everything that has not been acknowledged yet will be sent again on the next connection.
+//! Subchannel 0 is the control channel, subchannels opened by the leader have odd IDs and
+//! those of the follower even ones.
+
+use crate::{
+    transit::{self, Abilities, Hints, RelayHint, Transit, TransitConnectError, TransitConnector},
+    util, Key, Wormhole, WormholeError,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{AbortHandle, BoxFuture},
+    AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+/// The ability advertised in the versions message by peers that support dilation
+pub const ABILITY: &str = "dilation-v1";
+/// The version of the Dilation protocol, as advertised in `can-dilate`
+pub(crate) const VERSION: &str = "1";
+
+/// Maximum number of payload bytes per `DATA` frame. Noise messages are limited to 64KiB.
+const MAX_PAYLOAD_SIZE: usize = 32 * 1024;
+/// Maximum number of unacknowledged payload bytes before writes start to block
+const MAX_IN_FLIGHT: usize = 1024 * 1024;
+/// Received `DATA` frames that may wait for a subchannel to be read, before we stop reading
+/// from the connection altogether
+const SUBCHANNEL_BUFFER: usize = 16;
+/// Subchannels opened by the peer that may wait to be accepted. Further ones get closed right away.
+const ACCEPT_BUFFER: usize = 16;
+/// Buffer size of the channels between the handles, the connection tasks and the manager
+const CHANNEL_BUFFER: usize = 32;
+/// How often we ping our peer on an established connection
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Consider a connection dead if we didn't hear from our peer for that long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Upper bound for the delay between two connection attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DilationError {
+    #[error("The other side does not support dilation")]
+    Unsupported,
+    #[error("The dilated connection has been closed")]
+    Closed,
+    /// Some deserialization went wrong, we probably got some garbage
+    #[error("Corrupt JSON message received")]
+    ProtocolJson(
+        #[from]
+        #[source]
+        serde_json::Error,
+    ),
+    /// A generic string message for "something went wrong", i.e.
+    /// the peer sent some bullshit message order
+    #[error("Protocol error: {}", _0)]
+    Protocol(Box<str>),
+    #[error("Wormhole connection error")]
+    Wormhole(
+        #[from]
+        #[source]
+        WormholeError,
+    ),
+    #[error("IO error")]
+    IO(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+}
+
+/**
+ * Dilate a wormhole
+ *
+ * The wormhole will be used for coordinating the connections from now on, and can thus not be
+ * used for sending messages anymore. The returned handle is available immediately: subchannels
+ * can be opened and written to before the first connection to the peer has been established.
+ * (Opening subchannels will wait until the other side dilated as well though.)
+ *
+ * The `transit_abilities` and `relay_hints` are used for every connection attempt, like they would
+ * for a single [`transit`] connection.
+ */
+pub async fn dilate(
+    wormhole: Wormhole,
+    transit_abilities: Abilities,
+    relay_hints: Vec<RelayHint>,
+) -> Result<DilatedWormhole, DilationError> {
+    let their_abilities = wormhole
+        .peer_dilation_abilities()
+        .ok_or(DilationError::Unsupported)?;
+
+    let key = wormhole.key().derive_subkey_from_purpose(ABILITY);
+    let (commands_tx, commands) = mpsc::channel(CHANNEL_BUFFER);
+    let (events_tx, events) = mpsc::channel(CHANNEL_BUFFER);
+    let (accepted, incoming) = mpsc::channel(ACCEPT_BUFFER);
+    let window = Arc::new(Window::default());
+
+    let manager = Manager {
+        wormhole,
+        key,
+        our_abilities: transit_abilities,
+        their_abilities,
+        relay_hints,
+        is_leader: None,
+        phase: 0,
+        generation: 0,
+        connector: None,
+        their_hints: None,
+        connecting: None,
+        connection: None,
+        reconnect_timer: None,
+        awaiting_reconnecting: false,
+        failures: 0,
+        events_tx,
+        events,
+        commands_tx: commands_tx.clone(),
+        commands,
+        accepted,
+        window: window.clone(),
+        subchannels: HashMap::new(),
+        next_subchannel: 0,
+        pending_opens: Vec::new(),
+        blocked: None,
+        outbound: VecDeque::new(),
+        next_seqnum: 0,
+        inbound_seqnum: 0,
+        closing: false,
+    };
+
+    Ok(DilatedWormhole {
+        commands: commands_tx,
+        incoming,
+        manager: Some(async_std::task::spawn(manager.run())),
+    })
+}
+
+/**
+ * A dilated wormhole
+ *
+ * Use it to open and accept [`Subchannel`]s. Dropping it closes the connection, but only
+ * [`close`](Self::close) will wait for that to finish.
+ */
+pub struct DilatedWormhole {
+    commands: mpsc::Sender<Command>,
+    incoming: mpsc::Receiver<Subchannel>,
+    manager: Option<async_std::task::JoinHandle<Result<(), DilationError>>>,
+}
+
+impl std::fmt::Debug for DilatedWormhole {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("DilatedWormhole").finish_non_exhaustive()
+    }
+}
+
+impl DilatedWormhole {
+    /** Open a new subchannel to the other side */
+    pub async fn open_subchannel(&self) -> Result<Subchannel, DilationError> {
+        let (reply, subchannel) = oneshot::channel();
+        self.commands
+            .clone()
+            .send(Command::Open(reply))
+            .await
+            .map_err(|_| DilationError::Closed)?;
+        subchannel.await.map_err(|_| DilationError::Closed)
+    }
+
+    /**
+     * Wait for the other side to open a subchannel
+     *
+     * Only a few subchannels can wait to be accepted. If the other side opens more of them,
+     * they will get closed right away.
+     */
+    pub async fn accept_subchannel(&mut self) -> Result<Subchannel, DilationError> {
+        self.incoming.next().await.ok_or(DilationError::Closed)
+    }
+
+    /**
+     * Close the dilated connection
+     *
+     * This waits until everything written to our subchannels has been received by the other side.
+     * Afterwards, the wormhole will be closed as well.
+     */
+    pub async fn close(mut self) -> Result<(), DilationError> {
+        let _ = self.commands.send(Command::Shutdown).await;
+        self.manager.take().unwrap().await
+    }
+}
+
+impl Drop for DilatedWormhole {
+    fn drop(&mut self) {
+        if self.manager.is_some() {
+            send_detached(&mut self.commands, Command::Shutdown);
+        }
+    }
+}
+
+/**
+ * A reliable, ordered byte stream to the other side of a [`DilatedWormhole`]
+ *
+ * Closing it only closes our direction: the other side will read EOF, but may continue
+ * to send data. Dropping it closes both directions.
+ *
+ * Writes wait while too much data is in flight, and the other side stops receiving
+ * while a subchannel is not being read.
+ */
+pub struct Subchannel {
+    id: u32,
+    commands: mpsc::Sender<Command>,
+    window: Arc<Window>,
+    incoming: mpsc::Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    offset: usize,
+    write_closed: bool,
+}
+
+impl std::fmt::Debug for Subchannel {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Subchannel")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+impl Subchannel {
+    /**
+     * The identifier of this subchannel
+     *
+     * Subchannels opened by the leader are odd, those of the follower even.
+     * Subchannel 0 is the control channel and never handed out.
+     */
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl AsyncRead for Subchannel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        while self.offset >= self.buffer.len() {
+            match futures::ready!(self.incoming.poll_next_unpin(cx)) {
+                Some(data) => {
+                    self.buffer = data;
+                    self.offset = 0;
+                },
+                /* EOF */
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let this = &mut *self;
+        let length = buf.len().min(this.buffer.len() - this.offset);
+        buf[..length].copy_from_slice(&this.buffer[this.offset..this.offset + length]);
+        this.offset += length;
+        Poll::Ready(Ok(length))
+    }
+}
+
+impl AsyncWrite for Subchannel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.write_closed {
+            return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        /* Only reserve window space once we are sure that we can send the data */
+        futures::ready!(self.commands.poll_ready(cx))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        let length = buf.len().min(MAX_PAYLOAD_SIZE);
+        futures::ready!(self.window.poll_reserve(cx, length))?;
+        let id = self.id;
+        self.commands
+            .start_send(Command::Data {
+                id,
+                payload: buf[..length].to_vec(),
+            })
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Poll::Ready(Ok(length))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        /* Everything written is already queued for sending */
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.write_closed {
+            if futures::ready!(self.commands.poll_ready(cx)).is_ok() {
+                let id = self.id;
+                let _ = self.commands.start_send(Command::Close(id));
+            }
+            self.write_closed = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for Subchannel {
+    fn drop(&mut self) {
+        if !self.write_closed {
+            send_detached(&mut self.commands, Command::Close(self.id));
+        }
+    }
+}
+
+/** Send a command without waiting, for use in `Drop` */
+fn send_detached(commands: &mut mpsc::Sender<Command>, command: Command) {
+    if let Err(err) = commands.try_send(command) {
+        if err.is_full() {
+            /* Every sender has a guaranteed slot, so this won't wait for long */
+            let mut commands = commands.clone();
+            let command = err.into_inner();
+            async_std::task::spawn(async move {
+                let _ = commands.send(command).await;
+            });
+        }
+    }
+}
+
+/**
+ * Flow control shared between all subchannels
+ *
+ * Writes reserve space for their payload, and the space will be freed
+ * again once the other side acknowledged having received it.
+ */
+#[derive(Default)]
+struct Window {
+    state: Mutex<WindowState>,
+}
+
+#[derive(Default)]
+struct WindowState {
+    in_flight: usize,
+    closed: bool,
+    wakers: Vec<Waker>,
+}
+
+impl Window {
+    fn poll_reserve(&self, cx: &mut Context<'_>, length: usize) -> Poll<io::Result<()>> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        } else if state.in_flight >= MAX_IN_FLIGHT {
+            state.wakers.push(cx.waker().clone());
+            Poll::Pending
+        } else {
+            state.in_flight += length;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn release(&self, length: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= length;
+        state.wakers.drain(..).for_each(Waker::wake);
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+/* From the handles to the manager */
+enum Command {
+    Open(oneshot::Sender<Subchannel>),
+    Data { id: u32, payload: Vec<u8> },
+    Close(u32),
+    Shutdown,
+}
+
+/* From the connection tasks to the manager */
+enum Event {
+    Record { generation: u64, record: Box<[u8]> },
+    Lost { generation: u64 },
+}
+
+/* Sent over the mailbox, in the `dilate-N` phases */
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+enum DilationMessage {
+    /* The first message of both sides */
+    Please {
+        side: String,
+    },
+    ConnectionHints {
+        hints: Hints,
+    },
+    /* Sent by the leader when it lost the connection */
+    Reconnect,
+    /* The follower's answer to `reconnect` */
+    Reconnecting,
+    /* Our own extension, to end the dilation once everything has been delivered.
+     * Other implementations ignore it. */
+    Close,
+    #[serde(other)]
+    Unknown,
+}
+
+/* Sent over the transit connection, one per record */
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    /* The key confirmation message, which is part of the handshake */
+    Kcm,
+    Ping(u32),
+    Pong(u32),
+    Open {
+        seqnum: u32,
+        id: u32,
+    },
+    Data {
+        seqnum: u32,
+        id: u32,
+        payload: Vec<u8>,
+    },
+    Close {
+        seqnum: u32,
+        id: u32,
+    },
+    Ack(u32),
+}
+
+impl Frame {
+    fn encode(&self) -> Box<[u8]> {
+        let mut record = Vec::new();
+        match self {
+            Frame::Kcm => {
+                record.push(0x00);
+            },
+            Frame::Ping(ping) => {
+                record.push(0x01);
+                record.extend_from_slice(&ping.to_be_bytes());
+            },
+            Frame::Pong(ping) => {
+                record.push(0x02);
+                record.extend_from_slice(&ping.to_be_bytes());
+            },
+            Frame::Open { seqnum, id } => {
+                record.push(0x03);
+                record.extend_from_slice(&id.to_be_bytes());
+                record.extend_from_slice(&seqnum.to_be_bytes());
+            },
+            Frame::Data {
+                seqnum,
+                id,
+                payload,
+            } => {
+                record.push(0x04);
+                record.extend_from_slice(&id.to_be_bytes());
+                record.extend_from_slice(&seqnum.to_be_bytes());
+                record.extend_from_slice(payload);
+            },
+            Frame::Close { seqnum, id } => {
+                record.push(0x05);
+                record.extend_from_slice(&id.to_be_bytes());
+                record.extend_from_slice(&seqnum.to_be_bytes());
+            },
+            Frame::Ack(seqnum) => {
+                record.push(0x06);
+                record.extend_from_slice(&seqnum.to_be_bytes());
+            },
+        }
+        record.into_boxed_slice()
+    }
+
+    fn decode(record: &[u8]) -> Result<Self, Box<str>> {
+        fn read_u32(bytes: &[u8]) -> Result<u32, Box<str>> {
+            bytes
+                .get(..4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(|| "Truncated frame".into())
+        }
+        /* Subchannel ID and sequence number */
+        fn read_header(body: &[u8]) -> Result<(u32, u32), Box<str>> {
+            Ok((
+                read_u32(body)?,
+                read_u32(body.get(4..).unwrap_or_default())?,
+            ))
+        }
+
+        let (kind, body) = record.split_first().ok_or("Empty frame")?;
+        Ok(match kind {
+            0x00 => Frame::Kcm,
+            0x01 => Frame::Ping(read_u32(body)?),
+            0x02 => Frame::Pong(read_u32(body)?),
+            0x03 => {
+                let (id, seqnum) = read_header(body)?;
+                Frame::Open { seqnum, id }
+            },
+            0x04 => {
+                let (id, seqnum) = read_header(body)?;
+                Frame::Data {
+                    seqnum,
+                    id,
+                    payload: body[8..].to_vec(),
+                }
+            },
+            0x05 => {
+                let (id, seqnum) = read_header(body)?;
+                Frame::Close { seqnum, id }
+            },
+            0x06 => Frame::Ack(read_u32(body)?),
+            other => bail!(format!("Unknown frame type {}", other)),
+        })
+    }
+
+    /** `OPEN`, `DATA` and `CLOSE` must be acknowledged and may be retransmitted */
+    fn seqnum(&self) -> Option<u32> {
+        match self {
+            Frame::Open { seqnum, .. }
+            | Frame::Data { seqnum, .. }
+            | Frame::Close { seqnum, .. } => Some(*seqnum),
+            _ => None,
+        }
+    }
+}
+
+/** Compare two sequence numbers, accounting for wrap-around */
+fn seqnum_before(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < u32::MAX / 2
+}
+
+/* An established transit connection of some generation */
+struct Connection {
+    /* This does not need a bound: `DATA` frames are limited by the flow control window,
+     * and everything else is small and sent in response to the peer's frames. */
+    records: mpsc::UnboundedSender<Box<[u8]>>,
+    task: AbortHandle,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/* Our view on a subchannel */
+struct SubchannelState {
+    /* `None` once the peer closed their direction or our handle got dropped */
+    incoming: Option<mpsc::Sender<Vec<u8>>>,
+    we_closed: bool,
+    they_closed: bool,
+}
+
+/* A frame that has not been acknowledged yet */
+struct Unacked {
+    seqnum: u32,
+    record: Box<[u8]>,
+    payload_length: usize,
+}
+
+type ConnectFuture =
+    BoxFuture<'static, Result<(Transit, transit::TransitInfo), TransitConnectError>>;
+
+/* Owns the wormhole, and drives everything from a background task */
+struct Manager {
+    wormhole: Wormhole,
+    key: Key<transit::TransitKey>,
+    our_abilities: Abilities,
+    their_abilities: Abilities,
+    relay_hints: Vec<RelayHint>,
+    /* Known once we got the `please` message from the peer */
+    is_leader: Option<bool>,
+    /* Counter for our `dilate-N` mailbox phases */
+    phase: u64,
+
+    /* Counts the connection attempts, to ignore events of old connections */
+    generation: u64,
+    /* Our side of the next connection attempt, once our hints have been sent */
+    connector: Option<TransitConnector>,
+    their_hints: Option<Hints>,
+    connecting: Option<ConnectFuture>,
+    connection: Option<Connection>,
+    /* The leader waits a bit before it asks for a reconnect */
+    reconnect_timer: Option<BoxFuture<'static, ()>>,
+    /* The leader sent `reconnect`, and ignores all hints until the follower confirms it */
+    awaiting_reconnecting: bool,
+    /* Consecutive failed connection attempts, for the backoff */
+    failures: u32,
+    events_tx: mpsc::Sender<Event>,
+    events: mpsc::Receiver<Event>,
+
+    commands_tx: mpsc::Sender<Command>,
+    commands: mpsc::Receiver<Command>,
+    accepted: mpsc::Sender<Subchannel>,
+    window: Arc<Window>,
+    subchannels: HashMap<u32, SubchannelState>,
+    next_subchannel: u32,
+    /* Subchannels that were requested before we knew our role */
+    pending_opens: Vec<oneshot::Sender<Subchannel>>,
+    /* Data for a subchannel whose buffer is full. We stop reading
+     * from the connection until it has been delivered. */
+    blocked: Option<(u32, Vec<u8>)>,
+
+    outbound: VecDeque<Unacked>,
+    next_seqnum: u32,
+    /* The sequence number we expect next from the peer */
+    inbound_seqnum: u32,
+    closing: bool,
+}
+
+enum Step {
+    Mailbox(Vec<u8>),
+    Connected(Result<(Transit, transit::TransitInfo), TransitConnectError>),
+    Reconnect,
+    Connection(Event),
+    /* `false` if the subchannel of the blocked data went away */
+    Unblocked(bool),
+    Command(Command),
+}
+
+/** Poll an optional future, or never resolve if there is none */
+async fn maybe<F: std::future::Future + Unpin>(future: &mut Option<F>) -> F::Output {
+    match future {
+        Some(future) => future.await,
+        None => futures::future::pending().await,
+    }
+}
+
+/** Wait for the next event, unless we are blocked on delivering data */
+async fn next_event(events: &mut mpsc::Receiver<Event>, blocked: bool) -> Event {
+    if blocked {
+        futures::future::pending().await
+    } else {
+        events.next().await.expect("We hold a sender")
+    }
+}
+
+/** Wait until there is room for the blocked data in its subchannel */
+async fn unblocked(
+    blocked: &Option<(u32, Vec<u8>)>,
+    subchannels: &mut HashMap<u32, SubchannelState>,
+) -> bool {
+    let Some((id, _)) = blocked else {
+        return futures::future::pending().await;
+    };
+    match subchannels
+        .get_mut(id)
+        .and_then(|state| state.incoming.as_mut())
+    {
+        Some(incoming) => futures::future::poll_fn(|cx| incoming.poll_ready(cx))
+            .await
+            .is_ok(),
+        None => false,
+    }
+}
+
+impl Manager {
+    async fn run(mut self) -> Result<(), DilationError> {
+        let result = self.run_inner().await;
+        self.window.close();
+        /* Signal EOF to everybody who is still reading */
+        self.subchannels.clear();
+        self.accepted.close_channel();
+        self.connection = None;
+        match result {
+            Ok(()) => {
+                self.wormhole.close().await?;
+                Ok(())
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn run_inner(&mut self) -> Result<(), DilationError> {
+        let side = self.wormhole.side().to_owned();
+        self.send_mailbox(&DilationMessage::Please { side }).await?;
+        loop {
+            if self.closing && self.outbound.is_empty() {
+                log::debug!("Everything has been delivered, closing the dilated wormhole");
+                self.send_mailbox(&DilationMessage::Close).await?;
+                return Ok(());
+            }
+
+            let is_blocked = self.blocked.is_some();
+            let step = futures::select! {
+                message = Box::pin(self.wormhole.receive_dilation_message().fuse()) => {
+                    Step::Mailbox(message?)
+                },
+                result = Box::pin(maybe(&mut self.connecting).fuse()) => Step::Connected(result),
+                () = Box::pin(maybe(&mut self.reconnect_timer).fuse()) => Step::Reconnect,
+                event = Box::pin(next_event(&mut self.events, is_blocked).fuse()) => Step::Connection(event),
+                ready = Box::pin(unblocked(&self.blocked, &mut self.subchannels).fuse()) => Step::Unblocked(ready),
+                command = self.commands.next() => Step::Command(command.expect("We hold a sender")),
+            };
+
+            match step {
+                Step::Mailbox(message) => {
+                    if self
+                        .handle_mailbox(serde_json::from_slice(&message)?)
+                        .await?
+                    {
+                        log::info!("The other side closed the dilated wormhole");
+                        return Ok(());
+                    }
+                },
+                Step::Connected(Ok((transit, info))) => {
+                    self.connecting = None;
+                    transit::log_transit_connection(info);
+                    self.failures = 0;
+                    self.connected(transit);
+                },
+                Step::Connected(Err(err)) => {
+                    self.connecting = None;
+                    log::warn!("Failed to establish a dilated connection: {}", err);
+                    self.connection_lost();
+                },
+                Step::Reconnect => {
+                    self.reconnect_timer = None;
+                    log::debug!("Asking the follower to reconnect");
+                    self.connector = None;
+                    self.their_hints = None;
+                    self.awaiting_reconnecting = true;
+                    self.send_mailbox(&DilationMessage::Reconnect).await?;
+                },
+                Step::Connection(Event::Record { generation, record }) => {
+                    if generation == self.generation && self.connection.is_some() {
+                        Frame::decode(&record)
+                            .and_then(|frame| self.handle_frame(frame))
+                            .map_err(DilationError::Protocol)?;
+                    }
+                },
+                Step::Connection(Event::Lost { generation }) => {
+                    if generation == self.generation && self.connection.is_some() {
+                        log::info!("Lost the dilated connection, reconnecting …");
+                        self.connection = None;
+                        self.connection_lost();
+                    }
+                },
+                Step::Unblocked(ready) => {
+                    let (id, payload) = self.blocked.take().unwrap();
+                    let state = self.subchannels.get_mut(&id);
+                    if let Some(state) = state {
+                        let delivered = ready
+                            && state
+                                .incoming
+                                .as_mut()
+                                .is_some_and(|incoming| incoming.start_send(payload).is_ok());
+                        if !delivered {
+                            log::debug!("Subchannel {} is not being read anymore", id);
+                            state.incoming = None;
+                        }
+                    }
+                },
+                Step::Command(command) => self.handle_command(command),
+            }
+        }
+    }
+
+    /** Returns `true` if the peer closed the wormhole */
+    async fn handle_mailbox(&mut self, message: DilationMessage) -> Result<bool, DilationError> {
+        match message {
+            DilationMessage::Please { side } => {
+                if self.is_leader.is_some() {
+                    log::warn!("Ignoring duplicate dilation request");
+                    return Ok(false);
+                }
+                ensure!(
+                    side != self.wormhole.side(),
+                    DilationError::Protocol("The other side uses our side identifier".into())
+                );
+                /* The side with the "larger" identifier wins */
+                let is_leader = self.wormhole.side() > side.as_str();
+                log::debug!(
+                    "We are the {} of the dilated wormhole",
+                    if is_leader { "leader" } else { "follower" }
+                );
+                self.is_leader = Some(is_leader);
+                for reply in std::mem::take(&mut self.pending_opens) {
+                    self.open_subchannel(reply);
+                }
+                self.send_hints().await?;
+            },
+            DilationMessage::ConnectionHints { hints } => {
+                if self.awaiting_reconnecting {
+                    log::debug!("Ignoring hints that were sent before the reconnect");
+                } else {
+                    self.their_hints = Some(hints);
+                    self.try_connect();
+                }
+            },
+            DilationMessage::Reconnect => {
+                if self.is_leader == Some(false) {
+                    log::info!("The other side lost the dilated connection, reconnecting …");
+                    self.connection = None;
+                    self.connecting = None;
+                    self.their_hints = None;
+                    self.send_mailbox(&DilationMessage::Reconnecting).await?;
+                    self.send_hints().await?;
+                } else {
+                    log::warn!("Ignoring unexpected reconnect message");
+                }
+            },
+            DilationMessage::Reconnecting => {
+                if self.is_leader == Some(true) && self.awaiting_reconnecting {
+                    self.awaiting_reconnecting = false;
+                    self.send_hints().await?;
+                } else {
+                    log::warn!("Ignoring unexpected reconnecting message");
+                }
+            },
+            DilationMessage::Close => return Ok(true),
+            DilationMessage::Unknown => log::warn!("Ignoring unknown dilation message"),
+        }
+        Ok(false)
+    }
+
+    async fn send_mailbox(&mut self, message: &DilationMessage) -> Result<(), DilationError> {
+        self.wormhole
+            .send_dilation_message(self.phase, &serde_json::to_vec(message).unwrap())
+            .await?;
+        self.phase += 1;
+        Ok(())
+    }
+
+    /** Offer our hints for the next connection attempt */
+    async fn send_hints(&mut self) -> Result<(), DilationError> {
+        let connector = transit::init(self.our_abilities, None, self.relay_hints.clone()).await?;
+        self.send_mailbox(&DilationMessage::ConnectionHints {
+            hints: (**connector.our_hints()).clone(),
+        })
+        .await?;
+        self.connector = Some(connector);
+        self.try_connect();
+        Ok(())
+    }
+
+    /** Start connecting once we have the hints of both sides */
+    fn try_connect(&mut self) {
+        let (Some(is_leader), Some(_), Some(_)) =
+            (self.is_leader, &self.connector, &self.their_hints)
+        else {
+            return;
+        };
+        if self.connecting.is_some() || self.connection.is_some() {
+            return;
+        }
+        self.generation += 1;
+        log::debug!("Starting connection attempt {}", self.generation);
+        self.connecting = Some(Box::pin(self.connector.take().unwrap().dilation_connect(
+            is_leader,
+            Key::new(self.key.0.clone()),
+            self.their_abilities,
+            Arc::new(self.their_hints.take().unwrap()),
+        )));
+    }
+
+    /** The leader asks for a new connection after a while, the follower waits for that */
+    fn connection_lost(&mut self) {
+        if self.is_leader == Some(true) {
+            self.failures += 1;
+            let delay = Duration::from_millis(500)
+                .saturating_mul(1 << self.failures.min(8))
+                .min(MAX_RECONNECT_DELAY);
+            log::debug!("Reconnecting in {:?}", delay);
+            self.reconnect_timer = Some(Box::pin(util::sleep(delay)));
+        } else {
+            log::debug!("Waiting for the leader to reconnect");
+        }
+    }
+
+    fn connected(&mut self, transit: Transit) {
+        let (records, mut outgoing) = mpsc::unbounded::<Box<[u8]>>();
+        let generation = self.generation;
+        let mut events = self.events_tx.clone();
+        let pings = records.clone();
+
+        let (sink, stream) = transit.split();
+        let (task, abort) = futures::future::abortable(async move {
+            let mut sink = Box::pin(sink);
+            let mut stream = Box::pin(stream);
+            let writer = async {
+                while let Some(record) = outgoing.next().await {
+                    if let Err(err) = sink.send(record).await {
+                        log::debug!("Failed to write to the dilated connection: {}", err);
+                        break;
+                    }
+                }
+            };
+            let mut records_events = events.clone();
+            let reader = async {
+                loop {
+                    match util::timeout(IDLE_TIMEOUT, stream.next()).await {
+                        Ok(Some(Ok(record))) => {
+                            /* Waiting here stops reading when the manager is busy */
+                            let event = Event::Record { generation, record };
+                            if records_events.send(event).await.is_err() {
+                                break;
+                            }
+                        },
+                        Ok(Some(Err(err))) => {
+                            log::debug!("Failed to read from the dilated connection: {}", err);
+                            break;
+                        },
+                        Ok(None) => break,
+                        Err(_) => {
+                            log::debug!("The dilated connection timed out");
+                            break;
+                        },
+                    }
+                }
+            };
+            let pinger = async {
+                for ping in 0.. {
+                    util::sleep(PING_INTERVAL).await;
+                    if pings.unbounded_send(Frame::Ping(ping).encode()).is_err() {
+                        break;
+                    }
+                }
+            };
+            futures::select! {
+                () = Box::pin(writer).fuse() => (),
+                () = Box::pin(reader).fuse() => (),
+                () = Box::pin(pinger).fuse() => (),
+            }
+            let _ = events.send(Event::Lost { generation }).await;
+        });
+        async_std::task::spawn(task);
+
+        /* Everything that the peer did not acknowledge yet needs to be sent again */
+        for unacked in &self.outbound {
+            let _ = records.unbounded_send(unacked.record.clone());
+        }
+        self.connection = Some(Connection {
+            records,
+            task: abort,
+        });
+    }
+
+    /** Send a frame that does not need to be acknowledged, if we are connected */
+    fn send_frame(&mut self, frame: Frame) {
+        if let Some(connection) = &self.connection {
+            let _ = connection.records.unbounded_send(frame.encode());
+        }
+    }
+
+    /** Send a frame reliably, across reconnects */
+    fn queue_frame(&mut self, frame: impl FnOnce(u32) -> Frame) {
+        let seqnum = self.next_seqnum;
+        self.next_seqnum = self.next_seqnum.wrapping_add(1);
+        let frame = frame(seqnum);
+        let payload_length = match &frame {
+            Frame::Data { payload, .. } => payload.len(),
+            _ => 0,
+        };
+        let record = frame.encode();
+        if let Some(connection) = &self.connection {
+            let _ = connection.records.unbounded_send(record.clone());
+        }
+        self.outbound.push_back(Unacked {
+            seqnum,
+            record,
+            payload_length,
+        });
+    }
+
+    fn new_subchannel(&mut self, id: u32) -> Subchannel {
+        let (incoming_tx, incoming) = mpsc::channel(SUBCHANNEL_BUFFER);
+        self.subchannels.insert(
+            id,
+            SubchannelState {
+                incoming: Some(incoming_tx),
+                we_closed: false,
+                they_closed: false,
+            },
+        );
+        Subchannel {
+            id,
+            commands: self.commands_tx.clone(),
+            window: self.window.clone(),
+            incoming,
+            buffer: Vec::new(),
+            offset: 0,
+            write_closed: false,
+        }
+    }
+
+    fn open_subchannel(&mut self, reply: oneshot::Sender<Subchannel>) {
+        /* Subchannel 0 is reserved. Leader IDs are odd and follower IDs are even,
+         * so that both sides can open subchannels without coordination. */
+        self.next_subchannel += 1;
+        let id = self.next_subchannel * 2 - u32::from(self.is_leader.unwrap());
+        let subchannel = self.new_subchannel(id);
+        self.queue_frame(|seqnum| Frame::Open { seqnum, id });
+        /* If the caller went away, dropping the subchannel will close it again */
+        let _ = reply.send(subchannel);
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Open(reply) => {
+                if self.is_leader.is_some() {
+                    self.open_subchannel(reply);
+                } else {
+                    /* We don't know which IDs we may use yet */
+                    self.pending_opens.push(reply);
+                }
+            },
+            Command::Data { id, payload } => {
+                self.queue_frame(|seqnum| Frame::Data {
+                    seqnum,
+                    id,
+                    payload,
+                });
+            },
+            Command::Close(id) => {
+                if let Some(state) = self.subchannels.get_mut(&id) {
+                    state.we_closed = true;
+                    if state.they_closed {
+                        self.subchannels.remove(&id);
+                    }
+                    self.queue_frame(|seqnum| Frame::Close { seqnum, id });
+                }
+            },
+            Command::Shutdown => {
+                log::debug!("Closing the dilated wormhole once everything has been delivered");
+                self.closing = true;
+            },
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Box<str>> {
+        if let Some(seqnum) = frame.seqnum() {
+            if seqnum_before(seqnum, self.inbound_seqnum) {
+                /* Retransmitted after a reconnect, but we already have it */
+                self.send_frame(Frame::Ack(seqnum));
+                return Ok(());
+            }
+            ensure!(
+                seqnum == self.inbound_seqnum,
+                format!("Expected frame {}, got {}", self.inbound_seqnum, seqnum)
+            );
+            self.inbound_seqnum = self.inbound_seqnum.wrapping_add(1);
+            self.send_frame(Frame::Ack(seqnum));
+        }
+
+        match frame {
+            /* Already checked during the handshake */
+            Frame::Kcm => {},
+            Frame::Ping(ping) => self.send_frame(Frame::Pong(ping)),
+            Frame::Pong(_) => {},
+            Frame::Ack(seqnum) => {
+                while let Some(unacked) = self.outbound.front() {
+                    if seqnum_before(seqnum, unacked.seqnum) {
+                        break;
+                    }
+                    self.window.release(unacked.payload_length);
+                    self.outbound.pop_front();
+                }
+            },
+            Frame::Open { id, .. } => {
+                ensure!(
+                    id != 0 && id % 2 == u32::from(!self.is_leader.unwrap_or_default()),
+                    format!("Subchannel {} must not be opened by peer", id)
+                );
+                ensure!(
+                    !self.subchannels.contains_key(&id),
+                    format!("Subchannel {} is already open", id)
+                );
+                let subchannel = self.new_subchannel(id);
+                /* If nobody is accepting, dropping the subchannel will close it again */
+                if let Err(err) = self.accepted.try_send(subchannel) {
+                    if err.is_full() {
+                        log::warn!(
+                            "Too many subchannels waiting to be accepted, closing {}",
+                            id
+                        );
+                    }
+                }
+            },
+            Frame::Data { id, payload, .. } => match self.subchannels.get_mut(&id) {
+                Some(SubchannelState {
+                    incoming: Some(incoming),
+                    ..
+                }) => {
+                    if let Err(err) = incoming.try_send(payload) {
+                        if err.is_full() {
+                            self.blocked = Some((id, err.into_inner()));
+                        } else {
+                            log::debug!("Subchannel {} is not being read anymore", id);
+                            self.subchannels.get_mut(&id).unwrap().incoming = None;
+                        }
+                    }
+                },
+                _ => log::debug!("Dropping data for closed subchannel {}", id),
+            },
+            Frame::Close { id, .. } => {
+                if let Some(state) = self.subchannels.get_mut(&id) {
+                    state.incoming = None;
+                    state.they_closed = true;
+                    if state.we_closed {
+                        self.subchannels.remove(&id);
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transit::DirectHint;
+    use serde_json::json;
+
+    #[test]
+    fn test_frame_encoding() {
+        let frames = [
+            Frame::Kcm,
+            Frame::Ping(7),
+            Frame::Pong(7),
+            Frame::Open { seqnum: 0, id: 1 },
+            Frame::Data {
+                seqnum: 1,
+                id: 1,
+                payload: b"hello".to_vec(),
+            },
+            Frame::Data {
+                seqnum: 2,
+                id: 1,
+                payload: Vec::new(),
+            },
+            Frame::Close {
+                seqnum: u32::MAX,
+                id: 2,
+            },
+            Frame::Ack(3),
+        ];
+        for frame in frames {
+            assert_eq!(Frame::decode(&frame.encode()).unwrap(), frame);
+        }
+
+        assert!(Frame::decode(&[]).is_err());
+        assert!(Frame::decode(&[0x03, 0, 0, 0, 1]).is_err());
+        assert!(Frame::decode(&[0x42]).is_err());
+    }
+
+    /** The records as laid out in the Dilation protocol */
+    #[test]
+    fn test_spec_framing() {
+        assert_eq!(&*Frame::Kcm.encode(), &[0x00]);
+        assert_eq!(&*Frame::Ping(0x01020304).encode(), &[0x01, 1, 2, 3, 4]);
+        assert_eq!(&*Frame::Pong(0x01020304).encode(), &[0x02, 1, 2, 3, 4]);
+        assert_eq!(
+            &*Frame::Open { seqnum: 7, id: 1 }.encode(),
+            &[0x03, 0, 0, 0, 1, 0, 0, 0, 7]
+        );
+        assert_eq!(
+            &*Frame::Data {
+                seqnum: 1,
+                id: 2,
+                payload: vec![0xff]
+            }
+            .encode(),
+            &[0x04, 0, 0, 0, 2, 0, 0, 0, 1, 0xff]
+        );
+        assert_eq!(
+            &*Frame::Close { seqnum: 256, id: 3 }.encode(),
+            &[0x05, 0, 0, 0, 3, 0, 0, 1, 0]
+        );
+        assert_eq!(&*Frame::Ack(258).encode(), &[0x06, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_seqnum_ordering() {
+        assert!(seqnum_before(0, 1));
+        assert!(!seqnum_before(1, 0));
+        assert!(!seqnum_before(5, 5));
+        assert!(seqnum_before(u32::MAX, 0));
+        assert!(!seqnum_before(0, u32::MAX));
+    }
+
+    #[test]
+    fn test_message_encoding() {
+        assert_eq!(
+            serde_json::to_value(DilationMessage::Please {
+                side: "0123456789abcdef".into()
+            })
+            .unwrap(),
+            json!({"type": "please", "side": "0123456789abcdef"})
+        );
+        assert_eq!(
+            serde_json::to_value(DilationMessage::Reconnect).unwrap(),
+            json!({"type": "reconnect"})
+        );
+        assert_eq!(
+            serde_json::to_value(DilationMessage::Reconnecting).unwrap(),
+            json!({"type": "reconnecting"})
+        );
+        assert_eq!(
+            serde_json::to_value(DilationMessage::ConnectionHints {
+                hints: Hints::new([DirectHint::new("192.168.1.8", 46066)], [])
+            })
+            .unwrap(),
+            json!({
+                "type": "connection-hints",
+                "hints": [{"type": "direct-tcp-v1", "hostname": "192.168.1.8", "port": 46066}],
+            })
+        );
+        assert!(matches!(
+            serde_json::from_str(r#"{"type": "something-new"}"#).unwrap(),
+            DilationMessage::Unknown
+        ));
+    }
+
+    /** Hints as sent by the Python implementation */
+    #[test]
+    fn test_python_hints() {
+        let message: DilationMessage = serde_json::from_value(json!({
+            "type": "connection-hints",
+            "hints": [
+                {"type": "direct-tcp-v1", "priority": 0.0, "hostname": "10.0.0.2", "port": 40125},
+                {"type": "relay-v1", "hints": [
+                    {"type": "direct-tcp-v1", "priority": 0.0, "hostname": "relay.example", "port": 4001},
+                ]},
+            ],
+        }))
+        .unwrap();
+        let DilationMessage::ConnectionHints { hints } = message else {
+            panic!("Wrong message type: {:?}", message);
+        };
+        assert!(hints
+            .direct_tcp
+            .contains(&DirectHint::new("10.0.0.2", 40125)));
+        assert_eq!(hints.relay.len(), 1);
+        assert!(hints.relay[0]
+            .tcp
+            .contains(&DirectHint::new("relay.example", 4001)));
+    }
+}
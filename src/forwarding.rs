@@ -34,14 +34,14 @@ pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
 ///
 /// You **must not** change `id` and `rendezvous_url` to be interoperable.
 /// The `app_version` can be adjusted if you want to disable some features.
-pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersion> {
-    id: AppID(Cow::Borrowed(APPID_RAW)),
-    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
-    app_version: AppVersion {
+pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::new(
+    AppID(Cow::Borrowed(APPID_RAW)),
+    Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    AppVersion {
         transit_abilities: transit::Abilities::ALL_ABILITIES,
         other: serde_json::Value::Null,
     },
-};
+);
 
 /**
  * The application specific version information for this protocol.
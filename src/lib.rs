@@ -14,6 +14,9 @@
 //!
 //! As an alternative to file transfer, there is the [`forwarding`] module, which allows to forward arbitrary TCP connections over the Wormhole/Transit tunnel.
 //!
+//! Applications that need a long-lived connection can use the [`dilation`] module. It turns a Wormhole into a durable connection that
+//! transparently reconnects on network changes and multiplexes any number of independent byte streams ("subchannels").
+//!
 //! Transferring large amounts of data should not be done over the rendezvous server. Instead, you have to set up a [`transit`]
 //! connection. A transit is little more than an encrypted TcpConnection. If a direct connection between both clients is not possible,
 //! a relay server will transparently connect them together. Transit is used by the file transfer for example, but any other AppID protocol
@@ -27,6 +30,8 @@
 #[macro_use]
 mod util;
 mod core;
+#[cfg(feature = "dilation")]
+pub mod dilation;
 #[cfg(feature = "forwarding")]
 pub mod forwarding;
 #[cfg(feature = "transfer")]
@@ -38,6 +43,6 @@ pub mod uri;
 
 pub use crate::core::{
     key::{GenericKey, Key, KeyPurpose, WormholeKey},
-    rendezvous, AppConfig, AppID, Code, MailboxConnection, Mood, Nameplate, Wormhole,
-    WormholeError,
+    rendezvous, wordlist, AppConfig, AppID, Code, CodeCompleter, CodeParseError, MailboxConnection,
+    Mood, Nameplate, Wormhole, WormholeError, MIN_PASSWORD_LENGTH,
 };
@@ -30,7 +30,7 @@ mod cancel;
 mod v1;
 mod v2;
 
-pub use v1::ReceiveRequest as ReceiveRequestV1;
+pub use v1::{ReceiveRequest as ReceiveRequestV1, TextReceiveRequest as ReceiveRequestText};
 pub use v2::ReceiveRequest as ReceiveRequestV2;
 
 const APPID_RAW: &str = "lothar.com/wormhole/text-or-file-xfer";
@@ -42,11 +42,11 @@ pub const APPID: AppID = AppID(Cow::Borrowed(APPID_RAW));
 ///
 /// You **must not** change `id` and `rendezvous_url` to be interoperable.
 /// The `app_version` can be adjusted if you want to disable some features.
-pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::<AppVersion> {
-    id: AppID(Cow::Borrowed(APPID_RAW)),
-    rendezvous_url: Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
-    app_version: AppVersion::new(),
-};
+pub const APP_CONFIG: crate::AppConfig<AppVersion> = crate::AppConfig::new(
+    AppID(Cow::Borrowed(APPID_RAW)),
+    Cow::Borrowed(crate::rendezvous::DEFAULT_RENDEZVOUS_SERVER),
+    AppVersion::new(),
+);
 
 // TODO be more extensible on the JSON enum types (i.e. recognize unknown variants)
 
@@ -149,10 +149,45 @@ impl AppVersion {
         }
     }
 
-    #[allow(dead_code)]
-    fn supports_v2(&self) -> bool {
+    /**
+     * Advertize support for the transfer-v2 protocol
+     *
+     * It is still experimental and thus not enabled by default. It will only be used if both
+     * sides enable it. Among other things, it allows resuming interrupted transfers.
+     */
+    pub fn enable_v2(mut self) -> Self {
+        if !self.supports_v2() {
+            self.abilities.to_mut().push("transfer-v2".into());
+        }
+        self
+    }
+
+    /**
+     * Set the zstd compression level for files sent with transfer-v2
+     *
+     * Valid levels go from 1 (fastest) to 22 (smallest), 0 picks zstd's default. `None`, the default,
+     * disables compressing what we send. Files only get compressed if the receiver supports it.
+     */
+    pub fn compression_level(mut self, level: Option<i32>) -> Self {
+        if let Some(transfer_v2) = &mut self.transfer_v2 {
+            transfer_v2.compression_level = level;
+        }
+        self
+    }
+
+    /** Whether we advertize support for the transfer-v2 protocol */
+    pub fn supports_v2(&self) -> bool {
         self.abilities.contains(&"transfer-v2".into())
     }
+
+    /** Whether to use transfer-v2 with this wormhole, both sides must support it */
+    fn use_v2(wormhole: &Wormhole, peer_version: &AppVersion) -> bool {
+        let ours = wormhole
+            .our_version
+            .downcast_ref::<AppVersion>()
+            .map_or(true, AppVersion::supports_v2);
+        ours && peer_version.supports_v2()
+    }
 }
 
 impl Default for AppVersion {
@@ -166,6 +201,12 @@ impl Default for AppVersion {
 pub struct AppVersionTransferV2Hint {
     supported_formats: Cow<'static, [Cow<'static, str>]>,
     transit_abilities: transit::Abilities,
+    /** The compression algorithms we can decompress */
+    #[serde(default)]
+    compression: Cow<'static, [Cow<'static, str>]>,
+    /** How hard we compress what we send, this is not part of the protocol */
+    #[serde(skip)]
+    compression_level: Option<i32>,
 }
 
 impl AppVersionTransferV2Hint {
@@ -173,8 +214,18 @@ impl AppVersionTransferV2Hint {
         Self {
             supported_formats: Cow::Borrowed(&[Cow::Borrowed("plain"), Cow::Borrowed("tar")]),
             transit_abilities: transit::Abilities::ALL_ABILITIES,
+            #[cfg(not(target_family = "wasm"))]
+            compression: Cow::Borrowed(&[Cow::Borrowed("zstd")]),
+            #[cfg(target_family = "wasm")]
+            compression: Cow::Borrowed(&[]),
+            compression_level: None,
         }
     }
+
+    /** Whether the peer with this hint can decompress zstd */
+    fn supports_zstd(&self) -> bool {
+        self.compression.contains(&"zstd".into())
+    }
 }
 
 impl Default for AppVersionTransferV2Hint {
@@ -218,7 +269,6 @@ pub enum PeerMessage {
 }
 
 impl PeerMessage {
-    #[allow(unused)]
     fn offer_message_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Offer(v1::OfferMessage::Message(msg.into()))
     }
@@ -247,7 +297,6 @@ impl PeerMessage {
         })
     }
 
-    #[allow(dead_code)]
     fn message_ack_v1(msg: impl Into<String>) -> Self {
         PeerMessage::Answer(v1::AnswerMessage::MessageAck(msg.into()))
     }
@@ -341,9 +390,30 @@ impl OfferSend {
     /// as advertized in file_size.
     pub fn new_file_custom(offer_name: String, size: u64, content: OfferContent) -> Self {
         let mut content_ = BTreeMap::new();
-        content_.insert(offer_name, OfferSendEntry::RegularFile { size, content });
+        content_.insert(
+            offer_name,
+            OfferSendEntry::RegularFile {
+                size,
+                metadata: FileMetadata::default(),
+                content,
+            },
+        );
         Self { content: content_ }
     }
+
+    /// Offer a single file with the given content, without touching the file system
+    pub fn new_file_bytes(offer_name: String, content: impl Into<Arc<[u8]>>) -> Self {
+        let content: Arc<[u8]> = content.into();
+        let size = content.len() as u64;
+        Self::new_file_custom(
+            offer_name,
+            size,
+            new_offer_content(move || {
+                let content = content.clone();
+                async move { Ok(futures::io::Cursor::new(content)) }
+            }),
+        )
+    }
 }
 
 impl<T> Offer<T> {
@@ -390,27 +460,61 @@ impl<T> Offer<T> {
 
     #[cfg(not(target_family = "wasm"))]
     pub fn accept_all(&self, target_dir: &Path) -> OfferAccept {
-        self.set_content(|path| {
-            let full_path: PathBuf = target_dir.join(path.join("/"));
-            let content = new_accept_content(move |append| {
-                let full_path = full_path.clone();
-                async_std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(append)
-                    .truncate(!append)
-                    .open(full_path)
-            });
-            AcceptInner {
-                content: Box::new(content) as _,
-                offset: 0,
-                sha256: None,
-            }
+        self.set_content(|path| AcceptInner::new_file(target_dir.join(path.join("/")), 0, None))
+    }
+
+    /**
+     * Accept only some of the files, each one to its own destination
+     *
+     * `destination` gets called with the path of every file in the offer, and returns where to
+     * write it, or `None` to skip the file. Directories of the destinations are not created.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn accept_files(
+        &self,
+        mut destination: impl FnMut(&[String]) -> Option<PathBuf>,
+    ) -> OfferAccept {
+        self.filter_content(|path| {
+            destination(path).map(|destination| AcceptInner::new_file(destination, 0, None))
         })
     }
 
+    /**
+     * Accept all files, but continue where a previous attempt left off
+     *
+     * Existing files in `target_dir` that are not larger than offered are taken as partially
+     * received: we hash them and only ask for the missing rest. The sender checks the hash
+     * against its own files, and sends the whole file again if it does not match.
+     *
+     * This only works with peers that support the transfer-v2 protocol, v1 transfers always
+     * start from scratch.
+     */
     #[cfg(not(target_family = "wasm"))]
-    pub async fn create_directories(&self, target_path: &Path) -> std::io::Result<()> {
+    pub async fn accept_all_resumable(&self, target_dir: &Path) -> std::io::Result<OfferAccept> {
+        let files: Vec<_> = self
+            .iter_files()
+            .map(|(path, _, size)| (path, size))
+            .collect();
+        let mut partial_files = std::collections::HashMap::new();
+        for (path, size) in files {
+            if let Some(partial) = partial_file(&target_dir.join(path.join("/")), size).await? {
+                partial_files.insert(path, partial);
+            }
+        }
+
+        Ok(self.set_content(|path| {
+            let (offset, sha256) = partial_files
+                .get(path)
+                .map_or((0, None), |&(offset, sha256)| (offset, Some(sha256)));
+            AcceptInner::new_file(target_dir.join(path.join("/")), offset, sha256)
+        }))
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn create_directories(&self, target_path: &Path) -> std::io::Result<()>
+    where
+        T: Sync,
+    {
         // TODO this could be made more efficient by passing around just one buffer
         for (name, file) in &self.content {
             file.create_directories(&target_path.join(name)).await?;
@@ -418,14 +522,164 @@ impl<T> Offer<T> {
         Ok(())
     }
 
-    // #[cfg(not(target_family = "wasm"))]
-    // pub async fn create_symlinks(&self, target_path: &Path) -> std::io::Result<()> {
-    //     // TODO this could be made more efficient by passing around just one buffer
-    //     for (name, file) in &self.content {
-    //         file.create_symlinks(&target_path.join(name)).await?;
-    //     }
-    //     Ok(())
-    // }
+    /** Recursively list all symlinks, together with their target */
+    pub fn iter_symlinks(&self) -> impl Iterator<Item = (Vec<String>, &str)> + '_ {
+        self.content.iter().flat_map(|(name, offer)| {
+            let name = name.clone();
+            offer.iter_symlinks().map(move |mut val| {
+                val.0.insert(0, name.clone());
+                val
+            })
+        })
+    }
+
+    /**
+     * Check whether the symlink at `path` points to somewhere within this offer
+     *
+     * This is only decided on the paths, without looking at the file system. The target must name an
+     * entry of the offer, so the root of the offer and anything next to it are rejected. Targets that
+     * pass through other symlinks are rejected as well, since those might lead anywhere.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    fn is_contained_symlink(&self, path: &[String], target: &str) -> bool {
+        use std::path::Component;
+
+        /* Relative targets start in the directory of the symlink */
+        let mut resolved = path[..path.len() - 1].to_vec();
+        for component in Path::new(target).components() {
+            if matches!(self.get(&resolved), Some(OfferEntry::Symlink { .. })) {
+                return false;
+            }
+            match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => resolved.push(name.to_owned()),
+                    None => return false,
+                },
+                Component::CurDir => {},
+                Component::ParentDir => {
+                    if resolved.pop().is_none() {
+                        return false;
+                    }
+                },
+                Component::Prefix(_) | Component::RootDir => return false,
+            }
+        }
+        /* `get` rejects the empty path, i.e. the directory the offer gets received into */
+        self.get(&resolved).is_some()
+    }
+
+    /**
+     * Check that all symlinks are allowed by the `policy`
+     *
+     * [`create_symlinks`](Self::create_symlinks) does this too, but you may want to do it before
+     * accepting the offer.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub fn check_symlinks(&self, policy: SymlinkPolicy) -> std::io::Result<()> {
+        if policy != SymlinkPolicy::Contained {
+            return Ok(());
+        }
+        match self
+            .iter_symlinks()
+            .find(|(path, target)| !self.is_contained_symlink(path, target))
+        {
+            Some((path, target)) => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!(
+                    "Refusing to create symlink {} -> {}, it does not point to one of the received files",
+                    path.join("/"),
+                    target
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /**
+     * Create all symlinks of the offer in `target_path`
+     *
+     * Do this after receiving the files, so that no file gets written through a symlink.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn create_symlinks(
+        &self,
+        target_path: &Path,
+        policy: SymlinkPolicy,
+    ) -> std::io::Result<()> {
+        if policy == SymlinkPolicy::Skip {
+            return Ok(());
+        }
+        self.check_symlinks(policy)?;
+
+        /* Don't hold the iterator across await points, to keep this future `Send` */
+        let symlinks = self.iter_symlinks().collect::<Vec<_>>();
+        for (path, target) in symlinks {
+            let link = target_path.join(path.join("/"));
+            #[cfg(unix)]
+            async_std::os::unix::fs::symlink(target, &link).await?;
+            #[cfg(windows)]
+            {
+                /* Windows wants to know whether the target is a directory */
+                let target_dir = path[..path.len() - 1]
+                    .iter()
+                    .fold(target_path.to_owned(), |dir, name| dir.join(name));
+                if async_std::path::Path::new(&target_dir.join(target))
+                    .is_dir()
+                    .await
+                {
+                    async_std::os::windows::fs::symlink_dir(target, &link).await?;
+                } else {
+                    async_std::os::windows::fs::symlink_file(target, &link).await?;
+                }
+            }
+            #[cfg(not(any(unix, windows)))]
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Cannot create symlink {}", link.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     * Apply the modification times and permissions sent along with the files to the received files
+     *
+     * Permissions only get applied on Unix, and without the setuid, setgid and sticky bits.
+     * Do this after receiving the files, since they might not be writable afterwards.
+     */
+    #[cfg(not(target_family = "wasm"))]
+    pub async fn apply_metadata(&self, target_path: &Path) -> std::io::Result<()> {
+        let paths = self.iter_file_paths().collect::<Vec<_>>();
+        for path in paths {
+            let metadata = match self.get(&path) {
+                Some(OfferEntry::RegularFile { metadata, .. }) => *metadata,
+                _ => continue,
+            };
+            let path = target_path.join(path.join("/"));
+
+            if let Some(mtime) = metadata.mtime {
+                let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                let path = path.clone();
+                async_std::task::spawn_blocking(move || {
+                    std::fs::File::options()
+                        .write(true)
+                        .open(path)?
+                        .set_modified(mtime)
+                })
+                .await?;
+            }
+            #[cfg(unix)]
+            if let Some(permissions) = metadata.permissions {
+                use std::os::unix::fs::PermissionsExt;
+                async_std::fs::set_permissions(
+                    &path,
+                    std::fs::Permissions::from_mode(permissions & 0o777),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
 
     pub fn offer_name(&self) -> String {
         let (name, entry) = self.content.iter().next().unwrap();
@@ -463,6 +717,20 @@ impl<T> Offer<T> {
                 .collect(),
         }
     }
+
+    /** Like [`Offer::set_content`], but drops all files for which `f` returns `None` */
+    pub fn filter_content<U>(&self, mut f: impl FnMut(&[String]) -> Option<U>) -> Offer<U> {
+        Offer {
+            content: self
+                .content
+                .iter()
+                .filter_map(|(k, v)| {
+                    v.filter_content(&mut vec![k.clone()], &mut f)
+                        .map(|v| (k.clone(), v))
+                })
+                .collect(),
+        }
+    }
 }
 
 impl<T: 'static + Send> Offer<T> {
@@ -519,61 +787,137 @@ pub type OfferSendEntry = OfferEntry<OfferContent>;
 #[serde(tag = "type")]
 #[serde(bound(deserialize = "T: Default"))]
 pub enum OfferEntry<T = ()> {
+    /**
+     * A file of `size` bytes
+     *
+     * **Breaking change in 0.7.0:** the `metadata` field is new. Use `FileMetadata::default()`
+     * where it is unknown, or build files with [`OfferEntry::regular_file`]. Offers from peers
+     * that don't send any metadata get the default one.
+     */
     RegularFile {
         size: u64,
+        #[serde(flatten)]
+        metadata: FileMetadata,
         #[serde(skip)]
         content: T,
     },
     Directory {
         content: BTreeMap<String, Self>,
     },
-    // Symlink {
-    //     target: String,
-    // },
+    Symlink {
+        target: String,
+    },
+}
+
+/**
+ * Optional metadata of a file in an offer
+ *
+ * Senders fill this in when they know it, receivers may choose to apply it with
+ * [`Offer::apply_metadata`].
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct FileMetadata {
+    /** Modification time, in seconds since the Unix epoch */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    /** Unix permission bits, like `0o755` */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<u32>,
+}
+
+impl FileMetadata {
+    pub fn new(mtime: Option<u64>, permissions: Option<u32>) -> Self {
+        Self { mtime, permissions }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    fn from_fs(metadata: &std::fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_secs());
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o777)
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+        Self { mtime, permissions }
+    }
+}
+
+/**
+ * Which symlinks of an offer to create when receiving it
+ *
+ * A malicious sender could otherwise use them to make us, or whoever uses the received
+ * files afterwards, read or write files elsewhere.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum SymlinkPolicy {
+    /** Refuse the offer if a symlink has an absolute target or does not point to one of the received files */
+    #[default]
+    Contained,
+    /** Create all symlinks as they are */
+    Any,
+    /** Don't create any symlinks */
+    Skip,
 }
 
 impl OfferSendEntry {
+    /**
+     * Walk `path`
+     *
+     * If the path itself is a symlink, it will be followed. Symlinks within directories are
+     * not followed but sent as such.
+     */
     #[cfg(not(target_family = "wasm"))]
     async fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::new_inner(path, true).await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    async fn new_inner(path: impl AsRef<Path>, follow_symlinks: bool) -> std::io::Result<Self> {
         // Workaround for https://github.com/rust-lang/rust/issues/78649
         #[inline(always)]
         fn new_recurse<'a>(
             path: impl AsRef<Path> + 'a + Send,
         ) -> futures::future::BoxFuture<'a, std::io::Result<OfferSendEntry>> {
-            Box::pin(OfferSendEntry::new(path))
+            Box::pin(OfferSendEntry::new_inner(path, false))
         }
 
         let path = path.as_ref();
-        // let metadata = async_std::fs::symlink_metadata(path).await?;
-        let metadata = async_std::fs::metadata(path).await?;
-        // let mtime = metadata.modified()?
-        //     .duration_since(std::time::SystemTime::UNIX_EPOCH)
-        //     .unwrap_or_default()
-        //     .as_secs();
+        let metadata = if follow_symlinks {
+            async_std::fs::metadata(path).await?
+        } else {
+            async_std::fs::symlink_metadata(path).await?
+        };
         if metadata.is_file() {
             log::trace!("OfferSendEntry::new {path:?} is file");
             let path = path.to_owned();
             Ok(Self::RegularFile {
                 size: metadata.len(),
+                metadata: FileMetadata::from_fs(&metadata),
                 content: new_offer_content(move || {
                     let path = path.clone();
                     async_std::fs::File::open(path)
                 }),
             })
-        // } else if metadata.is_symlink() {
-        //     log::trace!("OfferSendEntry::new {path:?} is symlink");
-        //     let target = async_std::fs::read_link(path).await?;
-        //     Ok(Self::Symlink {
-        //         target: target
-        //             .to_str()
-        //             .ok_or_else(|| {
-        //                 std::io::Error::new(
-        //                     std::io::ErrorKind::Other,
-        //                     format!("{} is not UTF-8 encoded", target.display()),
-        //                 )
-        //             })?
-        //             .to_string(),
-        //     })
+        } else if metadata.is_symlink() {
+            log::trace!("OfferSendEntry::new {path:?} is symlink");
+            let target = async_std::fs::read_link(path).await?;
+            Ok(Self::Symlink {
+                target: target
+                    .to_str()
+                    .ok_or_else(|| {
+                        std::io::Error::other(format!("{} is not UTF-8 encoded", target.display()))
+                    })?
+                    .to_string(),
+            })
         } else if metadata.is_dir() {
             use futures::TryStreamExt;
             log::trace!("OfferSendEntry::new {path:?} is directory");
@@ -606,6 +950,15 @@ impl OfferSendEntry {
 }
 
 impl<T> OfferEntry<T> {
+    /** A file of `size` bytes, with the given `metadata` (which may be the default, unknown one) */
+    pub fn regular_file(size: u64, metadata: FileMetadata, content: T) -> Self {
+        Self::RegularFile {
+            size,
+            metadata,
+            content,
+        }
+    }
+
     /** Recursively list all files, without directory names or symlinks. */
     fn iter_files(&self) -> impl Iterator<Item = (Vec<String>, &T, u64)> + '_ {
         // TODO I couldn't think up a less efficient way to do this ^^
@@ -620,10 +973,30 @@ impl<T> OfferEntry<T> {
                 });
                 Box::new(iter) as Box<dyn Iterator<Item = _>>
             },
-            Self::RegularFile { content, size } => {
+            Self::RegularFile { content, size, .. } => {
                 Box::new(std::iter::once((vec![], content, *size))) as Box<dyn Iterator<Item = _>>
             },
-            // Self::Symlink { .. } => Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _>>,
+            Self::Symlink { .. } => Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _>>,
+        }
+    }
+
+    /** Recursively list all symlinks, together with their target */
+    fn iter_symlinks(&self) -> impl Iterator<Item = (Vec<String>, &str)> + '_ {
+        match self {
+            Self::Directory { content, .. } => {
+                let iter = content.iter().flat_map(|(name, offer)| {
+                    let name = name.clone();
+                    offer.iter_symlinks().map(move |mut val| {
+                        val.0.insert(0, name.clone());
+                        val
+                    })
+                });
+                Box::new(iter) as Box<dyn Iterator<Item = _>>
+            },
+            Self::RegularFile { .. } => Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _>>,
+            Self::Symlink { target } => {
+                Box::new(std::iter::once((vec![], target.as_str()))) as Box<dyn Iterator<Item = _>>
+            },
         }
     }
 
@@ -642,7 +1015,7 @@ impl<T> OfferEntry<T> {
     fn get_file(&self, path: &[String]) -> Option<(&T, u64)> {
         match path {
             [] => match self {
-                Self::RegularFile { content, size } => Some((content, *size)),
+                Self::RegularFile { content, size, .. } => Some((content, *size)),
                 _ => None,
             },
             [start, rest @ ..] => match self {
@@ -655,17 +1028,21 @@ impl<T> OfferEntry<T> {
     }
 
     #[cfg(not(target_family = "wasm"))]
-    async fn create_directories(&self, target_path: &Path) -> std::io::Result<()> {
+    async fn create_directories(&self, target_path: &Path) -> std::io::Result<()>
+    where
+        T: Sync,
+    {
         #[inline(always)]
-        fn recurse<'a, T>(
+        fn recurse<'a, T: Sync>(
             this: &'a OfferEntry<T>,
             path: &'a Path,
-        ) -> futures::future::LocalBoxFuture<'a, std::io::Result<()>> {
+        ) -> futures::future::BoxFuture<'a, std::io::Result<()>> {
             Box::pin(OfferEntry::create_directories(this, path))
         }
         match self {
             Self::Directory { content, .. } => {
-                async_std::fs::create_dir(target_path).await?;
+                /* They might already exist if we resume a previous transfer */
+                async_std::fs::create_dir_all(target_path).await?;
                 for (name, file) in content {
                     recurse(file, &target_path.join(name)).await?;
                 }
@@ -675,37 +1052,15 @@ impl<T> OfferEntry<T> {
         }
     }
 
-    // #[cfg(not(target_family = "wasm"))]
-    // async fn create_symlinks(&self, target_path: &Path) -> std::io::Result<()> {
-    //     #[inline(always)]
-    //     fn recurse<'a, T>(
-    //         this: &'a OfferEntry<T>,
-    //         path: &'a Path,
-    //     ) -> futures::future::LocalBoxFuture<'a, std::io::Result<()>> {
-    //         Box::pin(OfferEntry::create_symlinks(this, path))
-    //     }
-    //     match self {
-    //         Self::Symlink { target } => {
-    //             todo!()
-    //         },
-    //         Self::Directory { content, .. } => {
-    //             for (name, file) in content {
-    //                 recurse(file, &target_path.join(name)).await?;
-    //             }
-    //             Ok(())
-    //         },
-    //         _ => Ok(()),
-    //     }
-    // }
-
     fn set_content<U>(
         &self,
         base_path: &mut Vec<String>,
         f: &mut impl FnMut(&[String]) -> U,
     ) -> OfferEntry<U> {
         match self {
-            OfferEntry::RegularFile { size, .. } => OfferEntry::RegularFile {
+            OfferEntry::RegularFile { size, metadata, .. } => OfferEntry::RegularFile {
                 size: *size,
+                metadata: *metadata,
                 content: f(base_path),
             },
             OfferEntry::Directory { content } => OfferEntry::Directory {
@@ -719,9 +1074,39 @@ impl<T> OfferEntry<T> {
                     })
                     .collect(),
             },
-            // OfferEntry::Symlink { target } => OfferEntry::Symlink {
-            //     target: target.clone(),
-            // },
+            OfferEntry::Symlink { target } => OfferEntry::Symlink {
+                target: target.clone(),
+            },
+        }
+    }
+
+    fn filter_content<U>(
+        &self,
+        base_path: &mut Vec<String>,
+        f: &mut impl FnMut(&[String]) -> Option<U>,
+    ) -> Option<OfferEntry<U>> {
+        match self {
+            OfferEntry::RegularFile { size, metadata, .. } => {
+                f(base_path).map(|content| OfferEntry::RegularFile {
+                    size: *size,
+                    metadata: *metadata,
+                    content,
+                })
+            },
+            OfferEntry::Directory { content } => Some(OfferEntry::Directory {
+                content: content
+                    .iter()
+                    .filter_map(|(k, v)| {
+                        base_path.push(k.clone());
+                        let v = v.filter_content(base_path, f);
+                        base_path.pop();
+                        v.map(|v| (k.clone(), v))
+                    })
+                    .collect(),
+            }),
+            OfferEntry::Symlink { target } => Some(OfferEntry::Symlink {
+                target: target.clone(),
+            }),
         }
     }
 }
@@ -740,13 +1125,13 @@ impl<T: 'static + Send> OfferEntry<T> {
                 });
                 Box::new(iter) as Box<dyn Iterator<Item = _> + Send>
             },
-            Self::RegularFile { content, size } => {
+            Self::RegularFile { content, size, .. } => {
                 Box::new(std::iter::once((vec![], content, size)))
                     as Box<dyn Iterator<Item = _> + Send>
             },
-            // Self::Symlink { .. } => {
-            //     Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _> + Send>
-            // },
+            Self::Symlink { .. } => {
+                Box::new(std::iter::empty()) as Box<dyn Iterator<Item = _> + Send>
+            },
         }
     }
 }
@@ -797,6 +1182,56 @@ pub struct AcceptInner {
     pub content: AcceptContent,
 }
 
+impl AcceptInner {
+    /** Write to `path`, appending if we resume at `offset` */
+    #[cfg(not(target_family = "wasm"))]
+    fn new_file(path: PathBuf, offset: u64, sha256: Option<[u8; 32]>) -> Self {
+        let content = new_accept_content(move |append| {
+            async_std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(append)
+                .truncate(!append)
+                .open(path.clone())
+        });
+        AcceptInner {
+            content: Box::new(content) as _,
+            offset,
+            sha256,
+        }
+    }
+}
+
+/**
+ * Check for a partially received file at `path`
+ *
+ * Returns its length and the hash of its content, or `None` if there is nothing to resume from.
+ */
+#[cfg(not(target_family = "wasm"))]
+async fn partial_file(path: &Path, size: u64) -> std::io::Result<Option<(u64, [u8; 32])>> {
+    use futures::AsyncReadExt;
+    use sha2::{digest::FixedOutput, Digest, Sha256};
+
+    let file = match async_std::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let metadata = file.metadata().await?;
+    let len = metadata.len();
+    if !metadata.is_file() || len == 0 || len > size {
+        return Ok(None);
+    }
+
+    let mut hasher = Sha256::new();
+    futures::io::copy(
+        file.take(len),
+        &mut futures::io::AllowStdIo::new(&mut hasher),
+    )
+    .await?;
+    Ok(Some((len, hasher.finalize_fixed().into())))
+}
+
 pub async fn send(
     wormhole: Wormhole,
     relay_hints: Vec<transit::RelayHint>,
@@ -807,13 +1242,14 @@ pub async fn send(
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
-    if peer_version.supports_v2() {
+    if AppVersion::use_v2(&wormhole, &peer_version) {
         v2::send(
             wormhole,
             relay_hints,
             transit_abilities,
             offer,
             progress_handler,
+            transit_handler,
             peer_version,
             cancel,
         )
@@ -834,7 +1270,192 @@ pub async fn send(
 }
 
 /**
- * Wait for a file offer from the other side
+ * When [`send_many`] stops waiting for further recipients
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendManyLimits {
+    /// Send to at most this many recipients. This is also the number of tries a potential
+    /// attacker gets at guessing the code.
+    pub max_recipients: u64,
+    /// Stop waiting for new recipients once this much time has passed
+    pub timeout: std::time::Duration,
+}
+
+impl Default for SendManyLimits {
+    fn default() -> Self {
+        Self {
+            max_recipients: 30,
+            timeout: std::time::Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/**
+ * Callbacks for the individual recipients of [`send_many`]
+ *
+ * Recipients are numbered in the order in which they connect, starting at zero. The transfers
+ * run concurrently, so the calls for different recipients may interleave.
+ */
+pub trait SendManyHandler: Send + Sync {
+    /// A recipient connected, and the transfer to it is about to start
+    fn connected(&self, _recipient: u64) {}
+
+    /// The transit connection to a recipient has been established
+    fn transit(&self, _recipient: u64, _info: transit::TransitInfo) {}
+
+    /// The number of bytes sent to a recipient, and the total amount of bytes
+    fn progress(&self, _recipient: u64, _sent: u64, _total: u64) {}
+
+    /// The transfer to a recipient is over
+    ///
+    /// This also gets called if the recipient failed to connect, for example because they
+    /// entered a wrong code.
+    fn finished(&self, _recipient: u64, _result: Result<(), TransferError>) {}
+}
+
+/**
+ * Send the same files to multiple recipients, using the same code for all of them
+ *
+ * The first recipient is the peer of `wormhole`. After that, this reconnects to the rendezvous
+ * server with `config` and `code` and waits for the next recipient, while the previous transfers
+ * keep running. Every recipient gets a fresh offer from `make_offer`.
+ *
+ * This stops waiting for recipients once one of the `limits` is reached or `cancel` resolves,
+ * whichever comes first, but it always sends to at least one recipient. It then waits for the
+ * running transfers to finish, cancelling them too if `cancel` resolves. The outcome of the
+ * individual transfers is reported to the `handler`, errors are only returned if this
+ * cannot continue at all.
+ *
+ * Note that every recipient gives an attacker another try at guessing the code, so consider
+ * using longer codes.
+ */
+pub async fn send_many<F, G>(
+    wormhole: Wormhole,
+    config: crate::AppConfig<AppVersion>,
+    code: crate::Code,
+    relay_hints: Vec<transit::RelayHint>,
+    transit_abilities: transit::Abilities,
+    mut make_offer: F,
+    limits: SendManyLimits,
+    handler: impl SendManyHandler + 'static,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError>
+where
+    F: FnMut() -> G,
+    G: Future<Output = std::io::Result<OfferSend>>,
+{
+    use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
+
+    let handler = Arc::new(handler);
+    let cancel = cancel.shared();
+    let send_to = |recipient: u64, wormhole: Wormhole, offer: OfferSend| {
+        let handler = handler.clone();
+        let transit_handler = handler.clone();
+        let progress_handler = handler.clone();
+        let relay_hints = relay_hints.clone();
+        let cancel = cancel.clone();
+        async move {
+            let result = send(
+                wormhole,
+                relay_hints,
+                transit_abilities,
+                offer,
+                |info| transit_handler.transit(recipient, info),
+                move |sent, total| progress_handler.progress(recipient, sent, total),
+                cancel,
+            )
+            .await;
+            handler.finished(recipient, result);
+        }
+    };
+
+    let mut transfers = FuturesUnordered::new();
+    let deadline = crate::util::sleep(limits.timeout).fuse();
+    futures::pin_mut!(deadline);
+
+    /* Special-case the first recipient with reusing the existing connection */
+    let mut next = Some(wormhole);
+    let mut recipient = 0;
+    let result = loop {
+        if let Some(wormhole) = next.take() {
+            let offer = match make_offer().await {
+                Ok(offer) => offer,
+                Err(err) => {
+                    let _ = wormhole.close().await;
+                    break Err(err.into());
+                },
+            };
+            handler.connected(recipient);
+            transfers.push(send_to(recipient, wormhole, offer));
+        }
+        recipient += 1;
+
+        if recipient >= limits.max_recipients {
+            info!("Reached {recipient} recipients, not accepting any new connections");
+            break Ok(());
+        }
+
+        /* Wait for the next recipient, while driving the transfers to the previous ones */
+        let connect = async {
+            let mailbox =
+                crate::MailboxConnection::connect(config.clone(), code.clone(), true).await?;
+            Ok::<_, WormholeError>(Wormhole::connect(mailbox).await)
+        }
+        .fuse();
+        futures::pin_mut!(connect);
+        let connection = loop {
+            futures::select! {
+                connection = connect => break Some(connection),
+                () = deadline => {
+                    info!("{:?} have elapsed, not accepting any new connections", limits.timeout);
+                    break None;
+                },
+                () = cancel.clone() => break None,
+                _ = transfers.next() => {},
+            }
+        };
+        match connection {
+            None => break Ok(()),
+            Some(Err(err)) => break Err(err.into()),
+            Some(Ok(Ok(wormhole))) => next = Some(wormhole),
+            Some(Ok(Err(err))) => {
+                warn!("Recipient #{recipient} failed to connect: {err}");
+                handler.finished(recipient, Err(err.into()));
+            },
+        }
+    };
+
+    while transfers.next().await.is_some() {}
+    result
+}
+
+/**
+ * Send a text message to the other side
+ *
+ * Text messages are part of the v1 protocol, so don't enable transfer-v2 in the [`AppVersion`]
+ * when connecting for sending text.
+ */
+pub async fn send_text(
+    mut wormhole: Wormhole,
+    text: impl Into<String>,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
+    if AppVersion::use_v2(&wormhole, &peer_version) {
+        let error = TransferError::Protocol(
+            "Text messages cannot be sent once transfer-v2 has been negotiated".into(),
+        );
+        let _ = wormhole
+            .send_json(&PeerMessage::error_message(error.to_string()))
+            .await;
+        let _ = wormhole.close().await;
+        bail!(error);
+    }
+    v1::send_text(wormhole, text.into(), cancel).await
+}
+
+/**
+ * Wait for a file offer or text message from the other side
  *
  * This method waits for an offer message and builds up a [`ReceiveRequest`](ReceiveRequest).
  * It will also start building a TCP connection to the other side using the transit protocol.
@@ -848,7 +1469,7 @@ pub async fn request(
     cancel: impl Future<Output = ()>,
 ) -> Result<Option<ReceiveRequest>, TransferError> {
     let peer_version: AppVersion = serde_json::from_value(wormhole.peer_version.clone())?;
-    if peer_version.supports_v2() {
+    if AppVersion::use_v2(&wormhole, &peer_version) {
         v2::request(
             wormhole,
             relay_hints,
@@ -859,21 +1480,40 @@ pub async fn request(
         .await
         .map(|req| req.map(ReceiveRequest::V2))
     } else {
-        v1::request(wormhole, relay_hints, transit_abilities, cancel)
-            .await
-            .map(|req| req.map(ReceiveRequest::V1))
+        v1::request(wormhole, relay_hints, transit_abilities, cancel).await
     }
 }
 
 /**
- * A pending files send offer from the other side
+ * A pending files send offer or text message from the other side
  *
  * You *should* consume this object, by matching on the protocol version and then calling either `accept` or `reject`.
+ * More kinds of requests may be added in the future.
+ *
+ * **Breaking change in 0.7.0:** the `Text` variant is new, and the enum is `#[non_exhaustive]`
+ * now. Matches need a wildcard arm, which may simply [`reject`](Self::reject) the request.
  */
 #[must_use]
+#[non_exhaustive]
 pub enum ReceiveRequest {
     V1(ReceiveRequestV1),
     V2(ReceiveRequestV2),
+    Text(ReceiveRequestText),
+}
+
+impl ReceiveRequest {
+    /**
+     * Reject the request, whatever kind it is
+     *
+     * This will send an error message to the other side so that it knows the transfer failed.
+     */
+    pub async fn reject(self) -> Result<(), TransferError> {
+        match self {
+            Self::V1(req) => req.reject().await,
+            Self::V2(req) => req.reject().await,
+            Self::Text(req) => req.reject().await,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -956,4 +1596,189 @@ mod test {
             "{\"answer\":{\"file_ack\":\"ok\"}}"
         );
     }
+
+    #[async_std::test]
+    async fn test_offer_bytes() {
+        use futures::AsyncReadExt;
+
+        let offer = OfferSend::new_file_bytes("config.toml".into(), b"key = 1\n".to_vec());
+        let (path, content, size) = offer.iter_files().next().unwrap();
+        assert_eq!(path, ["config.toml"]);
+        assert_eq!(size, 8);
+
+        /* The content can be read more than once */
+        for _ in 0..2 {
+            let mut read = Vec::new();
+            content()
+                .await
+                .unwrap()
+                .read_to_end(&mut read)
+                .await
+                .unwrap();
+            assert_eq!(read, b"key = 1\n");
+        }
+    }
+
+    #[test]
+    fn test_filter_content() {
+        let offer: Offer = serde_json::from_value(serde_json::json!({
+            "content": {
+                "a": {"type": "regular-file", "size": 1},
+                "folder": {
+                    "type": "directory",
+                    "content": {
+                        "b": {"type": "regular-file", "size": 2},
+                        "c": {"type": "regular-file", "size": 3},
+                    },
+                },
+            },
+        }))
+        .unwrap();
+
+        let filtered = offer.filter_content(|path| (path != ["folder", "b"]).then_some(path.len()));
+        assert_eq!(
+            filtered.iter_files().collect::<Vec<_>>(),
+            [
+                (vec!["a".to_string()], &1, 1),
+                (vec!["folder".to_string(), "c".to_string()], &2, 3),
+            ]
+        );
+        assert!(offer
+            .filter_content(|_| None::<()>)
+            .iter_files()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_symlink_policy() {
+        let offer = |target: &str| -> Offer {
+            serde_json::from_value(serde_json::json!({
+                "content": {
+                    "folder": {
+                        "type": "directory",
+                        "content": {
+                            "file": {"type": "regular-file", "size": 0},
+                            "link": {"type": "symlink", "target": target},
+                            "here": {"type": "symlink", "target": "."},
+                            "sub": {"type": "directory", "content": {}},
+                        },
+                    },
+                },
+            }))
+            .unwrap()
+        };
+
+        for target in [
+            "file",
+            "./sub/../file",
+            "../folder/sub",
+            "sub/",
+            ".",
+            "here",
+        ] {
+            assert!(
+                offer(target)
+                    .check_symlinks(SymlinkPolicy::Contained)
+                    .is_ok(),
+                "{target}"
+            );
+        }
+        for target in [
+            "/etc/passwd",
+            "../..",
+            "sub/../../..",
+            "..",
+            "../sibling-not-in-offer",
+            "missing",
+            "here/..",
+            "./here/sub",
+        ] {
+            let err = offer(target)
+                .check_symlinks(SymlinkPolicy::Contained)
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied, "{target}");
+            assert!(offer(target).check_symlinks(SymlinkPolicy::Any).is_ok());
+            assert!(offer(target).check_symlinks(SymlinkPolicy::Skip).is_ok());
+        }
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_symlink_offer() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("source");
+        async_std::fs::create_dir_all(source.join("sub")).await?;
+        async_std::fs::write(source.join("file"), b"hello").await?;
+        async_std::os::unix::fs::symlink("../file", source.join("sub/link")).await?;
+
+        let offer = OfferSend::new_file_or_folder("source".into(), &source).await?;
+        let offer = Offer::from(&offer);
+        assert_eq!(
+            offer.get(&["source".into(), "sub".into(), "link".into()]),
+            Some(&OfferEntry::Symlink {
+                target: "../file".into()
+            })
+        );
+        assert_eq!(offer.iter_file_paths().count(), 1);
+
+        let target = dir.path().join("target");
+        offer.create_directories(&target).await?;
+        offer.create_symlinks(&target, SymlinkPolicy::Skip).await?;
+        assert!(
+            async_std::fs::symlink_metadata(target.join("source/sub/link"))
+                .await
+                .is_err()
+        );
+        offer
+            .create_symlinks(&target, SymlinkPolicy::Contained)
+            .await?;
+        assert_eq!(
+            async_std::fs::read_link(target.join("source/sub/link")).await?,
+            async_std::path::Path::new("../file")
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_file_metadata() -> std::io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        async_std::fs::create_dir_all(dir.path().join("source")).await?;
+        async_std::fs::write(dir.path().join("source/script.sh"), b"#!/bin/sh").await?;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.path().join("source/script.sh"))?
+            .set_modified(mtime)?;
+        std::fs::set_permissions(
+            dir.path().join("source/script.sh"),
+            std::fs::Permissions::from_mode(0o750),
+        )?;
+
+        let offer =
+            OfferSend::new_file_or_folder("source".into(), dir.path().join("source")).await?;
+        let offer = Offer::from(&offer);
+        assert_eq!(
+            offer.get(&["source".into(), "script.sh".into()]),
+            Some(&OfferEntry::RegularFile {
+                size: 9,
+                metadata: FileMetadata::new(Some(1_700_000_000), Some(0o750)),
+                content: (),
+            })
+        );
+
+        let target = dir.path().join("target");
+        offer.create_directories(&target).await?;
+        async_std::fs::write(target.join("source/script.sh"), b"#!/bin/sh").await?;
+        offer.apply_metadata(&target).await?;
+        let metadata = async_std::fs::metadata(target.join("source/script.sh")).await?;
+        assert_eq!(metadata.modified()?, mtime);
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+
+        Ok(())
+    }
 }
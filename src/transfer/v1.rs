@@ -108,7 +108,7 @@ pub async fn send(
     } else {
         let (file_name, file) = offer.content.into_iter().next().unwrap();
         let (mut file, file_size) = match file {
-            OfferSendEntry::RegularFile { content, size } => {
+            OfferSendEntry::RegularFile { content, size, .. } => {
                 /* This must be split into two statements to appease the borrow checker (unfortunate side effect of borrow-through) */
                 let content = content();
                 let content = content.await?;
@@ -294,9 +294,13 @@ pub async fn send_folder(
                         path.pop();
                     }
                 },
-                OfferSendEntry::RegularFile { size, content } => {
+                OfferSendEntry::RegularFile {
+                    size,
+                    metadata,
+                    content,
+                } => {
                     log::debug!("Adding file {path:?}; {size} bytes");
-                    let header = tar_helper::create_header_file(path, size)?;
+                    let header = tar_helper::create_header_file(path, size, metadata)?;
                     let padding = tar_helper::padding(size);
                     *total_size += header.len() as u64;
                     *total_size += padding.len() as u64;
@@ -310,7 +314,12 @@ pub async fn send_folder(
                     total_content.push(Box::pin(content) as _);
                     total_content.push(wrap(padding));
                 },
-                // OfferSendEntry::Symlink { .. } => todo!(),
+                OfferSendEntry::Symlink { target } => {
+                    log::debug!("Adding symlink {path:?} -> {target:?}");
+                    let header = tar_helper::create_header_symlink(path, &target)?;
+                    *total_size += header.len() as u64;
+                    total_content.push(wrap(header));
+                },
             }
             Ok(total_content)
         }
@@ -393,8 +402,58 @@ pub async fn send_folder(
     cancel::handle_run_result(wormhole, result).await
 }
 
+/** Send a text message and wait for the other side to acknowledge it */
+pub async fn send_text(
+    mut wormhole: Wormhole,
+    text: String,
+    cancel: impl Future<Output = ()>,
+) -> Result<(), TransferError> {
+    let run = Box::pin(async {
+        debug!("Sending text message");
+        wormhole
+            .send_json(&PeerMessage::offer_message_v1(text))
+            .await?;
+
+        loop {
+            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
+                /* The receiver doesn't know yet that it won't need a transit connection */
+                PeerMessage::Transit(_) => continue,
+                PeerMessage::Answer(AnswerMessage::MessageAck(msg)) => {
+                    ensure!(msg == "ok", TransferError::AckError);
+                    break;
+                },
+                other => {
+                    bail!(TransferError::unexpected_message(
+                        "answer/message_ack",
+                        other
+                    ));
+                },
+            }
+        }
+        debug!("Text message acknowledged");
+
+        Ok(())
+    });
+
+    futures::pin_mut!(cancel);
+    let result = cancel::cancellable_2(run, cancel).await;
+    cancel::handle_run_result(wormhole, result).await
+}
+
+/** What the peer offered us */
+enum Offered {
+    File {
+        filename: String,
+        filesize: u64,
+        connector: TransitConnector,
+        their_abilities: transit::Abilities,
+        their_hints: transit::Hints,
+    },
+    Text(String),
+}
+
 /**
- * Wait for a file offer from the other side
+ * Wait for a file offer or a text message from the other side
  *
  * This method waits for an offer message and builds up a [`ReceiveRequest`](ReceiveRequest).
  * It will also start building a TCP connection to the other side using the transit protocol.
@@ -406,7 +465,7 @@ pub async fn request(
     relay_hints: Vec<transit::RelayHint>,
     transit_abilities: transit::Abilities,
     cancel: impl Future<Output = ()>,
-) -> Result<Option<ReceiveRequest>, TransferError> {
+) -> Result<Option<super::ReceiveRequest>, TransferError> {
     // Error handling
     let run = Box::pin(async {
         let connector = transit::init(transit_abilities, None, relay_hints).await?;
@@ -420,39 +479,46 @@ pub async fn request(
             ))
             .await?;
 
-        // receive transit message
-        let (their_abilities, their_hints): (transit::Abilities, transit::Hints) =
+        // receive transit message, text messages come without one
+        let mut their_transit = None;
+        let offer = loop {
             match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
-                PeerMessage::Transit(transit) => {
+                PeerMessage::Transit(transit) if their_transit.is_none() => {
                     debug!("received transit message: {:?}", transit);
-                    (transit.abilities_v1, transit.hints_v1)
-                },
-                other => {
-                    bail!(TransferError::unexpected_message("transit", other));
-                },
-            };
-
-        // 3. receive file offer message from peer
-        let (filename, filesize) =
-            match wormhole.receive_json::<PeerMessage>().await??.check_err()? {
-                PeerMessage::Offer(offer_type) => match offer_type {
-                    v1::OfferMessage::File { filename, filesize } => (filename, filesize),
-                    v1::OfferMessage::Directory {
-                        mut dirname,
-                        zipsize,
-                        ..
-                    } => {
-                        dirname.push_str(".zip");
-                        (dirname, zipsize)
-                    },
-                    _ => bail!(TransferError::UnsupportedOffer),
+                    their_transit = Some((transit.abilities_v1, transit.hints_v1));
                 },
+                PeerMessage::Offer(offer) => break offer,
                 other => {
                     bail!(TransferError::unexpected_message("offer", other));
                 },
-            };
+            }
+        };
 
-        Ok((filename, filesize, connector, their_abilities, their_hints))
+        // 3. receive file offer message from peer
+        let (filename, filesize) = match offer {
+            v1::OfferMessage::Message(text) => return Ok(Offered::Text(text)),
+            v1::OfferMessage::File { filename, filesize } => (filename, filesize),
+            v1::OfferMessage::Directory {
+                mut dirname,
+                zipsize,
+                ..
+            } => {
+                dirname.push_str(".zip");
+                (dirname, zipsize)
+            },
+            _ => bail!(TransferError::UnsupportedOffer),
+        };
+        let (their_abilities, their_hints) = their_transit.ok_or_else(|| {
+            TransferError::Protocol("Received a file offer without transit hints".into())
+        })?;
+
+        Ok(Offered::File {
+            filename,
+            filesize,
+            connector,
+            their_abilities,
+            their_hints,
+        })
     });
 
     futures::pin_mut!(cancel);
@@ -460,21 +526,71 @@ pub async fn request(
     cancel::handle_run_result_noclose(wormhole, result)
         .await
         .map(|inner: Option<_>| {
-            inner.map(
-                |((filename, filesize, connector, their_abilities, their_hints), wormhole, _)| {
-                    ReceiveRequest {
-                        wormhole,
-                        filename,
-                        filesize,
-                        connector,
-                        their_abilities,
-                        their_hints: Arc::new(their_hints),
-                    }
+            inner.map(|(offered, wormhole, _)| match offered {
+                Offered::File {
+                    filename,
+                    filesize,
+                    connector,
+                    their_abilities,
+                    their_hints,
+                } => super::ReceiveRequest::V1(ReceiveRequest {
+                    wormhole,
+                    filename,
+                    filesize,
+                    connector,
+                    their_abilities,
+                    their_hints: Arc::new(their_hints),
+                }),
+                Offered::Text(text) => {
+                    super::ReceiveRequest::Text(TextReceiveRequest { wormhole, text })
                 },
-            )
+            })
         })
 }
 
+/**
+ * A text message from the other side
+ *
+ * You *should* consume this object, either by calling [`accept`](TextReceiveRequest::accept) or [`reject`](TextReceiveRequest::reject).
+ */
+#[must_use]
+pub struct TextReceiveRequest {
+    wormhole: Wormhole,
+    /// **Security warning:** this is untrusted and unverified input
+    pub text: String,
+}
+
+impl TextReceiveRequest {
+    /**
+     * Acknowledge the text message
+     *
+     * This tells the sender that we got it and closes the Wormhole.
+     */
+    pub async fn accept(mut self) -> Result<(), TransferError> {
+        debug!("Sending ack");
+        self.wormhole
+            .send_json(&PeerMessage::message_ack_v1("ok"))
+            .await?;
+        self.wormhole.close().await?;
+
+        Ok(())
+    }
+
+    /**
+     * Reject the text message
+     *
+     * This will send an error message to the other side so that it knows the transfer failed.
+     */
+    pub async fn reject(mut self) -> Result<(), TransferError> {
+        self.wormhole
+            .send_json(&PeerMessage::error_message("transfer rejected"))
+            .await?;
+        self.wormhole.close().await?;
+
+        Ok(())
+    }
+}
+
 /**
  * A pending files send offer from the other side
  *
@@ -701,12 +817,19 @@ mod tar_helper {
         str,
     };
 
-    pub fn create_header_file(path: &[String], size: u64) -> std::io::Result<Vec<u8>> {
+    pub fn create_header_file(
+        path: &[String],
+        size: u64,
+        metadata: super::FileMetadata,
+    ) -> std::io::Result<Vec<u8>> {
         let mut header = tar::Header::new_gnu();
         header.set_size(size);
         let mut data = Vec::with_capacity(1024);
         prepare_header_path(&mut data, &mut header, path.join("/").as_ref())?;
-        header.set_mode(0o644);
+        header.set_mode(metadata.permissions.unwrap_or(0o644));
+        if let Some(mtime) = metadata.mtime {
+            header.set_mtime(mtime);
+        }
         header.set_cksum();
         data.write_all(header.as_bytes())?;
         Ok(data)
@@ -724,6 +847,19 @@ mod tar_helper {
         Ok(data)
     }
 
+    pub fn create_header_symlink(path: &[String], target: &str) -> std::io::Result<Vec<u8>> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        let mut data = Vec::with_capacity(1024);
+        prepare_header_path(&mut data, &mut header, path.join("/").as_ref())?;
+        prepare_header_link(&mut data, &mut header, target)?;
+        header.set_mode(0o777);
+        header.set_cksum();
+        data.write_all(header.as_bytes())?;
+        Ok(data)
+    }
+
     pub fn padding(size: u64) -> &'static [u8] {
         const BLOCK: [u8; 512] = [0; 512];
         if size % 512 != 0 {
@@ -795,6 +931,24 @@ mod tar_helper {
         Ok(())
     }
 
+    fn prepare_header_link(
+        dst: &mut dyn std::io::Write,
+        header: &mut tar::Header,
+        link_name: &str,
+    ) -> std::io::Result<()> {
+        // Same as with the path, but the GNU extension for long link names is 'K'
+        if let Err(e) = header.set_link_name(link_name) {
+            let data = path2bytes(link_name);
+            if data.len() < header.as_old().linkname.len() {
+                return Err(e);
+            }
+            let header2 = prepare_header(data.len() as u64, b'K');
+            let mut data2 = data.chain(io::repeat(0).take(1));
+            append(dst, &header2, &mut data2)?;
+        }
+        Ok(())
+    }
+
     #[cfg(any(windows, target_arch = "wasm32"))]
     pub fn path2bytes(p: &str) -> Cow<[u8]> {
         let bytes = p.as_bytes();
@@ -827,4 +981,52 @@ mod test {
         let f1 = TransitAck::new("ok", "deadbeaf");
         assert_eq!(f1.serialize(), "{\"ack\":\"ok\",\"sha256\":\"deadbeaf\"}");
     }
+
+    #[test]
+    fn test_tar_file_metadata() {
+        let metadata = FileMetadata::new(Some(1_700_000_000), Some(0o755));
+        let header = tar_helper::create_header_file(&["script.sh".into()], 0, metadata).unwrap();
+        let header = tar::Header::from_byte_slice(&header);
+        assert_eq!(header.mode().unwrap(), 0o755);
+        assert_eq!(header.mtime().unwrap(), 1_700_000_000);
+
+        let header =
+            tar_helper::create_header_file(&["file".into()], 0, FileMetadata::default()).unwrap();
+        let header = tar::Header::from_byte_slice(&header);
+        assert_eq!(header.mode().unwrap(), 0o644);
+    }
+
+    #[test]
+    fn test_tar_symlink() {
+        let long_target = "a/".repeat(100) + "file";
+        let mut data =
+            tar_helper::create_header_symlink(&["folder".into(), "link".into()], "../file")
+                .unwrap();
+        data.extend(
+            tar_helper::create_header_symlink(&["folder".into(), "long".into()], &long_target)
+                .unwrap(),
+        );
+        data.extend([0; 1024]);
+
+        let mut archive = tar::Archive::new(&data[..]);
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                assert_eq!(entry.header().entry_type(), tar::EntryType::Symlink);
+                (
+                    entry.path().unwrap().into_owned(),
+                    entry.link_name().unwrap().unwrap().into_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("folder/link".into(), "../file".into()),
+                ("folder/long".into(), long_target.into()),
+            ]
+        );
+    }
 }
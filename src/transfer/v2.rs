@@ -80,6 +80,23 @@ struct AnswerMessageInner {
 pub struct FileStart {
     pub file: Vec<String>,
     pub start_at_offset: bool,
+    /** How the payload of this file is compressed, if at all */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+}
+
+/**
+ * Compression algorithms for the file payload
+ *
+ * The receiver advertizes which ones it supports in its [`AppVersionTransferV2Hint`], the sender picks
+ * one per file.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Compression {
+    /** A single zstd frame, split across all payload messages */
+    Zstd,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -96,6 +113,117 @@ pub struct FileEnd {}
 #[serde(rename_all = "kebab-case")]
 pub struct TransferAck {}
 
+/** Turns file content into payload, compressing it on the fly if requested */
+enum PayloadEncoder {
+    Plain,
+    #[cfg(not(target_family = "wasm"))]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl PayloadEncoder {
+    fn new(compression: Option<(Compression, i32)>) -> std::io::Result<Self> {
+        match compression {
+            None => Ok(Self::Plain),
+            #[cfg(not(target_family = "wasm"))]
+            Some((Compression::Zstd, level)) => Ok(Self::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                level,
+            )?)),
+            #[cfg(target_family = "wasm")]
+            Some((compression, _)) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{:?} compression is not supported", compression),
+            )),
+        }
+    }
+
+    /** The next payload, may be empty if the compressor buffers the data */
+    fn encode(&mut self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Plain => Ok(content.to_vec()),
+            #[cfg(not(target_family = "wasm"))]
+            Self::Zstd(encoder) => {
+                std::io::Write::write_all(encoder, content)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            },
+        }
+    }
+
+    /** The remaining payload after the end of the file */
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Plain => Ok(Vec::new()),
+            #[cfg(not(target_family = "wasm"))]
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/** The reverse of [`PayloadEncoder`] */
+enum PayloadDecoder {
+    Plain,
+    #[cfg(not(target_family = "wasm"))]
+    Zstd(zstd::stream::raw::Decoder<'static>),
+}
+
+impl PayloadDecoder {
+    fn new(compression: Option<Compression>) -> std::io::Result<Self> {
+        match compression {
+            None => Ok(Self::Plain),
+            #[cfg(not(target_family = "wasm"))]
+            Some(Compression::Zstd) => Ok(Self::Zstd(zstd::stream::raw::Decoder::new()?)),
+            #[cfg(target_family = "wasm")]
+            Some(compression) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{:?} compression is not supported", compression),
+            )),
+        }
+    }
+
+    fn is_compressed(&self) -> bool {
+        !matches!(self, Self::Plain)
+    }
+
+    /**
+     * The file content of the next payload, may be empty if the decompressor needs more data
+     *
+     * Decompression stops with an error as soon as the content would exceed `limit` bytes, so that a
+     * small payload cannot make us allocate arbitrary amounts of memory. Plain payloads are
+     * returned as they are, their size is bounded by the transit record size anyways.
+     */
+    fn decode(&mut self, payload: Vec<u8>, limit: u64) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Plain => Ok(payload),
+            #[cfg(not(target_family = "wasm"))]
+            Self::Zstd(decoder) => {
+                use zstd::stream::raw::{InBuffer, Operation, OutBuffer};
+
+                let mut input = InBuffer::around(&payload);
+                let mut content = Vec::new();
+                let mut buffer = vec![0; zstd::zstd_safe::DCtx::out_size()];
+                loop {
+                    let mut output = OutBuffer::around(&mut buffer[..]);
+                    decoder.run(&mut input, &mut output)?;
+                    let written = output.pos();
+                    if (content.len() + written) as u64 > limit {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!(
+                                "File too large: payload decompresses to more than {limit} bytes"
+                            ),
+                        ));
+                    }
+                    content.extend_from_slice(&buffer[..written]);
+                    /* A full output buffer means there may be more to flush */
+                    if input.pos() == payload.len() && written < buffer.len() {
+                        break Ok(content);
+                    }
+                }
+            },
+        }
+    }
+}
+
 /** The code to establish a transit connection is essentially the same on both sides. */
 async fn make_transit(
     wormhole: &mut Wormhole,
@@ -156,29 +284,38 @@ pub async fn send(
     transit_abilities: transit::Abilities,
     offer: OfferSend,
     progress_handler: impl FnMut(u64, u64) + 'static,
+    transit_handler: impl FnOnce(transit::TransitInfo),
     peer_version: AppVersion,
     cancel: impl Future<Output = ()>,
 ) -> Result<(), TransferError> {
     let peer_abilities = peer_version.transfer_v2.unwrap();
     futures::pin_mut!(cancel);
 
+    /* Only compress if we want to and they can decompress it */
+    let compression = wormhole
+        .our_version
+        .downcast_ref::<AppVersion>()
+        .and_then(|version| version.transfer_v2.as_ref()?.compression_level)
+        .filter(|_| peer_abilities.supports_zstd())
+        .map(|level| (Compression::Zstd, level));
+
     /* Establish transit connection, close the Wormhole and switch to using the transit connection (msgpack instead of json) */
-    let (mut transit, wormhole, cancel) = cancel::with_cancel_wormhole!(
+    let ((mut transit, info), wormhole, cancel) = cancel::with_cancel_wormhole!(
         wormhole,
         run = async {
-            Ok(make_transit(
+            make_transit(
                 &mut wormhole,
                 true,
                 relay_hints,
                 transit_abilities,
                 peer_abilities.transit_abilities,
             )
-            .await?
-            .0)
+            .await
         },
         cancel,
         ret_cancel = (),
     );
+    transit_handler(info);
 
     cancel::with_cancel_transit!(
         transit,
@@ -186,7 +323,7 @@ pub async fn send(
             /* Close the wormhole only here so that the operation may be cancelled */
             wormhole.close().await?;
 
-            send_inner(&mut transit, offer, progress_handler).await
+            send_inner(&mut transit, offer, compression, progress_handler).await
         },
         cancel,
         |err| PeerMessageV2::Error(err.to_string()).ser_msgpack(),
@@ -204,6 +341,7 @@ pub async fn send(
 async fn send_inner(
     transit: &mut transit::Transit,
     offer: OfferSend,
+    compression: Option<(Compression, i32)>,
     mut progress_handler: impl FnMut(u64, u64) + 'static,
 ) -> Result<(), TransferError> {
     transit.send_record(&{
@@ -220,18 +358,23 @@ async fn send_inner(
 
     let mut total_size = 0;
     for file in &files {
-        if let Some((_, size)) = offer.get_file(&file.file) {
-            total_size += size;
-        } else {
-            bail!(TransferError::Protocol(
+        match offer.get_file(&file.file) {
+            Some((_, size)) if file.offset <= size => total_size += size,
+            Some(_) => bail!(TransferError::Protocol(
+                format!(
+                    "Invalid file request: {} starting at offset {}",
+                    file.file.join("/"),
+                    file.offset
+                )
+                .into()
+            )),
+            None => bail!(TransferError::Protocol(
                 format!("Invalid file request: {}", file.file.join("/")).into()
-            ));
+            )),
         }
     }
     let mut total_sent = 0;
 
-    // use zstd::stream::raw::Encoder;
-    // let zstd = Encoder::new(zstd::DEFAULT_COMPRESSION_LEVEL);
     const BUFFER_LEN: usize = 16 * 1024;
     let mut buffer = Box::new([0u8; BUFFER_LEN]);
 
@@ -249,7 +392,6 @@ async fn send_inner(
 
         /* If they specified a hash, check our local file's contents */
         if let Some(sha256) = sha256 {
-            content.seek(std::io::SeekFrom::Start(offset)).await?;
             let mut hasher = Sha256::default();
             futures::io::copy(
                 (&mut content).take(offset),
@@ -260,11 +402,13 @@ async fn send_inner(
 
             /* If it doesn't match, start at 0 instead of the originally requested offset */
             if *our_hash == sha256[..] {
+                total_sent += offset;
                 transit
                     .send_record(
                         &PeerMessageV2::FileStart(FileStart {
                             file,
                             start_at_offset: true,
+                            compression: compression.map(|(compression, _)| compression),
                         })
                         .ser_msgpack(),
                     )
@@ -275,20 +419,22 @@ async fn send_inner(
                         &PeerMessageV2::FileStart(FileStart {
                             file,
                             start_at_offset: false,
+                            compression: compression.map(|(compression, _)| compression),
                         })
                         .ser_msgpack(),
                     )
                     .await?;
                 content.seek(std::io::SeekFrom::Start(0)).await?;
-                // offset = 0; TODO
             }
         } else {
             content.seek(std::io::SeekFrom::Start(offset)).await?;
+            total_sent += offset;
             transit
                 .send_record(
                     &PeerMessageV2::FileStart(FileStart {
                         file,
                         start_at_offset: true,
+                        compression: compression.map(|(compression, _)| compression),
                     })
                     .ser_msgpack(),
                 )
@@ -296,6 +442,7 @@ async fn send_inner(
         }
 
         progress_handler(total_sent, total_size);
+        let mut encoder = PayloadEncoder::new(compression)?;
         loop {
             let n = content.read(&mut buffer[..]).await?;
             let buffer = &buffer[..n];
@@ -305,14 +452,13 @@ async fn send_inner(
                 break;
             }
 
-            transit
-                .send_record(
-                    &PeerMessageV2::Payload(Payload {
-                        payload: buffer.into(),
-                    })
-                    .ser_msgpack(),
-                )
-                .await?;
+            let payload = encoder.encode(buffer)?;
+            if !payload.is_empty() {
+                transit
+                    .send_record(&PeerMessageV2::Payload(Payload { payload }).ser_msgpack())
+                    .await?;
+            }
+            /* Progress is measured in file content, no matter how well it compresses */
             total_sent += n as u64;
             progress_handler(total_sent, total_size);
 
@@ -320,6 +466,12 @@ async fn send_inner(
                 break;
             }
         }
+        let payload = encoder.finish()?;
+        if !payload.is_empty() {
+            transit
+                .send_record(&PeerMessageV2::Payload(Payload { payload }).ser_msgpack())
+                .await?;
+        }
 
         transit
             .send_record(&PeerMessageV2::FileEnd(FileEnd {}).ser_msgpack())
@@ -528,12 +680,15 @@ async fn receive_inner(
             content = (answer.content)(true).await?;
             let offset = answer.offset;
             received_size = offset;
+            total_received += offset;
         } else {
             content = (answer.content)(false).await?;
         }
 
         progress_handler(total_received, total_size);
-        loop {
+        let mut decoder = PayloadDecoder::new(file_start.compression)?;
+        /* If we already had the whole file, there won't be any payload */
+        while received_size < size {
             let payload =
                 match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
                     PeerMessageV2::Payload(payload) => payload.payload,
@@ -551,21 +706,20 @@ async fn receive_inner(
                     },
                 };
 
+            let payload = decoder.decode(payload, size - received_size)?;
             content.write_all(&payload).await?;
             received_size += payload.len() as u64;
             total_received += payload.len() as u64;
             progress_handler(total_received, total_size);
 
-            if received_size == size {
-                break;
-            } else if received_size >= size {
+            if received_size > size {
                 /* `received_size` must never become greater than `size` or we might panic on an integer underflow in the next iteration
                  * (only on an unhappy path, but still). Also, the progress bar might not appreciate.
                  */
                 bail!(TransferError::Protocol(
                     format!(
                         "File too large: expected only {size} bytes, got at least {} more",
-                        size - received_size
+                        received_size - size
                     )
                     .into_boxed_str()
                 ))
@@ -574,11 +728,18 @@ async fn receive_inner(
 
         content.close().await?;
 
-        let _end = match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
-            PeerMessageV2::FileEnd(end) => end,
-            other => {
-                bail!(TransferError::unexpected_message("file-end", other))
-            },
+        /* A compressed payload may end with some bytes that don't contain any more content */
+        let _end = loop {
+            match PeerMessageV2::de_msgpack(&transit.receive_record().await?)?.check_err()? {
+                PeerMessageV2::FileEnd(end) => break end,
+                PeerMessageV2::Payload(payload) if decoder.is_compressed() => {
+                    /* Any more content would exceed the file size, which makes decoding fail */
+                    decoder.decode(payload.payload, 0)?;
+                },
+                other => {
+                    bail!(TransferError::unexpected_message("file-end", other))
+                },
+            }
         };
     }
 
@@ -599,3 +760,113 @@ async fn receive_inner(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_start() {
+        /* Peers that don't know about compression don't send the field */
+        let file_start = FileStart {
+            file: vec!["example.txt".into()],
+            start_at_offset: false,
+            compression: None,
+        };
+        let message = PeerMessageV2::FileStart(file_start).ser_msgpack();
+        let PeerMessageV2::FileStart(file_start) = PeerMessageV2::de_msgpack(&message).unwrap()
+        else {
+            panic!("Expected a file-start message");
+        };
+        assert_eq!(file_start.compression, None);
+
+        let file_start = FileStart {
+            compression: Some(Compression::Zstd),
+            ..file_start
+        };
+        let message = PeerMessageV2::FileStart(file_start).ser_msgpack();
+        let PeerMessageV2::FileStart(file_start) = PeerMessageV2::de_msgpack(&message).unwrap()
+        else {
+            panic!("Expected a file-start message");
+        };
+        assert_eq!(file_start.compression, Some(Compression::Zstd));
+    }
+
+    #[test]
+    fn test_offer_metadata() {
+        let offer: Offer = serde_json::from_value(serde_json::json!({
+            "content": {
+                "old.txt": {"type": "regular-file", "size": 1},
+                "script.sh": {"type": "regular-file", "size": 2, "mtime": 1700000000, "permissions": 0o755},
+            },
+        }))
+        .unwrap();
+        let message = PeerMessageV2::Offer(offer.clone()).ser_msgpack();
+        let PeerMessageV2::Offer(received) = PeerMessageV2::de_msgpack(&message).unwrap() else {
+            panic!("Expected an offer message");
+        };
+        assert_eq!(received, offer);
+        assert_eq!(
+            received.get(&["old.txt".into()]),
+            Some(&OfferEntry::RegularFile {
+                size: 1,
+                metadata: FileMetadata::default(),
+                content: (),
+            })
+        );
+        assert_eq!(
+            received.get(&["script.sh".into()]),
+            Some(&OfferEntry::RegularFile {
+                size: 2,
+                metadata: FileMetadata::new(Some(1700000000), Some(0o755)),
+                content: (),
+            })
+        );
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn test_payload_compression() {
+        let content = "All work and no play makes Jack a dull boy\n".repeat(10_000);
+
+        for compression in [None, Some(Compression::Zstd)] {
+            let mut encoder = PayloadEncoder::new(compression.map(|c| (c, 0))).unwrap();
+            let mut payloads: Vec<Vec<u8>> = content
+                .as_bytes()
+                .chunks(16 * 1024)
+                .map(|chunk| encoder.encode(chunk).unwrap())
+                .collect();
+            payloads.push(encoder.finish().unwrap());
+            let compressed_size = payloads.iter().map(Vec::len).sum::<usize>();
+            match compression {
+                None => assert_eq!(compressed_size, content.len()),
+                Some(_) => assert!(compressed_size < content.len() / 100),
+            }
+
+            let mut decoder = PayloadDecoder::new(compression).unwrap();
+            let mut received = Vec::new();
+            for payload in payloads {
+                let limit = (content.len() - received.len()) as u64;
+                received.extend(decoder.decode(payload, limit).unwrap());
+            }
+            assert!(received == content.as_bytes());
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    #[test]
+    fn test_payload_decompression_limit() {
+        /* A few kilobytes that expand to a gigabyte */
+        let mut encoder = PayloadEncoder::new(Some((Compression::Zstd, 19))).unwrap();
+        let mut payload = Vec::new();
+        for _ in 0..1024 {
+            payload.extend(encoder.encode(&[0; 1024 * 1024]).unwrap());
+        }
+        payload.extend(encoder.finish().unwrap());
+        assert!(payload.len() < 1024 * 1024);
+
+        let mut decoder = PayloadDecoder::new(Some(Compression::Zstd)).unwrap();
+        let err = decoder.decode(payload, 1024 * 1024).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
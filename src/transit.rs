@@ -33,6 +33,8 @@ use std::{
 };
 
 mod crypto;
+#[cfg(all(feature = "server", not(target_family = "wasm")))]
+pub mod server;
 mod transport;
 use crypto::TransitHandshakeError;
 use transport::{TransitTransport, TransitTransportRx, TransitTransportTx};
@@ -864,6 +866,19 @@ impl TransitConnector {
         transit_key: Key<TransitKey>,
         their_abilities: Abilities,
         their_hints: Arc<Hints>,
+    ) -> Result<(Transit, TransitInfo), TransitConnectError> {
+        let transit_key = Arc::new(transit_key);
+        let cryptor = Self::cryptor(&self.our_abilities, &their_abilities, &transit_key);
+        self.leader_connect_with(cryptor, transit_key, their_abilities, their_hints)
+            .await
+    }
+
+    async fn leader_connect_with(
+        self,
+        cryptor: Arc<dyn crypto::TransitCryptoInit>,
+        transit_key: Arc<Key<TransitKey>>,
+        their_abilities: Abilities,
+        their_hints: Arc<Hints>,
     ) -> Result<(Transit, TransitInfo), TransitConnectError> {
         let Self {
             #[cfg(not(target_family = "wasm"))]
@@ -871,13 +886,13 @@ impl TransitConnector {
             our_abilities,
             our_hints,
         } = self;
-        let transit_key = Arc::new(transit_key);
 
         let start = instant::Instant::now();
         let mut connection_stream = Box::pin(
             Self::connect_inner(
                 true,
                 transit_key,
+                cryptor,
                 our_abilities,
                 our_hints,
                 their_abilities,
@@ -970,6 +985,19 @@ impl TransitConnector {
         transit_key: Key<TransitKey>,
         their_abilities: Abilities,
         their_hints: Arc<Hints>,
+    ) -> Result<(Transit, TransitInfo), TransitConnectError> {
+        let transit_key = Arc::new(transit_key);
+        let cryptor = Self::cryptor(&self.our_abilities, &their_abilities, &transit_key);
+        self.follower_connect_with(cryptor, transit_key, their_abilities, their_hints)
+            .await
+    }
+
+    async fn follower_connect_with(
+        self,
+        cryptor: Arc<dyn crypto::TransitCryptoInit>,
+        transit_key: Arc<Key<TransitKey>>,
+        their_abilities: Abilities,
+        their_hints: Arc<Hints>,
     ) -> Result<(Transit, TransitInfo), TransitConnectError> {
         let Self {
             #[cfg(not(target_family = "wasm"))]
@@ -977,12 +1005,12 @@ impl TransitConnector {
             our_abilities,
             our_hints,
         } = self;
-        let transit_key = Arc::new(transit_key);
 
         let mut connection_stream = Box::pin(
             Self::connect_inner(
                 false,
                 transit_key,
+                cryptor,
                 our_abilities,
                 our_hints,
                 their_abilities,
@@ -1032,6 +1060,51 @@ impl TransitConnector {
         transit
     }
 
+    /**
+     * Connect to the other side of a dilated wormhole
+     *
+     * This works like [`connect`](Self::connect), but uses the Noise handshake of the
+     * Dilation protocol instead of the transit one.
+     */
+    #[cfg(feature = "dilation")]
+    pub(crate) async fn dilation_connect(
+        self,
+        is_leader: bool,
+        dilation_key: Key<TransitKey>,
+        their_abilities: Abilities,
+        their_hints: Arc<Hints>,
+    ) -> Result<(Transit, TransitInfo), TransitConnectError> {
+        let dilation_key = Arc::new(dilation_key);
+        let cryptor = Arc::new(crypto::NoiseInit {
+            key: dilation_key.clone(),
+        });
+        if is_leader {
+            self.leader_connect_with(cryptor, dilation_key, their_abilities, their_hints)
+                .await
+        } else {
+            self.follower_connect_with(cryptor, dilation_key, their_abilities, their_hints)
+                .await
+        }
+    }
+
+    fn cryptor(
+        our_abilities: &Abilities,
+        their_abilities: &Abilities,
+        transit_key: &Arc<Key<TransitKey>>,
+    ) -> Arc<dyn crypto::TransitCryptoInit> {
+        if our_abilities.can_noise_crypto() && their_abilities.can_noise_crypto() {
+            log::debug!("Using noise protocol for encryption");
+            Arc::new(crypto::NoiseInit {
+                key: transit_key.clone(),
+            })
+        } else {
+            log::debug!("Using secretbox for encryption");
+            Arc::new(crypto::SecretboxInit {
+                key: transit_key.clone(),
+            })
+        }
+    }
+
     /** Try to establish a connection with the peer.
      *
      * This encapsulates code that is common to both the leader and the follower.
@@ -1044,6 +1117,7 @@ impl TransitConnector {
     fn connect_inner(
         is_leader: bool,
         transit_key: Arc<Key<TransitKey>>,
+        cryptor: Arc<dyn crypto::TransitCryptoInit>,
         our_abilities: Abilities,
         our_hints: Arc<Hints>,
         their_abilities: Abilities,
@@ -1054,18 +1128,6 @@ impl TransitConnector {
         #[cfg(not(target_family = "wasm"))]
         assert!(sockets.is_none() || our_abilities.can_direct());
 
-        let cryptor = if our_abilities.can_noise_crypto() && their_abilities.can_noise_crypto() {
-            log::debug!("Using noise protocol for encryption");
-            Arc::new(crypto::NoiseInit {
-                key: transit_key.clone(),
-            }) as Arc<dyn crypto::TransitCryptoInit>
-        } else {
-            log::debug!("Using secretbox for encryption");
-            Arc::new(crypto::SecretboxInit {
-                key: transit_key.clone(),
-            }) as Arc<dyn crypto::TransitCryptoInit>
-        };
-
         // 8. listen for connections on the port and simultaneously try connecting to the peer port.
         let tside = Arc::new(hex::encode(rand::random::<[u8; 8]>()));
 
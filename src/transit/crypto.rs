@@ -230,15 +230,19 @@ type NoiseHandshakeState = noise_protocol::HandshakeState<
 >;
 type NoiseCipherState = noise_protocol::CipherState<noise_rust_crypto::ChaCha20Poly1305>;
 
-/// Cryptography based on the [noise protocol](noiseprotocol.org).
+/// The key confirmation message that both sides send after the Noise handshake
+const NOISE_KCM: [u8; 1] = [0x00];
+
+/// Cryptography based on the [noise protocol](noiseprotocol.org), as used by the Dilation protocol.
 /// → "Magic-Wormhole Dilation Handshake v1 Leader\n\n"
 /// ← "Magic-Wormhole Dilation Handshake v1 Follower\n\n"
 /// → psk, e // Handshake
 /// ← e, ee
-/// ← "" // First real message
-/// → "" // Not in this method, to confirm the connection
+/// ← KCM // First real message
+/// → KCM // Not in this method, to confirm the connection
 ///
-/// The noise protocol pattern used is "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s"
+/// The noise protocol pattern used is "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s". The key confirmation
+/// message (KCM) is the record `0x00` of the Dilation protocol.
 pub struct NoiseInit {
     pub key: Arc<Key<TransitKey>>,
 }
@@ -276,10 +280,10 @@ impl TransitCryptoInit for NoiseInit {
         assert!(handshake.completed());
         let (tx, mut rx) = handshake.get_ciphers();
 
-        // ← ""
+        // ← KCM
         let peer_confirmation_message = rx.decrypt_vec(&socket.read_transit_message().await?)?;
         ensure!(
-            peer_confirmation_message.is_empty(),
+            peer_confirmation_message == NOISE_KCM,
             TransitHandshakeError::HandshakeFailed
         );
 
@@ -294,9 +298,9 @@ impl TransitCryptoInit for NoiseInit {
                 socket: &mut dyn TransitTransport,
             ) -> BoxFuture<Result<DynTransitCrypto, TransitHandshakeError>> {
                 Box::pin(async move {
-                    // → ""
+                    // → KCM
                     socket
-                        .write_transit_message(&self.tx.encrypt_vec(&[]))
+                        .write_transit_message(&self.tx.encrypt_vec(&NOISE_KCM))
                         .await?;
 
                     Ok::<_, TransitHandshakeError>((
@@ -344,13 +348,15 @@ impl TransitCryptoInit for NoiseInit {
         // Warning: rx and tx are swapped here (read the `get_ciphers` doc carefully)
         let (mut rx, mut tx) = handshake.get_ciphers();
 
-        // → ""
-        socket.write_transit_message(&tx.encrypt_vec(&[])).await?;
+        // → KCM
+        socket
+            .write_transit_message(&tx.encrypt_vec(&NOISE_KCM))
+            .await?;
 
-        // ← ""
+        // ← KCM
         let peer_confirmation_message = rx.decrypt_vec(&socket.read_transit_message().await?)?;
         ensure!(
-            peer_confirmation_message.is_empty(),
+            peer_confirmation_message == NOISE_KCM,
             TransitHandshakeError::HandshakeFailed
         );
 
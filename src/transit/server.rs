@@ -0,0 +1,464 @@
+//! An embeddable transit relay server
+//!
+//! When two sides cannot reach each other directly, they both connect to a relay server and
+//! send it the same handshake (`please relay <token> for side <side>\n`, with the token derived
+//! from the transit key). The relay pairs up two connections with the same token but different
+//! sides, answers both with `ok\n` and then blindly shovels bytes between them.
+//!
+//! This server speaks the handshake over plain TCP and over WebSockets (where the byte stream is
+//! carried in binary messages), and the two can be freely mixed. State is kept in memory only.
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> { async_std::task::block_on(async {
+//! use magic_wormhole::transit::server::RelayServer;
+//! let relay = RelayServer::new()
+//!     .listen_tcp("127.0.0.1:4001")
+//!     .await?
+//!     .listen_websocket("127.0.0.1:4002")
+//!     .await?;
+//! let hint = relay.relay_hint();
+//! async_std::task::spawn(relay.run());
+//! # Ok(()) })}
+//! ```
+
+use super::{DirectHint, RelayHint};
+use async_std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use async_tungstenite::tungstenite as ws2;
+use futures::{
+    channel::oneshot,
+    future::{BoxFuture, Either},
+    io::{AsyncReadExt, AsyncWriteExt},
+    prelude::*,
+    stream::BoxStream,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/* Nobody sends a legit handshake line longer than this */
+const MAX_HANDSHAKE_LENGTH: usize = 256;
+
+/**
+ * A transit relay listening on local sockets
+ *
+ * Create one with [`RelayServer::new`], add at least one listener and then drive it with
+ * [`RelayServer::run`] (usually in a background task).
+ */
+#[derive(Debug, Default)]
+pub struct RelayServer {
+    tcp: Option<TcpListener>,
+    websocket: Option<TcpListener>,
+}
+
+impl RelayServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /** Accept raw TCP connections on that address. Use port 0 to let the OS pick one. */
+    pub async fn listen_tcp(mut self, address: impl ToSocketAddrs) -> io::Result<Self> {
+        self.tcp = Some(TcpListener::bind(address).await?);
+        Ok(self)
+    }
+
+    /** Accept WebSocket connections on that address. Use port 0 to let the OS pick one. */
+    pub async fn listen_websocket(mut self, address: impl ToSocketAddrs) -> io::Result<Self> {
+        self.websocket = Some(TcpListener::bind(address).await?);
+        Ok(self)
+    }
+
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        self.tcp
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap())
+    }
+
+    pub fn websocket_addr(&self) -> Option<SocketAddr> {
+        self.websocket
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap())
+    }
+
+    /**
+     * A hint for clients to find this relay
+     *
+     * This is derived from the bound addresses, so it is only useful if the server
+     * has not been bound to a wildcard address.
+     */
+    pub fn relay_hint(&self) -> RelayHint {
+        RelayHint::new(
+            None,
+            self.tcp_addr().map(|addr| DirectHint {
+                hostname: addr.ip().to_string(),
+                port: addr.port(),
+            }),
+            self.websocket_addr()
+                .map(|addr| format!("ws://{}/", addr).parse().unwrap()),
+        )
+    }
+
+    /**
+     * Accept and relay clients until one of the listeners fails
+     *
+     * Every client connection is handled in its own task.
+     */
+    pub async fn run(self) -> io::Result<()> {
+        assert!(
+            self.tcp.is_some() || self.websocket.is_some(),
+            "Relay server needs at least one listener"
+        );
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+
+        let tcp = self.tcp.map(|listener| {
+            let pending = pending.clone();
+            Box::pin(async move {
+                let mut incoming = listener.incoming();
+                while let Some(stream) = incoming.next().await {
+                    let pending = pending.clone();
+                    async_std::task::spawn(handle_tcp(pending, stream?));
+                }
+                io::Result::Ok(())
+            }) as BoxFuture<'static, io::Result<()>>
+        });
+        let websocket = self.websocket.map(|listener| {
+            let pending = pending.clone();
+            Box::pin(async move {
+                let mut incoming = listener.incoming();
+                while let Some(stream) = incoming.next().await {
+                    let pending = pending.clone();
+                    async_std::task::spawn(handle_websocket(pending, stream?));
+                }
+                io::Result::Ok(())
+            }) as BoxFuture<'static, io::Result<()>>
+        });
+
+        futures::future::try_join_all(tcp.into_iter().chain(websocket)).await?;
+        Ok(())
+    }
+}
+
+/* Connections waiting for their partner, indexed by token */
+type Pending = Arc<Mutex<HashMap<String, Vec<PendingConnection>>>>;
+
+struct PendingConnection {
+    side: String,
+    partner: oneshot::Sender<Peer>,
+}
+
+/** One end of the relay, abstracted over the transport */
+struct Peer {
+    name: String,
+    reader: BoxStream<'static, io::Result<Vec<u8>>>,
+    writer: Pin<Box<dyn Sink<Vec<u8>, Error = io::Error> + Send>>,
+}
+
+async fn handle_tcp(pending: Pending, stream: TcpStream) {
+    let name = format!(
+        "tcp:{}",
+        stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".into(), |addr| addr.to_string())
+    );
+    let reader = futures::stream::try_unfold(stream.clone(), |mut stream| async move {
+        let mut buffer = vec![0; 16 * 1024];
+        let read = stream.read(&mut buffer).await?;
+        buffer.truncate(read);
+        Ok((read > 0).then_some((buffer, stream)))
+    });
+    let peer = Peer {
+        name,
+        reader: Box::pin(reader),
+        writer: Box::pin(TcpSink {
+            sink: stream.clone().into_sink(),
+            stream,
+        }),
+    };
+    handle_peer(pending, peer).await
+}
+
+/* `AsyncWrite::poll_close` only flushes a `TcpStream`, but the peer needs to see the EOF */
+struct TcpSink {
+    sink: futures::io::IntoSink<TcpStream, Vec<u8>>,
+    stream: TcpStream,
+}
+
+impl Sink<Vec<u8>> for TcpSink {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Vec<u8>) -> io::Result<()> {
+        Pin::new(&mut self.sink).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        futures::ready!(Pin::new(&mut self.sink).poll_close(cx))?;
+        /* The other side might be gone already, which is fine */
+        let _ = self.stream.shutdown(std::net::Shutdown::Write);
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn handle_websocket(pending: Pending, stream: TcpStream) {
+    let name = format!(
+        "ws:{}",
+        stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".into(), |addr| addr.to_string())
+    );
+    let websocket = match async_tungstenite::accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            log::debug!("WebSocket handshake with {} failed: {}", name, e);
+            return;
+        },
+    };
+    let (writer, reader) = websocket.split();
+    let reader = reader
+        .map_err(io::Error::other)
+        .try_filter_map(|message| async move {
+            Ok(match message {
+                ws2::Message::Binary(data) => Some(data),
+                /* Be lenient, the handshake is text after all */
+                ws2::Message::Text(data) => Some(data.into_bytes()),
+                _ => None,
+            })
+        });
+    let writer = writer
+        .sink_map_err(io::Error::other)
+        .with(|data| futures::future::ok(ws2::Message::Binary(data)));
+    let peer = Peer {
+        name,
+        reader: Box::pin(reader),
+        writer: Box::pin(writer),
+    };
+    handle_peer(pending, peer).await
+}
+
+async fn handle_peer(pending: Pending, mut peer: Peer) {
+    let (token, side) = match read_handshake(&mut peer).await {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            log::debug!("Relay handshake with {} failed: {}", peer.name, e);
+            let _ = peer.writer.send(b"bad handshake\n".to_vec()).await;
+            let _ = peer.writer.close().await;
+            return;
+        },
+    };
+    log::debug!("{} wants relay for {} (side {})", peer.name, token, side);
+
+    /* Either hand us over to a waiting partner, or wait for one ourselves */
+    let (tx, rx) = oneshot::channel();
+    {
+        let mut pending = pending.lock().unwrap();
+        let waiting = pending.entry(token.clone()).or_default();
+        waiting.retain(|connection| !connection.partner.is_canceled());
+        while let Some(index) = waiting
+            .iter()
+            .position(|connection| connection.side != side)
+        {
+            match waiting.remove(index).partner.send(peer) {
+                Ok(()) => {
+                    if waiting.is_empty() {
+                        pending.remove(&token);
+                    }
+                    return;
+                },
+                /* Lost the race against that one going away, try the next */
+                Err(returned) => peer = returned,
+            }
+        }
+        waiting.push(PendingConnection { side, partner: tx });
+    }
+
+    /* Nobody must talk before being paired up, so any activity means we're done */
+    let partner = match futures::future::select(rx, peer.reader.next()).await {
+        Either::Left((Ok(partner), _)) => partner,
+        Either::Left((Err(oneshot::Canceled), _)) => {
+            log::debug!("{} got dropped while waiting", peer.name);
+            return;
+        },
+        Either::Right((message, rx)) => {
+            /* Drop our end first, so that our own entry gets cleaned up below */
+            drop(rx);
+            log::debug!(
+                "{} went away or talked out of turn before being paired ({:?})",
+                peer.name,
+                message.map(|message| message.map(|data| data.len()))
+            );
+            let mut pending = pending.lock().unwrap();
+            if let Some(waiting) = pending.get_mut(&token) {
+                waiting.retain(|connection| !connection.partner.is_canceled());
+                if waiting.is_empty() {
+                    pending.remove(&token);
+                }
+            }
+            return;
+        },
+    };
+
+    if let Err(e) = relay(peer, partner).await {
+        log::debug!("Relaying for {} stopped: {}", token, e);
+    }
+}
+
+/** Read `please relay <token> for side <side>\n` */
+async fn read_handshake(peer: &mut Peer) -> io::Result<(String, String)> {
+    let mut line = Vec::new();
+    let newline = loop {
+        if let Some(position) = line.iter().position(|&byte| byte == b'\n') {
+            break position;
+        }
+        if line.len() > MAX_HANDSHAKE_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "handshake too long",
+            ));
+        }
+        match peer.reader.next().await {
+            Some(data) => line.extend(data?),
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    };
+    /* Nobody is supposed to send more before getting their "ok", but let's not lose it */
+    let rest = line.split_off(newline + 1);
+    if !rest.is_empty() {
+        let reader = std::mem::replace(&mut peer.reader, Box::pin(futures::stream::empty()));
+        peer.reader = Box::pin(futures::stream::once(async { Ok(rest) }).chain(reader));
+    }
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid handshake");
+    let line = std::str::from_utf8(&line[..newline]).map_err(|_| invalid())?;
+    match line.split(' ').collect::<Vec<_>>()[..] {
+        ["please", "relay", token, "for", "side", side]
+            if hex::decode(token).is_ok() && !side.is_empty() =>
+        {
+            Ok((token.to_owned(), side.to_owned()))
+        },
+        _ => Err(invalid()),
+    }
+}
+
+async fn relay(mut a: Peer, mut b: Peer) -> io::Result<()> {
+    log::debug!("Relaying between {} and {}", a.name, b.name);
+    a.writer.send(b"ok\n".to_vec()).await?;
+    b.writer.send(b"ok\n".to_vec()).await?;
+
+    /* Each direction ends independently, so that half-closed connections still work */
+    let (a_to_b, b_to_a) = futures::join!(a.reader.forward(b.writer), b.reader.forward(a.writer));
+    a_to_b.and(b_to_a)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn relay_server() -> RelayServer {
+        RelayServer::new()
+            .listen_tcp("127.0.0.1:0")
+            .await
+            .unwrap()
+            .listen_websocket("127.0.0.1:0")
+            .await
+            .unwrap()
+    }
+
+    async fn expect(stream: &mut TcpStream, expected: &[u8]) {
+        let mut buffer = vec![0; expected.len()];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, expected);
+    }
+
+    #[async_std::test]
+    async fn test_relay_tcp() {
+        let relay = relay_server().await;
+        let address = relay.tcp_addr().unwrap();
+        async_std::task::spawn(relay.run());
+
+        let mut a = TcpStream::connect(address).await.unwrap();
+        let mut b = TcpStream::connect(address).await.unwrap();
+        let mut c = TcpStream::connect(address).await.unwrap();
+        a.write_all(b"please relay abcd for side 01\n")
+            .await
+            .unwrap();
+        /* Someone else, with a different token */
+        c.write_all(b"please relay 1234 for side 02\n")
+            .await
+            .unwrap();
+        b.write_all(b"please relay abcd for side 02\n")
+            .await
+            .unwrap();
+        expect(&mut a, b"ok\n").await;
+        expect(&mut b, b"ok\n").await;
+
+        a.write_all(b"hello").await.unwrap();
+        expect(&mut b, b"hello").await;
+        b.write_all(b"world").await.unwrap();
+        expect(&mut a, b"world").await;
+
+        /* Closing one side closes the other */
+        drop(a);
+        let mut rest = Vec::new();
+        b.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_relay_bad_handshake() {
+        let relay = relay_server().await;
+        let address = relay.tcp_addr().unwrap();
+        async_std::task::spawn(relay.run());
+
+        let mut a = TcpStream::connect(address).await.unwrap();
+        a.write_all(b"please let me in\n").await.unwrap();
+        expect(&mut a, b"bad handshake\n").await;
+    }
+
+    #[async_std::test]
+    async fn test_relay_websocket_to_tcp() {
+        let relay = relay_server().await;
+        let hint = relay.relay_hint();
+        let address = relay.tcp_addr().unwrap();
+        async_std::task::spawn(relay.run());
+
+        let (mut ws, _) =
+            async_tungstenite::async_std::connect_async(hint.ws.iter().next().unwrap().as_str())
+                .await
+                .unwrap();
+        ws.send(ws2::Message::Binary(
+            b"please relay abcd for side 01\n".to_vec(),
+        ))
+        .await
+        .unwrap();
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        tcp.write_all(b"please relay abcd for side 02\n")
+            .await
+            .unwrap();
+
+        expect(&mut tcp, b"ok\n").await;
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            ws2::Message::Binary(b"ok\n".to_vec())
+        );
+        tcp.write_all(b"hello").await.unwrap();
+        assert_eq!(
+            ws.next().await.unwrap().unwrap(),
+            ws2::Message::Binary(b"hello".to_vec())
+        );
+        ws.send(ws2::Message::Binary(b"world".to_vec()))
+            .await
+            .unwrap();
+        expect(&mut tcp, b"world").await;
+    }
+}
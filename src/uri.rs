@@ -5,6 +5,9 @@
 /// This, however, might change in the future.
 use super::*;
 
+#[cfg(feature = "qr")]
+pub mod qr;
+
 #[derive(Debug, thiserror::Error, Clone, Eq, PartialEq)]
 #[non_exhaustive]
 pub enum ParseError {
@@ -18,6 +21,12 @@ pub enum ParseError {
     UnsupportedVersion(String),
     #[error("Invalid 'role' parameter: '{_0}'")]
     InvalidRole(String),
+    #[error("Invalid code")]
+    InvalidCode(
+        #[from]
+        #[source]
+        crate::CodeParseError,
+    ),
     /// Some deserialization went wrong, we probably got some garbage
     #[error("String does not parse as URL")]
     UrlParseError(
@@ -88,15 +97,11 @@ impl TryFrom<&url::Url> for WormholeTransferUri {
             "follower" => false,
             invalid => return Err(ParseError::InvalidRole(invalid.into())),
         };
-        let code = Code(
-            percent_encoding::percent_decode_str(url.path())
-                .decode_utf8()?
-                .into(),
-        );
-        // TODO move the code validation to somewhere else and also add more checks
+        let code = percent_encoding::percent_decode_str(url.path()).decode_utf8()?;
         if code.is_empty() {
             return Err(ParseError::MissingCode);
         }
+        let code: Code = code.parse()?;
 
         Ok(WormholeTransferUri {
             code,
@@ -189,5 +194,11 @@ mod test {
                 .parse::<WormholeTransferUri>(),
             Err(ParseError::MissingCode)
         );
+        assert_eq!(
+            "wormhole-transfer:4-armistice-baboom".parse::<WormholeTransferUri>(),
+            Err(ParseError::InvalidCode(crate::CodeParseError::UnknownWord(
+                "baboom".into()
+            )))
+        );
     }
 }
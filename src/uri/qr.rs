@@ -0,0 +1,200 @@
+//! QR codes for wormhole URIs
+//!
+//! Phones can scan these to get the code (and the rendezvous server, if it's not the default one)
+//! without typing anything.
+//!
+//! ```
+//! use magic_wormhole::{
+//!     uri::{qr::QrCode, WormholeTransferUri},
+//!     Code,
+//! };
+//!
+//! let uri = WormholeTransferUri::new(Code("4-hurricane-equipment".into()));
+//! let qr = QrCode::new(&uri)?;
+//! println!("{}", qr.to_terminal_string(true));
+//! let svg = qr.to_svg(8);
+//! # Ok::<(), magic_wormhole::uri::qr::QrError>(())
+//! ```
+
+use super::WormholeTransferUri;
+
+/// Number of light modules around the code, as required by the standard
+const QUIET_ZONE: usize = 4;
+
+/// Terminals are short on space, and scanners are fine with a narrower quiet zone anyways
+const TERMINAL_QUIET_ZONE: usize = 2;
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum QrError {
+    #[error("Cannot encode the URI as QR code")]
+    Encode(
+        #[from]
+        #[source]
+        qrcode::types::QrError,
+    ),
+    #[error("Failed to encode the QR code as PNG")]
+    Png(
+        #[from]
+        #[source]
+        png::EncodingError,
+    ),
+}
+
+/** The modules of a QR code, without quiet zone */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QrCode {
+    width: usize,
+    modules: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn new(uri: &WormholeTransferUri) -> Result<Self, QrError> {
+        let code = qrcode::QrCode::new(uri.to_string())?;
+        Ok(Self {
+            width: code.width(),
+            modules: code
+                .to_colors()
+                .into_iter()
+                .map(|color| color == qrcode::Color::Dark)
+                .collect(),
+        })
+    }
+
+    /** Number of modules per row and column */
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /** Whether the module at `x`, `y` is dark. Everything outside of the code is light. */
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.width && self.modules[y * self.width + x]
+    }
+
+    /** Same as `is_dark`, but taking a quiet zone of `border` modules into account */
+    fn is_dark_with_border(&self, x: usize, y: usize, border: usize) -> bool {
+        x >= border && y >= border && self.is_dark(x - border, y - border)
+    }
+
+    /**
+     * Render with Unicode half blocks, two rows of modules per line
+     *
+     * The block characters are drawn for the dark modules, which is right for dark text on a light
+     * background. Most terminals show light text on a dark background, for those set `invert`.
+     */
+    pub fn to_terminal_string(&self, invert: bool) -> String {
+        let size = self.width + 2 * TERMINAL_QUIET_ZONE;
+        let drawn =
+            |x, y| y < size && self.is_dark_with_border(x, y, TERMINAL_QUIET_ZONE) != invert;
+        let mut string = String::new();
+        for y in (0..size).step_by(2) {
+            for x in 0..size {
+                string.push(match (drawn(x, y), drawn(x, y + 1)) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                });
+            }
+            string.push('\n');
+        }
+        string
+    }
+
+    /** Render as SVG image, `module_size` is the size of a single module in pixels */
+    pub fn to_svg(&self, module_size: u32) -> String {
+        let size = self.width + 2 * QUIET_ZONE;
+        let pixels = size as u64 * module_size as u64;
+        let mut path = String::new();
+        for y in 0..self.width {
+            for x in 0..self.width {
+                if self.is_dark(x, y) {
+                    path += &format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE);
+                }
+            }
+        }
+        format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{pixels}" height="{pixels}" viewBox="0 0 {size} {size}" shape-rendering="crispEdges">"#,
+                r##"<rect width="{size}" height="{size}" fill="#fff"/>"##,
+                r##"<path d="{path}" fill="#000"/>"##,
+                "</svg>"
+            ),
+            pixels = pixels,
+            size = size,
+            path = path
+        )
+    }
+
+    /** Render as grayscale PNG image, `module_size` is the size of a single module in pixels */
+    pub fn to_png(&self, module_size: u32) -> Result<Vec<u8>, QrError> {
+        let module_size = module_size.max(1) as usize;
+        let size = self.width + 2 * QUIET_ZONE;
+        let pixels = size * module_size;
+        let mut data = Vec::with_capacity(pixels * pixels);
+        for y in 0..pixels {
+            for x in 0..pixels {
+                let dark = self.is_dark_with_border(x / module_size, y / module_size, QUIET_ZONE);
+                data.push(if dark { 0x00 } else { 0xff });
+            }
+        }
+
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, pixels as u32, pixels as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(png)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Code;
+
+    #[test]
+    fn test_qr() {
+        let uri = WormholeTransferUri::new(Code("4-hurricane-equipment".into()));
+        let qr = QrCode::new(&uri).unwrap();
+        /* Version 3 is the smallest one that fits the URI */
+        assert_eq!(qr.width(), 29);
+        /* The finder patterns */
+        assert!(qr.is_dark(0, 0) && qr.is_dark(28, 0) && qr.is_dark(0, 28));
+        assert!(!qr.is_dark(7, 7) && !qr.is_dark(29, 0));
+
+        let terminal = qr.to_terminal_string(false);
+        let lines = terminal.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 17);
+        assert!(lines.iter().all(|line| line.chars().count() == 33));
+        assert_eq!(lines[0].trim(), "");
+        assert!(lines[1].starts_with("  █▀▀▀▀▀█ "));
+        let inverted = qr.to_terminal_string(true);
+        assert!(inverted.lines().next().unwrap().chars().all(|c| c == '█'));
+
+        let svg = qr.to_svg(4);
+        assert!(svg.contains(r#"width="148" height="148" viewBox="0 0 37 37""#));
+        assert!(svg.contains("M4,4h1v1h-1z"));
+        assert!(svg.ends_with("</svg>"));
+
+        let png = qr.to_png(2).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        assert_eq!((info.width, info.height), (74, 74));
+        /* Quiet zone, then the top left finder pattern */
+        assert_eq!(image[0], 0xff);
+        assert_eq!(image[8 * 74 + 8], 0x00);
+    }
+
+    #[test]
+    fn test_qr_too_long() {
+        let uri = WormholeTransferUri::new(Code(format!("4-{}", "a".repeat(3000))));
+        assert!(matches!(QrCode::new(&uri), Err(QrError::Encode(_))));
+    }
+}
@@ -0,0 +1,28 @@
+#!/bin/sh
+# Regenerate the certificates used by the TLS tests in `src/core/test.rs`:
+# a self-signed CA, a server certificate for localhost/127.0.0.1 and a client certificate,
+# both issued by that CA. They are valid for a long time, so this should rarely be needed.
+set -e
+cd "$(dirname "$0")"
+DAYS=36500
+
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out ca-key.pem
+openssl req -x509 -new -key ca-key.pem -days $DAYS -subj "/CN=Wormhole Test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -out ca.pem
+
+issue() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1-key.pem"
+    openssl req -new -key "$1-key.pem" -subj "/CN=$2" -out "$1.csr"
+    printf "basicConstraints=critical,CA:FALSE\nkeyUsage=critical,digitalSignature\n%s\n" "$3" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA ca.pem -CAkey ca-key.pem -CAcreateserial \
+        -days $DAYS -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+
+issue server localhost "extendedKeyUsage=serverAuth
+subjectAltName=DNS:localhost,IP:127.0.0.1"
+issue client "Wormhole Test Client" "extendedKeyUsage=clientAuth"
+
+rm -f ca.srl ca-key.pem